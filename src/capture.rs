@@ -0,0 +1,390 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::size_of;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use nix::errno::Errno;
+use nix::libc;
+use nix::unistd::close;
+
+use crate::error::IprouteError;
+use crate::ip::iplink::get_link_index;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: usize = 262144;
+
+/// A SOCK_RAW AF_PACKET socket receiving every protocol.
+pub struct PacketSocket(RawFd);
+
+impl PacketSocket {
+    /// Bound to the link with index, so only its frames are received and sent frames leave
+    /// through it.
+    pub fn bind(index: u32) -> Result<PacketSocket> {
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol as i32) };
+        if fd < 0 {
+            return Err(anyhow!("cannot open packet socket: {}", Errno::last()));
+        }
+        let socket = PacketSocket(fd);
+
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = index as i32;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                size_of::<libc::sockaddr_ll>() as u32,
+            )
+        };
+        if res < 0 {
+            return Err(anyhow!("cannot bind packet socket: {}", Errno::last()));
+        }
+        Ok(socket)
+    }
+
+    /// setsockopt(SO_ATTACH_FILTER) with a classic BPF program.
+    pub fn attach_filter(&self, filter: &mut [libc::sock_filter]) -> Result<()> {
+        let program = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                self.0,
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &program as *const libc::sock_fprog as *const libc::c_void,
+                size_of::<libc::sock_fprog>() as u32,
+            )
+        };
+        if res < 0 {
+            return Err(anyhow!("cannot attach filter: {}", Errno::last()));
+        }
+        Ok(())
+    }
+
+    pub fn send(&self, frame: &[u8]) -> Result<usize> {
+        let res = unsafe {
+            libc::send(
+                self.0,
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+                0,
+            )
+        };
+        if res < 0 {
+            return Err(anyhow!("cannot send frame: {}", Errno::last()));
+        }
+        Ok(res as usize)
+    }
+
+    /// setsockopt(SO_RCVTIMEO), recv then fails with EAGAIN after timeout without a frame.
+    pub fn set_recv_timeout(&self, timeout: Duration) -> Result<()> {
+        let timeout = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                self.0,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                size_of::<libc::timeval>() as u32,
+            )
+        };
+        if res < 0 {
+            return Err(anyhow!("cannot set receive timeout: {}", Errno::last()));
+        }
+        Ok(())
+    }
+
+    pub fn recv(&self, buffer: &mut [u8]) -> nix::Result<usize> {
+        let res = unsafe {
+            libc::recv(
+                self.0,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        Errno::result(res).map(|len| len as usize)
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        let _ = close(self.0);
+    }
+}
+
+/// Parses a classic BPF program in the format printed by tcpdump -ddd: the number of
+/// instructions, then one "code jt jf k" line per instruction.
+pub fn parse_filter(filter: &str) -> Result<Vec<libc::sock_filter>> {
+    let invalid = |reason: &str| -> anyhow::Error {
+        IprouteError::InvalidArgument(format!("invalid filter: {}", reason)).into()
+    };
+    let mut lines = filter
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let count: usize = lines
+        .next()
+        .ok_or_else(|| invalid("empty program"))?
+        .parse()
+        .map_err(|_| invalid("missing instruction count"))?;
+
+    let mut program = vec![];
+    for line in lines {
+        let fields: Vec<u32> = line
+            .split_whitespace()
+            .map(|field| field.parse().map_err(|_| invalid(line)))
+            .collect::<Result<_>>()?;
+        if fields.len() != 4 || fields[0] > u16::MAX as u32 || fields[1] > 255 || fields[2] > 255 {
+            return Err(invalid(line));
+        }
+        program.push(libc::sock_filter {
+            code: fields[0] as u16,
+            jt: fields[1] as u8,
+            jf: fields[2] as u8,
+            k: fields[3],
+        });
+    }
+    if program.len() != count || count == 0 || count > u16::MAX as usize {
+        return Err(invalid("instruction count does not match the program"));
+    }
+    Ok(program)
+}
+
+fn write_pcap_header(file: &mut impl Write) -> Result<()> {
+    file.write_all(&PCAP_MAGIC.to_ne_bytes())?;
+    file.write_all(&PCAP_VERSION_MAJOR.to_ne_bytes())?;
+    file.write_all(&PCAP_VERSION_MINOR.to_ne_bytes())?;
+    // thiszone and sigfigs
+    file.write_all(&[0; 8])?;
+    file.write_all(&(SNAPLEN as u32).to_ne_bytes())?;
+    file.write_all(&LINKTYPE_ETHERNET.to_ne_bytes())?;
+    Ok(())
+}
+
+fn write_pcap_record(file: &mut impl Write, packet: &[u8]) -> Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    file.write_all(&(now.as_secs() as u32).to_ne_bytes())?;
+    file.write_all(&now.subsec_micros().to_ne_bytes())?;
+    file.write_all(&(packet.len() as u32).to_ne_bytes())?;
+    file.write_all(&(packet.len() as u32).to_ne_bytes())?;
+    file.write_all(packet)?;
+    Ok(())
+}
+
+/// How often a capture checks whether it was stopped while no packet arrives.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A running capture_link. Dropping it stops the capture like stop, discarding the count.
+pub struct Capture {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<u64>>>,
+}
+
+impl Capture {
+    /// Stops the capture and returns how many packets were written.
+    pub fn stop(mut self) -> Result<u64> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.wait()
+    }
+
+    /// Waits until the link goes down or away and returns how many packets were written.
+    pub fn join(mut self) -> Result<u64> {
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<u64> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| anyhow!("capture thread panicked"))?,
+            None => Err(anyhow!("capture already stopped")),
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.wait();
+    }
+}
+
+/// tcpdump -i name -w file [filter]
+/// filter is a classic BPF program as printed by tcpdump -ddd, see parse_filter.
+/// The socket is bound before returning, so every packet seen by name from then on is written.
+/// The capture runs until name goes down or away, or until it is stopped or dropped, which
+/// takes up to STOP_POLL_INTERVAL.
+pub fn capture_link(name: &str, filter: Option<&str>, file: &Path) -> Result<Capture> {
+    let socket = PacketSocket::bind(get_link_index(name)?)?;
+    if let Some(filter) = filter {
+        socket.attach_filter(&mut parse_filter(filter)?)?;
+    }
+    socket.set_recv_timeout(STOP_POLL_INTERVAL)?;
+    let mut file = BufWriter::new(File::create(file)?);
+    write_pcap_header(&mut file)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let thread = std::thread::Builder::new()
+        .name(format!("capture-{}", name))
+        .spawn(move || {
+            let mut buffer = vec![0; SNAPLEN];
+            let mut count = 0;
+            while !stopped.load(Ordering::Relaxed) {
+                match socket.recv(&mut buffer) {
+                    Ok(len) => {
+                        write_pcap_record(&mut file, &buffer[..len])?;
+                        count += 1;
+                    }
+                    Err(Errno::EINTR) | Err(Errno::EAGAIN) => continue,
+                    // the link went down or was deleted
+                    Err(Errno::ENETDOWN) | Err(Errno::ENXIO) | Err(Errno::ENODEV) => break,
+                    Err(e) => return Err(anyhow!("capture failed: {}", e)),
+                }
+            }
+            file.flush()?;
+            Ok(count)
+        })?;
+    Ok(Capture {
+        stop,
+        thread: Some(thread),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{read, read_dir, read_to_string, remove_file};
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use rtnetlink::new_connection;
+
+    use crate::capture::{capture_link, parse_filter, PacketSocket, PCAP_MAGIC};
+    use crate::ip::iplink::get_link_index;
+    use crate::ip::testutil::{add_veth, del_link};
+
+    /// tcpdump -ddd ether proto 0x88b5
+    const FILTER: &str = "4\n40 0 0 12\n21 0 1 34997\n6 0 0 262144\n6 0 0 0\n";
+
+    fn pcap_records(pcap: &[u8]) -> Vec<&[u8]> {
+        let mut records = vec![];
+        let mut offset = 24;
+        while offset < pcap.len() {
+            let mut len = [0; 4];
+            len.copy_from_slice(&pcap[offset + 8..offset + 12]);
+            let len = u32::from_ne_bytes(len) as usize;
+            records.push(&pcap[offset + 16..offset + 16 + len]);
+            offset += 16 + len;
+        }
+        records
+    }
+
+    /// The threads of this process named name.
+    fn capture_threads(name: &str) -> usize {
+        read_dir("/proc/self/task")
+            .unwrap()
+            .filter_map(|task| read_to_string(task.ok()?.path().join("comm")).ok())
+            .filter(|comm| comm.trim_end() == name)
+            .count()
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(parse_filter(FILTER).unwrap().len(), 4);
+        assert!(parse_filter("2\n6 0 0 0\n").is_err());
+        assert!(parse_filter("1\n6 0 0\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_capture_link() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        // a veth rather than a dummy, so that frames sent on the peer are received
+        add_veth(&mut handle, "cap0", "cap1", true).await.unwrap();
+
+        let file = Path::new("/tmp/iproute2-rs-capture.pcap");
+        let capture = capture_link("cap0", Some(FILTER), file).unwrap();
+
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame.extend_from_slice(&0x88b5u16.to_be_bytes());
+        frame.resize(60, 0);
+        let sent = PacketSocket::bind(get_link_index("cap1").unwrap()).map(|socket| {
+            (0..5)
+                .map(|_| socket.send(&frame))
+                .collect::<anyhow::Result<Vec<_>>>()
+        });
+        // veth hands frames to the peer through the backlog, let it drain before the link goes
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        del_link(&mut handle, "cap0").await.unwrap();
+
+        let count = capture.join();
+        let pcap = read(file).unwrap();
+        remove_file(file).unwrap();
+
+        sent.unwrap().unwrap();
+        assert_eq!(count.unwrap(), 5);
+        let records = pcap_records(&pcap);
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().all(|record| *record == frame.as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_capture_link_drop() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "cap2", "cap3", true).await.unwrap();
+
+        let file = Path::new("/tmp/iproute2-rs-capture-drop.pcap");
+        let capture = capture_link("cap2", None, file).unwrap();
+        // the thread names itself once it runs
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut running = capture_threads("capture-cap2");
+        while running == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            running = capture_threads("capture-cap2");
+        }
+        let start = Instant::now();
+        drop(capture);
+        let elapsed = start.elapsed();
+        let stopped = capture_threads("capture-cap2");
+        let pcap = read(file).unwrap();
+        remove_file(file).unwrap();
+
+        // the link is still up, so only the drop stopped the capture
+        del_link(&mut handle, "cap2").await.unwrap();
+
+        assert_eq!(running, 1);
+        assert_eq!(stopped, 0);
+        assert!(elapsed < Duration::from_secs(1));
+        // flushed: the header and whole records, e.g. of IPv6 router solicitations
+        assert_eq!(&pcap[..4], &PCAP_MAGIC.to_ne_bytes());
+        assert_eq!(
+            pcap_records(&pcap)
+                .iter()
+                .map(|record| record.len() + 16)
+                .sum::<usize>(),
+            pcap.len() - 24
+        );
+    }
+}
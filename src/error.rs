@@ -0,0 +1,46 @@
+use std::net::IpAddr;
+
+use thiserror::Error;
+
+/// Errors raised by this crate itself rather than by the kernel.
+/// They are returned inside anyhow::Error and can be recovered with downcast_ref.
+#[derive(Debug, Eq, PartialEq, Clone, Error)]
+pub enum IprouteError {
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("network unreachable: no route to {0}")]
+    NetworkUnreachable(IpAddr),
+    #[error("network namespace {0} is busy")]
+    NamespaceBusy(String),
+}
+
+/// err once what was undone after it, with the failure of the undo as context if any: err stays
+/// the error downcast_ref finds.
+pub(crate) fn undone(err: anyhow::Error, undo: anyhow::Result<()>, what: &str) -> anyhow::Error {
+    match undo {
+        Ok(()) => err,
+        Err(e) => err.context(format!("cannot undo {}: {:#}", what, e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use crate::error::{undone, IprouteError};
+
+    #[test]
+    fn test_undone() {
+        let err = || IprouteError::InvalidArgument("first".to_string()).into();
+
+        let res = undone(err(), Ok(()), "it");
+        assert_eq!(res.to_string(), "invalid argument: first");
+
+        let res = undone(err(), Err(anyhow!("second")), "it");
+        assert_eq!(
+            res.downcast_ref::<IprouteError>(),
+            Some(&IprouteError::InvalidArgument("first".to_string()))
+        );
+        assert_eq!(res.to_string(), "cannot undo it: second");
+    }
+}
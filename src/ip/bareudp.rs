@@ -0,0 +1,110 @@
+use anyhow::Result;
+use netlink_packet_route::nlas::Nla as NlaTrait;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{emit_nlas, LinkTypeTrait};
+
+pub const IFLA_BAREUDP_PORT: u16 = 1;
+pub const IFLA_BAREUDP_ETHERTYPE: u16 = 2;
+pub const IFLA_BAREUDP_MULTIPROTO_MODE: u16 = 4;
+
+pub const ETH_P_MPLS_UC: u16 = 0x8847;
+pub const ETH_P_IP: u16 = 0x0800;
+
+/// IFLA_BAREUDP_* attributes nested in IFLA_INFO_DATA.
+/// The port and ethertype are carried in network byte order.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InfoBareUdp {
+    Port(u16),
+    EtherType(u16),
+    MultiProtoMode,
+}
+
+impl NlaTrait for InfoBareUdp {
+    fn value_len(&self) -> usize {
+        match self {
+            InfoBareUdp::Port(_) | InfoBareUdp::EtherType(_) => 2,
+            InfoBareUdp::MultiProtoMode => 0,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            InfoBareUdp::Port(_) => IFLA_BAREUDP_PORT,
+            InfoBareUdp::EtherType(_) => IFLA_BAREUDP_ETHERTYPE,
+            InfoBareUdp::MultiProtoMode => IFLA_BAREUDP_MULTIPROTO_MODE,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            InfoBareUdp::Port(value) | InfoBareUdp::EtherType(value) => {
+                buffer.copy_from_slice(&value.to_be_bytes())
+            }
+            InfoBareUdp::MultiProtoMode => {}
+        }
+    }
+}
+
+/// ip link add name type bareudp dstport port ethertype ethertype [multiproto]
+/// multiproto extends an MPLS unicast tunnel to multicast, or an IPv4 one to IPv6.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct BareUdp {
+    pub port: u16,
+    pub ethertype: u16,
+    pub multiproto: bool,
+}
+
+impl LinkTypeTrait for BareUdp {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        let mut info = vec![
+            InfoBareUdp::Port(self.port),
+            InfoBareUdp::EtherType(self.ethertype),
+        ];
+        if self.multiproto {
+            info.push(InfoBareUdp::MultiProtoMode);
+        }
+
+        let mut link_info_nlas = vec![Info::Kind(InfoKind::Other("bareudp".to_string()))];
+        link_info_nlas.push(Info::Data(InfoData::Other(emit_nlas(&info))));
+        message.nlas.push(Nla::Info(link_info_nlas));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rtnetlink::new_connection;
+
+    use crate::ip::bareudp::{BareUdp, ETH_P_MPLS_UC};
+    use crate::ip::iplink::{get_link_info_raw, Action, IPLink, LinkTypeEnum};
+    use crate::ip::testutil::del_link;
+
+    #[tokio::test]
+    #[ignore = "needs the bareudp kernel module"]
+    async fn test_bareudp_mpls() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "bareudp0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::BareUdp(BareUdp {
+                port: 6635,
+                ethertype: ETH_P_MPLS_UC,
+                multiproto: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let info = get_link_info_raw("bareudp0");
+
+        del_link(&mut handle, "bareudp0").await.unwrap();
+
+        assert_eq!(info.unwrap().kind, "bareudp");
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::Result;
+use netlink_packet_route::nlas::Nla as NlaTrait;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+use rtnetlink::Handle;
+
+use super::iplink::{emit_nlas, get_slaves, LinkTypeTrait};
+
+pub const IFLA_BOND_MODE: u16 = 1;
+pub const IFLA_BOND_MIIMON: u16 = 3;
+pub const IFLA_BOND_UPDELAY: u16 = 4;
+pub const IFLA_BOND_DOWNDELAY: u16 = 5;
+pub const IFLA_BOND_USE_CARRIER: u16 = 6;
+pub const IFLA_BOND_ARP_INTERVAL: u16 = 7;
+pub const IFLA_BOND_XMIT_HASH_POLICY: u16 = 14;
+pub const IFLA_BOND_MIN_LINKS: u16 = 18;
+pub const IFLA_BOND_AD_LACP_RATE: u16 = 21;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum BondMode {
+    BalanceRr,
+    ActiveBackup,
+    BalanceXor,
+    Broadcast,
+    /// 802.3ad, LACP
+    Ieee8023Ad,
+    BalanceTlb,
+    BalanceAlb,
+}
+
+impl BondMode {
+    /// BOND_MODE_*
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            BondMode::BalanceRr => 0,
+            BondMode::ActiveBackup => 1,
+            BondMode::BalanceXor => 2,
+            BondMode::Broadcast => 3,
+            BondMode::Ieee8023Ad => 4,
+            BondMode::BalanceTlb => 5,
+            BondMode::BalanceAlb => 6,
+        }
+    }
+}
+
+/// How often the partner is asked for LACPDUs.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LacpRate {
+    /// every 30 seconds
+    Slow,
+    /// every second
+    Fast,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum XmitHashPolicy {
+    Layer2,
+    Layer34,
+    Layer23,
+    Encap23,
+    Encap34,
+}
+
+impl XmitHashPolicy {
+    /// BOND_XMIT_POLICY_*
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            XmitHashPolicy::Layer2 => 0,
+            XmitHashPolicy::Layer34 => 1,
+            XmitHashPolicy::Layer23 => 2,
+            XmitHashPolicy::Encap23 => 3,
+            XmitHashPolicy::Encap34 => 4,
+        }
+    }
+}
+
+/// IFLA_BOND_* attributes nested in IFLA_INFO_DATA.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InfoBond {
+    Mode(u8),
+    MiiMon(u32),
+    UpDelay(u32),
+    DownDelay(u32),
+    UseCarrier(bool),
+    ArpInterval(u32),
+    XmitHashPolicy(u8),
+    MinLinks(u32),
+    AdLacpRate(u8),
+}
+
+impl NlaTrait for InfoBond {
+    fn value_len(&self) -> usize {
+        match self {
+            InfoBond::Mode(_)
+            | InfoBond::UseCarrier(_)
+            | InfoBond::XmitHashPolicy(_)
+            | InfoBond::AdLacpRate(_) => 1,
+            _ => 4,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            InfoBond::Mode(_) => IFLA_BOND_MODE,
+            InfoBond::MiiMon(_) => IFLA_BOND_MIIMON,
+            InfoBond::UpDelay(_) => IFLA_BOND_UPDELAY,
+            InfoBond::DownDelay(_) => IFLA_BOND_DOWNDELAY,
+            InfoBond::UseCarrier(_) => IFLA_BOND_USE_CARRIER,
+            InfoBond::ArpInterval(_) => IFLA_BOND_ARP_INTERVAL,
+            InfoBond::XmitHashPolicy(_) => IFLA_BOND_XMIT_HASH_POLICY,
+            InfoBond::MinLinks(_) => IFLA_BOND_MIN_LINKS,
+            InfoBond::AdLacpRate(_) => IFLA_BOND_AD_LACP_RATE,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            InfoBond::Mode(value)
+            | InfoBond::XmitHashPolicy(value)
+            | InfoBond::AdLacpRate(value) => buffer[0] = *value,
+            InfoBond::UseCarrier(value) => buffer[0] = *value as u8,
+            InfoBond::MiiMon(value)
+            | InfoBond::UpDelay(value)
+            | InfoBond::DownDelay(value)
+            | InfoBond::ArpInterval(value)
+            | InfoBond::MinLinks(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+        }
+    }
+}
+
+/// ip link add name type bond mode mode miimon miimon ...
+/// The kernel refuses options that do not apply to mode, so lacp_rate is only sent in 802.3ad
+/// mode, xmit_hash_policy only in the balance-xor, 802.3ad and balance-tlb modes and
+/// arp_interval only when set.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Bond {
+    pub mode: BondMode,
+    /// milliseconds between MII link checks, 0 turns them off
+    pub miimon: u32,
+    /// milliseconds
+    pub updelay: u32,
+    /// milliseconds
+    pub downdelay: u32,
+    pub use_carrier: bool,
+    /// milliseconds between ARP link checks, 0 turns them off
+    pub arp_interval: u32,
+    pub lacp_rate: LacpRate,
+    pub xmit_hash_policy: XmitHashPolicy,
+    pub min_links: u32,
+}
+
+impl Default for Bond {
+    fn default() -> Self {
+        Bond {
+            mode: BondMode::BalanceRr,
+            miimon: 0,
+            updelay: 0,
+            downdelay: 0,
+            use_carrier: true,
+            arp_interval: 0,
+            lacp_rate: LacpRate::Slow,
+            xmit_hash_policy: XmitHashPolicy::Layer2,
+            min_links: 0,
+        }
+    }
+}
+
+impl Bond {
+    /// ip link add name type bond mode 802.3ad miimon 100 lacp_rate fast
+    ///     xmit_hash_policy layer3+4
+    pub fn lacp_default() -> Bond {
+        Bond {
+            mode: BondMode::Ieee8023Ad,
+            miimon: 100,
+            lacp_rate: LacpRate::Fast,
+            xmit_hash_policy: XmitHashPolicy::Layer34,
+            ..Default::default()
+        }
+    }
+
+    fn info_bond(&self) -> Vec<InfoBond> {
+        let mut nlas = vec![
+            InfoBond::Mode(self.mode.as_u8()),
+            InfoBond::MiiMon(self.miimon),
+            InfoBond::UpDelay(self.updelay),
+            InfoBond::DownDelay(self.downdelay),
+            InfoBond::UseCarrier(self.use_carrier),
+            InfoBond::MinLinks(self.min_links),
+        ];
+        if self.arp_interval != 0 {
+            nlas.push(InfoBond::ArpInterval(self.arp_interval));
+        }
+        if matches!(
+            self.mode,
+            BondMode::BalanceXor | BondMode::Ieee8023Ad | BondMode::BalanceTlb
+        ) {
+            nlas.push(InfoBond::XmitHashPolicy(self.xmit_hash_policy.as_u8()));
+        }
+        if self.mode == BondMode::Ieee8023Ad {
+            nlas.push(InfoBond::AdLacpRate(match self.lacp_rate {
+                LacpRate::Slow => 0,
+                LacpRate::Fast => 1,
+            }));
+        }
+        nlas
+    }
+}
+
+impl LinkTypeTrait for Bond {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::Bond),
+            Info::Data(InfoData::Bond(emit_nlas(&self.info_bond()))),
+        ]));
+        Ok(())
+    }
+}
+
+/// ip link show master bond
+pub async fn get_bond_slaves(handle: &Handle, bond: &str) -> Result<Vec<LinkMessage>> {
+    get_slaves(handle, bond).await
+}
+
+#[cfg(test)]
+mod test {
+    use rtnetlink::new_connection;
+
+    use crate::ip::bond::{get_bond_slaves, Bond, BondMode, InfoBond};
+    use crate::ip::iplink::{
+        delete_link_safe, describe_link, link_exists_checked, Action, IPLink, LinkTypeEnum, Opt,
+    };
+    use crate::ip::testutil::{add_veth, del_link};
+
+    #[test]
+    fn test_bond_mode_options() {
+        let lacp = Bond::lacp_default().info_bond();
+        assert!(lacp.contains(&InfoBond::Mode(4)));
+        assert!(lacp.contains(&InfoBond::AdLacpRate(1)));
+        assert!(lacp.contains(&InfoBond::XmitHashPolicy(1)));
+
+        let backup = Bond {
+            mode: BondMode::ActiveBackup,
+            ..Bond::lacp_default()
+        }
+        .info_bond();
+        assert!(!backup
+            .iter()
+            .any(|nla| matches!(nla, InfoBond::AdLacpRate(_) | InfoBond::XmitHashPolicy(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the bonding kernel module"]
+    async fn test_bond() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "bond0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bond(Bond::lacp_default())),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        let exists = link_exists_checked("bond0");
+
+        del_link(&mut handle, "bond0").await.unwrap();
+
+        assert!(exists.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the bonding kernel module"]
+    async fn test_get_bond_slaves() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "bond1".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bond(Bond {
+                mode: BondMode::ActiveBackup,
+                ..Bond::lacp_default()
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        // slaves must be down when enslaved
+        for (name, peer_name) in [("bondslv0", "bondslv1"), ("bondslv2", "bondslv3")] {
+            add_veth(&mut handle, name, peer_name, false).await.unwrap();
+        }
+        let mut enslaved = vec![];
+        for name in ["bondslv0", "bondslv2"] {
+            enslaved.push(
+                IPLink {
+                    action: Action::Set,
+                    name: name.to_string(),
+                    options: vec![Opt::Master("bond1".to_string())],
+                    link_type: None,
+                }
+                .execute(&mut handle)
+                .await,
+            );
+        }
+        let slaves = get_bond_slaves(&handle, "bond1").await;
+
+        for name in ["bondslv0", "bondslv2", "bond1"] {
+            delete_link_safe(&mut handle, name).await.unwrap();
+        }
+
+        for res in enslaved {
+            res.unwrap();
+        }
+        let mut names: Vec<String> = slaves
+            .unwrap()
+            .iter()
+            .map(|link| describe_link(link).name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["bondslv0".to_string(), "bondslv2".to_string()]);
+    }
+}
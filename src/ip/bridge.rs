@@ -1,11 +1,129 @@
+use std::convert::TryInto;
+
 use anyhow::Result;
+use futures::stream::{StreamExt, TryStreamExt};
+use netlink_packet_route::constants::*;
+use netlink_packet_route::nlas::neighbour::Nla as NeighbourNla;
+use netlink_packet_route::nlas::{
+    DefaultNla, Nla as NlaTrait, NlaBuffer, NlasIterator, NLA_F_NESTED,
+};
 use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
 use netlink_packet_route::rtnl::nlas::link::InfoBridge;
-use netlink_packet_route::LinkMessage;
+use netlink_packet_route::traits::{Emitable, Parseable};
+use netlink_packet_route::{
+    LinkMessage, NeighbourMessage, NetlinkMessage, NetlinkPayload, RtnlMessage,
+};
+use rtnetlink::Handle;
+
+use super::iplink::{emit_nlas, get_link_index, get_slaves, request_ack, LinkTypeTrait};
+use super::ipneigh::NeighState;
+
+pub const IFLA_BRIDGE_VLAN_INFO: u16 = 2;
+pub const IFLA_BRIDGE_VLAN_TUNNEL_INFO: u16 = 3;
+pub const IFLA_BRIDGE_VLAN_TUNNEL_ID: u16 = 1;
+pub const IFLA_BRIDGE_VLAN_TUNNEL_VID: u16 = 2;
+pub const IFLA_BRIDGE_VLAN_TUNNEL_FLAGS: u16 = 3;
+pub const IFLA_BRPORT_VLAN_TUNNEL: u16 = 29;
+
+pub const BRIDGE_VLAN_INFO_RANGE_BEGIN: u16 = 1 << 3;
+pub const BRIDGE_VLAN_INFO_RANGE_END: u16 = 1 << 4;
+
+/// IFLA_BRIDGE_* attributes nested in IFLA_AF_SPEC of an AF_BRIDGE link message.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum AfSpecBridge {
+    /// struct bridge_vlan_info
+    VlanInfo {
+        flags: u16,
+        vid: u16,
+    },
+    VlanTunnelInfo(Vec<VlanTunnelInfo>),
+}
+
+impl NlaTrait for AfSpecBridge {
+    fn value_len(&self) -> usize {
+        match self {
+            AfSpecBridge::VlanInfo { .. } => 4,
+            AfSpecBridge::VlanTunnelInfo(nlas) => nlas.as_slice().buffer_len(),
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            AfSpecBridge::VlanInfo { .. } => IFLA_BRIDGE_VLAN_INFO,
+            AfSpecBridge::VlanTunnelInfo(_) => IFLA_BRIDGE_VLAN_TUNNEL_INFO | NLA_F_NESTED,
+        }
+    }
 
-use super::iplink::LinkTypeTrait;
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            AfSpecBridge::VlanInfo { flags, vid } => {
+                buffer[..2].copy_from_slice(&flags.to_ne_bytes());
+                buffer[2..].copy_from_slice(&vid.to_ne_bytes());
+            }
+            AfSpecBridge::VlanTunnelInfo(nlas) => nlas.as_slice().emit(buffer),
+        }
+    }
+}
+
+/// IFLA_BRIDGE_VLAN_TUNNEL_* attributes nested in IFLA_BRIDGE_VLAN_TUNNEL_INFO.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum VlanTunnelInfo {
+    Id(u32),
+    Vid(u16),
+    Flags(u16),
+}
+
+impl NlaTrait for VlanTunnelInfo {
+    fn value_len(&self) -> usize {
+        match self {
+            VlanTunnelInfo::Id(_) => 4,
+            VlanTunnelInfo::Vid(_) | VlanTunnelInfo::Flags(_) => 2,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            VlanTunnelInfo::Id(_) => IFLA_BRIDGE_VLAN_TUNNEL_ID,
+            VlanTunnelInfo::Vid(_) => IFLA_BRIDGE_VLAN_TUNNEL_VID,
+            VlanTunnelInfo::Flags(_) => IFLA_BRIDGE_VLAN_TUNNEL_FLAGS,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            VlanTunnelInfo::Id(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            VlanTunnelInfo::Vid(value) | VlanTunnelInfo::Flags(value) => {
+                buffer.copy_from_slice(&value.to_ne_bytes())
+            }
+        }
+    }
+}
 
+/// IFLA_BRPORT_* attributes nested in IFLA_PROTINFO of an AF_BRIDGE link message.
 #[derive(Debug, Eq, PartialEq, Clone)]
+pub enum BridgePort {
+    VlanTunnel(bool),
+}
+
+impl NlaTrait for BridgePort {
+    fn value_len(&self) -> usize {
+        1
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            BridgePort::VlanTunnel(_) => IFLA_BRPORT_VLAN_TUNNEL,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            BridgePort::VlanTunnel(on) => buffer[0] = *on as u8,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
 pub struct Bridge {
     pub info: Vec<InfoBridge>,
 }
@@ -18,3 +136,363 @@ impl LinkTypeTrait for Bridge {
         Ok(())
     }
 }
+
+/// bridge link show master bridge
+pub async fn get_bridge_ports(handle: &Handle, bridge: &str) -> Result<Vec<LinkMessage>> {
+    get_slaves(handle, bridge).await
+}
+
+/// bridge link set dev port vlan_tunnel on &&
+/// bridge vlan add dev port vid vid_in &&
+/// bridge vlan add dev port vid vid_in tunnel_info id vid_out
+/// Frames of VLAN vid_in leaving port, which is usually a vxlan link in external mode, are sent
+/// with tunnel id vid_out (the VNI), and frames arriving with that tunnel id are tagged vid_in.
+/// Needs Linux >= 4.12 with CONFIG_BRIDGE_VLAN_FILTERING.
+pub async fn bridge_port_vlan_rewrite(
+    handle: &mut Handle,
+    port: &str,
+    vid_in: u16,
+    vid_out: u16,
+) -> Result<()> {
+    let mut message = LinkMessage::default();
+    message.header.interface_family = AF_BRIDGE as u8;
+    message.header.index = get_link_index(port)?;
+    // the kernel only parses IFLA_PROTINFO as IFLA_BRPORT_* attributes when it is flagged as
+    // nested, which Nla::ProtoInfo is not
+    let protinfo = emit_nlas(&[ProtInfo(vec![BridgePort::VlanTunnel(true)])]);
+    message
+        .nlas
+        .push(Nla::Other(DefaultNla::parse(&NlaBuffer::new(&protinfo))?));
+    message.nlas.push(Nla::AfSpecBridge(emit_nlas(&[
+        AfSpecBridge::VlanInfo {
+            flags: 0,
+            vid: vid_in,
+        },
+        AfSpecBridge::VlanTunnelInfo(vec![
+            VlanTunnelInfo::Id(vid_out as u32),
+            VlanTunnelInfo::Vid(vid_in),
+        ]),
+    ])));
+
+    let mut req = NetlinkMessage::from(RtnlMessage::SetLink(message));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+
+    request_ack(handle, req).await
+}
+
+struct ProtInfo(Vec<BridgePort>);
+
+impl NlaTrait for ProtInfo {
+    fn value_len(&self) -> usize {
+        self.0.as_slice().buffer_len()
+    }
+
+    fn kind(&self) -> u16 {
+        IFLA_PROTINFO | NLA_F_NESTED
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        self.0.as_slice().emit(buffer)
+    }
+}
+
+/// bridge -d vlan tunnelshow dev port
+/// The (vid, tunnel id) pairs mapped on port, with ranges expanded.
+pub async fn get_bridge_port_vlan_tunnels(handle: &Handle, port: &str) -> Result<Vec<(u16, u32)>> {
+    let index = get_link_index(port)?;
+    let mut links = handle
+        .link()
+        .get()
+        .set_filter_mask(AF_BRIDGE as u8, RTEXT_FILTER_BRVLAN)
+        .execute();
+
+    let mut tunnels = vec![];
+    while let Some(link) = links.try_next().await? {
+        if link.header.index != index {
+            continue;
+        }
+        for nla in &link.nlas {
+            let spec = match nla {
+                Nla::AfSpecBridge(spec) => spec,
+                _ => continue,
+            };
+            let mut range_begin = None;
+            for nla in NlasIterator::new(spec.as_slice()) {
+                let nla = nla?;
+                if nla.kind() != IFLA_BRIDGE_VLAN_TUNNEL_INFO {
+                    continue;
+                }
+                let (mut id, mut vid, mut flags) = (0, 0, 0);
+                for nla in NlasIterator::new(nla.value()) {
+                    let nla = nla?;
+                    let value = nla.value();
+                    match (nla.kind(), value.len()) {
+                        (IFLA_BRIDGE_VLAN_TUNNEL_ID, 4) => {
+                            id = u32::from_ne_bytes(value.try_into()?)
+                        }
+                        (IFLA_BRIDGE_VLAN_TUNNEL_VID, 2) => {
+                            vid = u16::from_ne_bytes(value.try_into()?)
+                        }
+                        (IFLA_BRIDGE_VLAN_TUNNEL_FLAGS, 2) => {
+                            flags = u16::from_ne_bytes(value.try_into()?)
+                        }
+                        _ => {}
+                    }
+                }
+                if flags & BRIDGE_VLAN_INFO_RANGE_BEGIN != 0 {
+                    range_begin = Some((vid, id));
+                } else if let (true, Some((begin_vid, begin_id))) =
+                    (flags & BRIDGE_VLAN_INFO_RANGE_END != 0, range_begin.take())
+                {
+                    for offset in 0..=vid.saturating_sub(begin_vid) {
+                        tunnels.push((begin_vid + offset, begin_id + offset as u32));
+                    }
+                } else {
+                    tunnels.push((vid, id));
+                }
+            }
+        }
+    }
+    Ok(tunnels)
+}
+
+/// bridge fdb add|del mac dev port [vlan vlan] [self] [master] [static|permanent]
+/// state is NeighState::NoArp for a static entry, NeighState::Permanent for a local one, i.e. a
+/// MAC address of the bridge itself, and NeighState::Reachable for a dynamic one that ages out.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct BridgeFdbEntry {
+    pub mac: [u8; 6],
+    pub port: String,
+    pub vlan: Option<u16>,
+    pub state: NeighState,
+    pub flags: FdbFlags,
+}
+
+flags! {
+    /// NTF_* flags of an FDB entry. Without SELF or MASTER, the entry goes to the FDB of the bridge
+    /// port is enslaved to.
+    pub struct FdbFlags(u8) {
+        /// The FDB of port itself, e.g. of a vxlan link or a NIC offloading it.
+        const SELF = NTF_SELF;
+        /// The FDB of the bridge port is enslaved to.
+        const MASTER = NTF_MASTER;
+        /// Learned by an external control plane, so the bridge does not age it out.
+        const EXTERN_LEARN = NTF_EXT_LEARNED;
+    }
+}
+
+impl BridgeFdbEntry {
+    fn message(&self) -> Result<NeighbourMessage> {
+        let mut message = NeighbourMessage::default();
+        message.header.family = AF_BRIDGE as u8;
+        message.header.ifindex = get_link_index(&self.port)?;
+        message.header.state = self.state.as_u16();
+        message.header.flags = self.flags.0;
+        message
+            .nlas
+            .push(NeighbourNla::LinkLocalAddress(self.mac.to_vec()));
+        if let Some(vlan) = self.vlan {
+            message.nlas.push(NeighbourNla::Vlan(vlan));
+        }
+        Ok(message)
+    }
+}
+
+/// bridge fdb add
+pub async fn fdb_add(handle: &mut Handle, entry: &BridgeFdbEntry) -> Result<()> {
+    let mut req = NetlinkMessage::from(RtnlMessage::NewNeighbour(entry.message()?));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE;
+    request_ack(handle, req).await
+}
+
+/// bridge fdb del
+/// The entry is found by mac, port and vlan, its state is ignored.
+pub async fn fdb_del(handle: &mut Handle, entry: &BridgeFdbEntry) -> Result<()> {
+    let mut req = NetlinkMessage::from(RtnlMessage::DelNeighbour(entry.message()?));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+    request_ack(handle, req).await
+}
+
+/// bridge fdb show dev port
+/// The entries of both the bridge and port itself, e.g. the multicast addresses of a veth. Those
+/// of the bridge carry its index as NDA_MASTER, those of port are flagged NTF_SELF.
+pub async fn fdb_get(handle: &Handle, port: &str) -> Result<Vec<NeighbourMessage>> {
+    let index = get_link_index(port)?;
+    let mut message = NeighbourMessage::default();
+    message.header.family = AF_BRIDGE as u8;
+    let mut req = NetlinkMessage::from(RtnlMessage::GetNeighbour(message));
+    req.header.flags = NLM_F_REQUEST | NLM_F_DUMP;
+
+    let mut entries = vec![];
+    let mut response = handle.clone().request(req)?;
+    while let Some(message) = response.next().await {
+        match message.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewNeighbour(entry))
+                if entry.header.ifindex == index =>
+            {
+                entries.push(entry)
+            }
+            NetlinkPayload::Error(err) => {
+                return Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err)))
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::constants::NUD_NOARP;
+    use netlink_packet_route::nlas::neighbour::Nla as NeighbourNla;
+    use netlink_packet_route::NeighbourMessage;
+    use rtnetlink::new_connection;
+
+    use crate::ip::bridge::{
+        bridge_port_vlan_rewrite, fdb_add, fdb_del, fdb_get, get_bridge_port_vlan_tunnels,
+        get_bridge_ports, Bridge, BridgeFdbEntry, FdbFlags,
+    };
+    use crate::ip::iplink::{get_link_index, Action, IPLink, LinkTypeEnum, Opt};
+    use crate::ip::ipneigh::NeighState;
+    use crate::ip::testutil::{add_veth, del_link};
+    use crate::ip::veth::Veth;
+
+    #[tokio::test]
+    async fn test_get_bridge_ports() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "brports".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        for (name, peer_name) in [("brport0", "brport1"), ("brport2", "brport3")] {
+            add_veth(&mut handle, name, peer_name, false).await.unwrap();
+            IPLink {
+                action: Action::Set,
+                name: name.to_string(),
+                options: vec![Opt::Master("brports".to_string())],
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+        }
+
+        let ports = get_bridge_ports(&handle, "brports").await;
+
+        for name in ["brports", "brport0", "brport2"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        assert_eq!(ports.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CONFIG_BRIDGE_VLAN_FILTERING"]
+    async fn test_bridge_port_vlan_rewrite() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "brvt0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        IPLink {
+            action: Action::Add,
+            name: "brvt1".to_string(),
+            options: vec![Opt::Master("brvt0".to_string())],
+            link_type: Some(LinkTypeEnum::Veth(Veth {
+                peer_name: "brvt2".to_string(),
+                options: vec![],
+                auto_up: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let res = bridge_port_vlan_rewrite(&mut handle, "brvt1", 100, 1000).await;
+        let tunnels = get_bridge_port_vlan_tunnels(&handle, "brvt1").await;
+
+        for name in ["brvt0", "brvt1"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        res.unwrap();
+        assert!(tunnels.unwrap().contains(&(100, 1000)));
+    }
+
+    #[tokio::test]
+    async fn test_fdb() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "fdbbr0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        for (name, peer_name) in [("fdb0", "fdb1"), ("fdb2", "fdb3")] {
+            IPLink {
+                action: Action::Add,
+                name: name.to_string(),
+                options: vec![Opt::Master("fdbbr0".to_string())],
+                link_type: Some(LinkTypeEnum::Veth(Veth {
+                    peer_name: peer_name.to_string(),
+                    options: vec![],
+                    auto_up: false,
+                })),
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+        }
+
+        let entry = BridgeFdbEntry {
+            mac: [0x02, 0, 0, 0, 0, 0x40],
+            port: "fdb0".to_string(),
+            vlan: None,
+            state: NeighState::NoArp,
+            flags: FdbFlags::MASTER,
+        };
+        let lladdr = NeighbourNla::LinkLocalAddress(entry.mac.to_vec());
+        let find = |entries: Vec<NeighbourMessage>| {
+            entries.into_iter().find(|fdb| fdb.nlas.contains(&lladdr))
+        };
+        let bridge = get_link_index("fdbbr0").unwrap();
+        let added = fdb_add(&mut handle, &entry).await;
+        let port = fdb_get(&handle, "fdb0").await;
+        let other_port = fdb_get(&handle, "fdb2").await;
+        let deleted = fdb_del(&mut handle, &entry).await;
+        let remaining = fdb_get(&handle, "fdb0").await;
+
+        for name in ["fdbbr0", "fdb0", "fdb2"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        added.unwrap();
+        deleted.unwrap();
+        let fdb = find(port.unwrap()).unwrap();
+        assert_eq!(fdb.header.state, NUD_NOARP);
+        assert!(fdb
+            .nlas
+            .contains(&NeighbourNla::Master(bridge.to_ne_bytes().to_vec())));
+        assert!(find(other_port.unwrap()).is_none());
+        assert!(find(remaining.unwrap()).is_none());
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::Result;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::LinkTypeTrait;
+
+/// ip link add name type dummy
+/// A link that drops everything sent through it, e.g. to hold a loopback address announced by a
+/// routing daemon.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Dummy;
+
+impl LinkTypeTrait for Dummy {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message
+            .nlas
+            .push(Nla::Info(vec![Info::Kind(InfoKind::Dummy)]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rtnetlink::new_connection;
+
+    use crate::ip::dummy::Dummy;
+    use crate::ip::iplink::{Action, IPLink, LinkTypeEnum};
+    use crate::ip::testutil::del_link;
+
+    #[tokio::test]
+    #[ignore = "needs the dummy kernel module"]
+    async fn test_dummy() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "dummy0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Dummy(Dummy)),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        let added = Path::new("/sys/class/net/dummy0").exists();
+
+        del_link(&mut handle, "dummy0").await.unwrap();
+
+        assert!(added);
+        assert!(!Path::new("/sys/class/net/dummy0").exists());
+    }
+}
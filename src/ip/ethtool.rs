@@ -0,0 +1,308 @@
+use anyhow::{anyhow, Result};
+use nix::sys::socket::{socket, AddressFamily, SockFlag, SockType};
+use nix::unistd::close;
+
+const SIOCETHTOOL: u32 = 0x8946;
+const ETHTOOL_GSET: u32 = 0x1;
+const ETHTOOL_SSET: u32 = 0x2;
+
+const DUPLEX_HALF: u8 = 0x0;
+const DUPLEX_FULL: u8 = 0x1;
+const AUTONEG_DISABLE: u8 = 0x0;
+const AUTONEG_ENABLE: u8 = 0x1;
+const SPEED_UNKNOWN: u32 = u32::MAX;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Duplex {
+    Half,
+    Full,
+    /// let autonegotiation pick
+    Auto,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LinkSettings {
+    /// Mb/s, None when the driver does not know
+    pub speed: Option<u32>,
+    pub duplex: Option<Duplex>,
+    pub autoneg: bool,
+}
+
+/// struct ethtool_cmd from linux/ethtool.h
+#[repr(C)]
+#[derive(Default)]
+struct EthtoolCmd {
+    cmd: u32,
+    supported: u32,
+    advertising: u32,
+    speed: u16,
+    duplex: u8,
+    port: u8,
+    phy_address: u8,
+    transceiver: u8,
+    autoneg: u8,
+    mdio_support: u8,
+    maxtxpkt: u32,
+    maxrxpkt: u32,
+    speed_hi: u16,
+    eth_tp_mdix: u8,
+    eth_tp_mdix_ctrl: u8,
+    lp_advertising: u32,
+    reserved: [u32; 2],
+}
+
+/// struct ifreq with ifr_data as the active member of the union.
+#[repr(C)]
+struct IfReq {
+    name: [u8; 16],
+    data: *mut EthtoolCmd,
+    padding: [u8; 16],
+}
+
+nix::ioctl_readwrite_bad!(ethtool_ioctl, SIOCETHTOOL, IfReq);
+
+fn ethtool(name: &str, cmd: &mut EthtoolCmd) -> Result<()> {
+    if name.len() >= 16 {
+        return Err(anyhow!("invalid interface name {}", name));
+    }
+    let mut req = IfReq {
+        name: [0; 16],
+        data: cmd,
+        padding: [0; 16],
+    };
+    req.name[..name.len()].copy_from_slice(name.as_bytes());
+
+    let fd = socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    let res = unsafe { ethtool_ioctl(fd, &mut req) };
+    close(fd)?;
+    if let Err(e) = res {
+        return Err(anyhow!("ethtool request on {} failed: {}", name, e));
+    }
+    Ok(())
+}
+
+/// ethtool name
+pub fn get_link_settings(name: &str) -> Result<LinkSettings> {
+    let mut cmd = EthtoolCmd {
+        cmd: ETHTOOL_GSET,
+        ..Default::default()
+    };
+    ethtool(name, &mut cmd)?;
+
+    let speed = (cmd.speed_hi as u32) << 16 | cmd.speed as u32;
+    Ok(LinkSettings {
+        speed: if speed == SPEED_UNKNOWN || speed == 0 {
+            None
+        } else {
+            Some(speed)
+        },
+        duplex: match cmd.duplex {
+            DUPLEX_HALF => Some(Duplex::Half),
+            DUPLEX_FULL => Some(Duplex::Full),
+            _ => None,
+        },
+        autoneg: cmd.autoneg == AUTONEG_ENABLE,
+    })
+}
+
+/// ethtool -s name [speed N] [duplex half|full] [autoneg on]
+/// rtnetlink has no attribute for these, and only drivers implementing set_link_ksettings accept them.
+pub fn set_link_settings(name: &str, speed: Option<u32>, duplex: Option<Duplex>) -> Result<()> {
+    let mut cmd = EthtoolCmd {
+        cmd: ETHTOOL_GSET,
+        ..Default::default()
+    };
+    ethtool(name, &mut cmd)?;
+
+    cmd.cmd = ETHTOOL_SSET;
+    if let Some(speed) = speed {
+        cmd.speed = speed as u16;
+        cmd.speed_hi = (speed >> 16) as u16;
+        cmd.autoneg = AUTONEG_DISABLE;
+    }
+    match duplex {
+        Some(Duplex::Half) => {
+            cmd.duplex = DUPLEX_HALF;
+            cmd.autoneg = AUTONEG_DISABLE;
+        }
+        Some(Duplex::Full) => {
+            cmd.duplex = DUPLEX_FULL;
+            cmd.autoneg = AUTONEG_DISABLE;
+        }
+        Some(Duplex::Auto) => cmd.autoneg = AUTONEG_ENABLE,
+        None => {}
+    }
+    ethtool(name, &mut cmd)
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::LinkMessage;
+    use rtnetlink::new_connection;
+    use serial_test::serial;
+
+    use crate::error::IprouteError;
+    use crate::ip::ethtool::{get_link_settings, Duplex};
+    use crate::ip::iplink::{
+        delete_link_safe, link_exists, link_exists_checked, Action, IPLink, LinkTypeEnum, Opt,
+    };
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_exec};
+    use crate::ip::testutil::{add_veth, del_link};
+    use crate::ip::tuntap::{TunTap, TunTapMode};
+    use crate::ip::veth::Veth;
+
+    #[tokio::test]
+    async fn test_link_settings() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "speed0", "speed1", false)
+            .await
+            .unwrap();
+
+        let settings = get_link_settings("speed0");
+        // veth reports a fixed speed and does not implement set_link_ksettings
+        let set = IPLink {
+            action: Action::Set,
+            name: "speed0".to_string(),
+            options: vec![Opt::Speed(1000), Opt::Duplex(Duplex::Half)],
+            link_type: None,
+        }
+        .execute(&mut handle)
+        .await;
+        let exists = link_exists_checked("speed0");
+
+        del_link(&mut handle, "speed0").await.unwrap();
+
+        let settings = settings.unwrap();
+        assert_eq!(settings.speed, Some(10000));
+        assert_eq!(settings.duplex, Some(Duplex::Full));
+        assert!(set.is_err());
+        // only a failed Add is rolled back
+        assert!(exists.unwrap());
+
+        // the settings are no longer applied to the link a Delete removed
+        add_veth(&mut handle, "speed2", "speed3", false)
+            .await
+            .unwrap();
+        IPLink {
+            action: Action::Delete,
+            name: "speed2".to_string(),
+            options: vec![Opt::Speed(1000)],
+            link_type: None,
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        assert!(!link_exists_checked("speed2").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_link_settings_tun() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        // tun keeps whatever set_link_ksettings is given
+        let added = IPLink {
+            action: Action::Add,
+            name: "speedtun0".to_string(),
+            options: vec![Opt::Speed(1000), Opt::Duplex(Duplex::Half)],
+            link_type: Some(LinkTypeEnum::TunTap(TunTap {
+                mode: TunTapMode::Tun,
+                owner: None,
+                group: None,
+                multi_queue: false,
+                persist: true,
+            })),
+        }
+        .execute(&mut handle)
+        .await;
+        let settings = get_link_settings("speedtun0");
+        delete_link_safe(&mut handle, "speedtun0").await.unwrap();
+
+        added.unwrap();
+        let settings = settings.unwrap();
+        assert_eq!(settings.speed, Some(1000));
+        assert_eq!(settings.duplex, Some(Duplex::Half));
+    }
+
+    #[tokio::test]
+    async fn test_link_settings_rollback() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let added = IPLink {
+            action: Action::Add,
+            name: "speed4".to_string(),
+            options: vec![Opt::Speed(1000)],
+            link_type: Some(LinkTypeEnum::Veth(Veth {
+                peer_name: "speed5".to_string(),
+                options: vec![],
+                auto_up: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await;
+        let exists = link_exists_checked("speed4");
+        delete_link_safe(&mut handle, "speed4").await.unwrap();
+
+        assert!(added.is_err());
+        assert!(!exists.unwrap());
+    }
+
+    #[test]
+    fn test_link_settings_opt() {
+        let mut message = LinkMessage::default();
+        for opt in [Opt::Speed(1000), Opt::Duplex(Duplex::Full)] {
+            let res = opt.opt(&mut message);
+            assert!(matches!(
+                res.unwrap_err().downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+        assert!(message.nlas.is_empty());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_link_settings_other_namespace() {
+        ip_net_ns_add("vnetns41".to_string()).unwrap();
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns41".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        // the ioctl would reach the calling thread's namespace, where speed6 does not exist
+        let added = IPLink {
+            action: Action::Add,
+            name: "speed6".to_string(),
+            options: vec![Opt::Speed(1000)],
+            link_type: Some(LinkTypeEnum::Veth(Veth {
+                peer_name: "speed7".to_string(),
+                options: vec![],
+                auto_up: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await;
+        let exists = link_exists(&handle, "speed6").await;
+
+        ip_net_ns_del("vnetns41".to_string(), false).unwrap();
+
+        assert!(matches!(
+            added.unwrap_err().downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        assert!(!exists.unwrap());
+    }
+}
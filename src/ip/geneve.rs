@@ -0,0 +1,431 @@
+use std::convert::TryInto;
+use std::net::IpAddr;
+
+use anyhow::Result;
+use netlink_packet_route::nlas::{Nla as NlaTrait, NlasIterator};
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+use rtnetlink::Handle;
+
+use super::iplink::{emit_nlas, LinkTypeTrait};
+use crate::error::IprouteError;
+use crate::ip::ipaddr::ip_from_bytes;
+use crate::ip::iproute::has_route;
+
+pub const IFLA_GENEVE_ID: u16 = 1;
+pub const IFLA_GENEVE_REMOTE: u16 = 2;
+pub const IFLA_GENEVE_TTL: u16 = 3;
+pub const IFLA_GENEVE_TOS: u16 = 4;
+pub const IFLA_GENEVE_PORT: u16 = 5;
+pub const IFLA_GENEVE_REMOTE6: u16 = 7;
+pub const IFLA_GENEVE_UDP_CSUM: u16 = 8;
+pub const IFLA_GENEVE_UDP_ZERO_CSUM6_TX: u16 = 9;
+pub const IFLA_GENEVE_UDP_ZERO_CSUM6_RX: u16 = 10;
+
+/// VNIs are 24 bits wide.
+pub const GENEVE_VNI_MAX: u32 = (1 << 24) - 1;
+/// IANA assigned port, used by the kernel when none is given.
+pub const GENEVE_PORT: u16 = 6081;
+
+/// IFLA_GENEVE_* attributes nested in IFLA_INFO_DATA.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InfoGeneve {
+    Id(u32),
+    Remote(IpAddr),
+    Ttl(u8),
+    Tos(u8),
+    /// network byte order
+    Port(u16),
+    UdpCsum(bool),
+    UdpZeroCsum6Tx(bool),
+    UdpZeroCsum6Rx(bool),
+}
+
+impl NlaTrait for InfoGeneve {
+    fn value_len(&self) -> usize {
+        match self {
+            InfoGeneve::Id(_) => 4,
+            InfoGeneve::Remote(IpAddr::V4(_)) => 4,
+            InfoGeneve::Remote(IpAddr::V6(_)) => 16,
+            InfoGeneve::Ttl(_) | InfoGeneve::Tos(_) => 1,
+            InfoGeneve::Port(_) => 2,
+            InfoGeneve::UdpCsum(_)
+            | InfoGeneve::UdpZeroCsum6Tx(_)
+            | InfoGeneve::UdpZeroCsum6Rx(_) => 1,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            InfoGeneve::Id(_) => IFLA_GENEVE_ID,
+            InfoGeneve::Remote(IpAddr::V4(_)) => IFLA_GENEVE_REMOTE,
+            InfoGeneve::Remote(IpAddr::V6(_)) => IFLA_GENEVE_REMOTE6,
+            InfoGeneve::Ttl(_) => IFLA_GENEVE_TTL,
+            InfoGeneve::Tos(_) => IFLA_GENEVE_TOS,
+            InfoGeneve::Port(_) => IFLA_GENEVE_PORT,
+            InfoGeneve::UdpCsum(_) => IFLA_GENEVE_UDP_CSUM,
+            InfoGeneve::UdpZeroCsum6Tx(_) => IFLA_GENEVE_UDP_ZERO_CSUM6_TX,
+            InfoGeneve::UdpZeroCsum6Rx(_) => IFLA_GENEVE_UDP_ZERO_CSUM6_RX,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            InfoGeneve::Id(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            InfoGeneve::Remote(IpAddr::V4(addr)) => buffer.copy_from_slice(&addr.octets()),
+            InfoGeneve::Remote(IpAddr::V6(addr)) => buffer.copy_from_slice(&addr.octets()),
+            InfoGeneve::Ttl(value) | InfoGeneve::Tos(value) => buffer[0] = *value,
+            InfoGeneve::Port(value) => buffer.copy_from_slice(&value.to_be_bytes()),
+            InfoGeneve::UdpCsum(value)
+            | InfoGeneve::UdpZeroCsum6Tx(value)
+            | InfoGeneve::UdpZeroCsum6Rx(value) => buffer[0] = *value as u8,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Geneve {
+    pub id: u32,
+    pub remote: IpAddr,
+    pub ttl: u8,
+    pub tos: u8,
+    pub port: u16,
+    /// checksum the outer UDP header of IPv4 packets
+    pub udp_csum: bool,
+    /// leave the outer UDP checksum of IPv6 packets zero, against RFC 8200, for hardware that
+    /// cannot offload it
+    pub udp_zero_csum6_tx: bool,
+    /// accept IPv6 packets with a zero outer UDP checksum
+    pub udp_zero_csum6_rx: bool,
+    /// Check that the kernel has a route to remote before the link is created.
+    pub validate_reachability: bool,
+}
+
+impl Geneve {
+    /// ip link add name type geneve id id remote remote
+    pub fn new(id: u32, remote: IpAddr) -> Result<Geneve> {
+        let geneve = Geneve {
+            id,
+            remote,
+            ttl: 0,
+            tos: 0,
+            port: GENEVE_PORT,
+            udp_csum: false,
+            udp_zero_csum6_tx: false,
+            udp_zero_csum6_rx: false,
+            validate_reachability: false,
+        };
+        geneve.validate()?;
+        Ok(geneve)
+    }
+
+    pub fn with_ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_tos(mut self, tos: u8) -> Self {
+        self.tos = tos;
+        self
+    }
+
+    /// The port is checked when the link is created.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn with_udp_csum(mut self, udp_csum: bool) -> Self {
+        self.udp_csum = udp_csum;
+        self
+    }
+
+    /// Only for an IPv6 remote, checked when the link is created.
+    pub fn with_udp_zero_csum6(mut self, tx: bool, rx: bool) -> Self {
+        self.udp_zero_csum6_tx = tx;
+        self.udp_zero_csum6_rx = rx;
+        self
+    }
+
+    /// With validate, adding the link fails with IprouteError::NetworkUnreachable when there is
+    /// no route to remote, rather than creating a tunnel that cannot send anything.
+    pub fn validate_reachability(mut self, validate: bool) -> Self {
+        self.validate_reachability = validate;
+        self
+    }
+
+    /// The route lookup done by IPLink::execute when validate_reachability is set.
+    pub async fn check_reachability(&self, handle: &Handle) -> Result<()> {
+        if !self.validate_reachability || has_route(handle, self.remote).await? {
+            return Ok(());
+        }
+        Err(IprouteError::NetworkUnreachable(self.remote).into())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.id > GENEVE_VNI_MAX {
+            return Err(IprouteError::InvalidArgument(format!(
+                "geneve id {} does not fit in 24 bits",
+                self.id
+            ))
+            .into());
+        }
+        if self.port == 0 {
+            return Err(IprouteError::InvalidArgument("geneve dstport is 0".to_string()).into());
+        }
+        if self.remote.is_ipv4() && (self.udp_zero_csum6_tx || self.udp_zero_csum6_rx) {
+            return Err(IprouteError::InvalidArgument(format!(
+                "udp6zerocsum needs an IPv6 remote, got {}",
+                self.remote
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl LinkTypeTrait for Geneve {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        self.validate()?;
+        let mut info = vec![
+            InfoGeneve::Id(self.id),
+            InfoGeneve::Remote(self.remote),
+            InfoGeneve::Ttl(self.ttl),
+            InfoGeneve::Tos(self.tos),
+            InfoGeneve::Port(self.port),
+            InfoGeneve::UdpCsum(self.udp_csum),
+        ];
+        if self.remote.is_ipv6() {
+            info.push(InfoGeneve::UdpZeroCsum6Tx(self.udp_zero_csum6_tx));
+            info.push(InfoGeneve::UdpZeroCsum6Rx(self.udp_zero_csum6_rx));
+        }
+
+        let mut link_info_nlas = vec![Info::Kind(InfoKind::Other("geneve".to_string()))];
+        link_info_nlas.push(Info::Data(InfoData::Other(emit_nlas(&info))));
+        message.nlas.push(Nla::Info(link_info_nlas));
+        Ok(())
+    }
+}
+
+/// The configuration of an existing geneve tunnel, as read back from the kernel.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct GeneveConfig {
+    pub id: u32,
+    pub remote: Option<IpAddr>,
+    pub ttl: u8,
+    pub tos: u8,
+    pub port: u16,
+    pub udp_csum: bool,
+    pub udp_zero_csum6_tx: bool,
+    pub udp_zero_csum6_rx: bool,
+}
+
+impl GeneveConfig {
+    /// Parses the IFLA_GENEVE_* attributes of IFLA_INFO_DATA.
+    pub fn from_info_data(data: &[u8]) -> Result<GeneveConfig> {
+        let mut config = GeneveConfig::default();
+        for nla in NlasIterator::new(data) {
+            let nla = nla?;
+            let value = nla.value();
+            match (nla.kind(), value.len()) {
+                (IFLA_GENEVE_ID, 4) => config.id = u32::from_ne_bytes(value.try_into()?),
+                (IFLA_GENEVE_REMOTE, 4) | (IFLA_GENEVE_REMOTE6, 16) => {
+                    config.remote = ip_from_bytes(value)
+                }
+                (IFLA_GENEVE_TTL, 1) => config.ttl = value[0],
+                (IFLA_GENEVE_TOS, 1) => config.tos = value[0],
+                (IFLA_GENEVE_PORT, 2) => config.port = u16::from_be_bytes(value.try_into()?),
+                (IFLA_GENEVE_UDP_CSUM, 1) => config.udp_csum = value[0] != 0,
+                (IFLA_GENEVE_UDP_ZERO_CSUM6_TX, 1) => config.udp_zero_csum6_tx = value[0] != 0,
+                (IFLA_GENEVE_UDP_ZERO_CSUM6_RX, 1) => config.udp_zero_csum6_rx = value[0] != 0,
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, Nla};
+    use netlink_packet_route::LinkMessage;
+    use rtnetlink::new_connection;
+    use serial_test::serial;
+
+    use crate::error::IprouteError;
+    use crate::ip::geneve::{Geneve, GeneveConfig};
+    use crate::ip::ipaddr::{AddrAction, IPAddr};
+    use crate::ip::iplink::{get_link, Action, IPLink, LinkTypeEnum, LinkTypeTrait};
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_block_on};
+    use crate::ip::testutil::{add_veth, del_link};
+
+    fn geneve_link(name: &str, remote: IpAddr) -> IPLink {
+        IPLink {
+            action: Action::Add,
+            name: name.to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Geneve(
+                Geneve::new(100, remote)
+                    .unwrap()
+                    .validate_reachability(true),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_geneve_vni_range() {
+        let remote = "192.0.2.1".parse().unwrap();
+        let err = Geneve::new(16777216, remote).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        assert!(Geneve::new(16777215, remote).is_ok());
+    }
+
+    #[test]
+    fn test_geneve_udp_csum() {
+        let remote: IpAddr = "2001:db8::1".parse().unwrap();
+        let mut message = LinkMessage::default();
+        Geneve::new(100, remote)
+            .unwrap()
+            .with_udp_zero_csum6(true, false)
+            .link_type(&mut message)
+            .unwrap();
+        let data = message
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Info(infos) => infos.iter().find_map(|info| match info {
+                    Info::Data(InfoData::Other(data)) => Some(data.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            })
+            .unwrap();
+        let config = GeneveConfig::from_info_data(&data).unwrap();
+        assert_eq!(config.remote, Some(remote));
+        assert!(!config.udp_csum);
+        assert!(config.udp_zero_csum6_tx);
+        assert!(!config.udp_zero_csum6_rx);
+
+        let err = Geneve::new(100, "192.0.2.1".parse().unwrap())
+            .unwrap()
+            .with_udp_zero_csum6(true, true)
+            .link_type(&mut LinkMessage::default())
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_geneve_port() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let geneve = Geneve::new(100, "192.0.2.1".parse().unwrap())
+            .unwrap()
+            .with_port(0);
+        let err = IPLink {
+            action: Action::Add,
+            name: "geneve0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Geneve(geneve)),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap_err();
+        assert!(err.downcast_ref::<IprouteError>().is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the geneve kernel module"]
+    async fn test_geneve() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "geneve0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Geneve(
+                Geneve::new(100, "192.0.2.1".parse().unwrap())
+                    .unwrap()
+                    .with_ttl(64),
+            )),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let link = get_link(&handle, "geneve0").await;
+
+        del_link(&mut handle, "geneve0").await.unwrap();
+
+        link.unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_geneve_unreachable_remote() {
+        ip_net_ns_add("vnetns14".to_string()).unwrap();
+        // a new namespace has no routes at all
+        let res = thread_net_ns_block_on("vnetns14".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            geneve_link("geneve1", "192.0.2.1".parse()?)
+                .execute(&mut handle)
+                .await
+        });
+        ip_net_ns_del("vnetns14".to_string(), false).unwrap();
+
+        let err = res.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IprouteError>(),
+            Some(&IprouteError::NetworkUnreachable(
+                "192.0.2.1".parse().unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    #[serial]
+    #[ignore = "needs the geneve kernel module"]
+    fn test_geneve_validate_reachability() {
+        ip_net_ns_add("vnetns15".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns15".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "genv0", "genv1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.15.0.1".parse()?, 24, "genv0")
+                .execute(&mut handle)
+                .await?;
+            let mut route = handle
+                .route()
+                .add()
+                .v4()
+                .destination_prefix("192.0.2.0".parse()?, 24)
+                .gateway("10.15.0.2".parse()?);
+            let message = route.message_mut().clone();
+            route.execute().await?;
+
+            let remote = "192.0.2.1".parse()?;
+            geneve_link("geneve2", remote).execute(&mut handle).await?;
+            del_link(&mut handle, "geneve2").await?;
+
+            handle.route().del(message).execute().await?;
+            Ok(geneve_link("geneve2", remote).execute(&mut handle).await)
+        });
+        ip_net_ns_del("vnetns15".to_string(), false).unwrap();
+
+        let err = res.unwrap().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::NetworkUnreachable(_))
+        ));
+    }
+}
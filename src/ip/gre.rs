@@ -0,0 +1,553 @@
+use std::convert::{TryFrom, TryInto};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::{anyhow, Result};
+use netlink_packet_route::nlas::{Nla as NlaTrait, NlasIterator};
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{emit_nlas, get_link_info_raw, LinkTypeTrait};
+
+pub const IFLA_GRE_LINK: u16 = 1;
+pub const IFLA_GRE_IFLAGS: u16 = 2;
+pub const IFLA_GRE_OFLAGS: u16 = 3;
+pub const IFLA_GRE_IKEY: u16 = 4;
+pub const IFLA_GRE_OKEY: u16 = 5;
+pub const IFLA_GRE_LOCAL: u16 = 6;
+pub const IFLA_GRE_REMOTE: u16 = 7;
+pub const IFLA_GRE_TTL: u16 = 8;
+pub const IFLA_GRE_TOS: u16 = 9;
+pub const IFLA_GRE_PMTUDISC: u16 = 10;
+pub const IFLA_GRE_ENCAP_LIMIT: u16 = 11;
+pub const IFLA_GRE_COLLECT_METADATA: u16 = 18;
+
+pub const GRE_CSUM: u16 = 0x8000;
+pub const GRE_KEY: u16 = 0x2000;
+pub const GRE_SEQ: u16 = 0x1000;
+
+/// IFLA_GRE_* attributes nested in IFLA_INFO_DATA.
+/// Flags and keys are carried in network byte order, as the kernel expects.
+/// ip6gre and ip6gretap take Local6 and Remote6 instead of Local and Remote, and read Ttl as the
+/// hop limit.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InfoGre {
+    Link(u32),
+    IFlags(u16),
+    OFlags(u16),
+    IKey(u32),
+    OKey(u32),
+    Local(Ipv4Addr),
+    Remote(Ipv4Addr),
+    Local6(Ipv6Addr),
+    Remote6(Ipv6Addr),
+    Ttl(u8),
+    Tos(u8),
+    PMtuDisc(u8),
+    EncapLimit(u8),
+    CollectMetadata,
+}
+
+impl NlaTrait for InfoGre {
+    fn value_len(&self) -> usize {
+        match self {
+            InfoGre::Link(_) | InfoGre::IKey(_) | InfoGre::OKey(_) => 4,
+            InfoGre::IFlags(_) | InfoGre::OFlags(_) => 2,
+            InfoGre::Local(_) | InfoGre::Remote(_) => 4,
+            InfoGre::Local6(_) | InfoGre::Remote6(_) => 16,
+            InfoGre::Ttl(_) | InfoGre::Tos(_) | InfoGre::PMtuDisc(_) | InfoGre::EncapLimit(_) => 1,
+            InfoGre::CollectMetadata => 0,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            InfoGre::Link(_) => IFLA_GRE_LINK,
+            InfoGre::IFlags(_) => IFLA_GRE_IFLAGS,
+            InfoGre::OFlags(_) => IFLA_GRE_OFLAGS,
+            InfoGre::IKey(_) => IFLA_GRE_IKEY,
+            InfoGre::OKey(_) => IFLA_GRE_OKEY,
+            InfoGre::Local(_) | InfoGre::Local6(_) => IFLA_GRE_LOCAL,
+            InfoGre::Remote(_) | InfoGre::Remote6(_) => IFLA_GRE_REMOTE,
+            InfoGre::Ttl(_) => IFLA_GRE_TTL,
+            InfoGre::Tos(_) => IFLA_GRE_TOS,
+            InfoGre::PMtuDisc(_) => IFLA_GRE_PMTUDISC,
+            InfoGre::EncapLimit(_) => IFLA_GRE_ENCAP_LIMIT,
+            InfoGre::CollectMetadata => IFLA_GRE_COLLECT_METADATA,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            InfoGre::Link(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            InfoGre::IFlags(value) | InfoGre::OFlags(value) => {
+                buffer.copy_from_slice(&value.to_be_bytes())
+            }
+            InfoGre::IKey(value) | InfoGre::OKey(value) => {
+                buffer.copy_from_slice(&value.to_be_bytes())
+            }
+            InfoGre::Local(addr) | InfoGre::Remote(addr) => buffer.copy_from_slice(&addr.octets()),
+            InfoGre::Local6(addr) | InfoGre::Remote6(addr) => {
+                buffer.copy_from_slice(&addr.octets())
+            }
+            InfoGre::Ttl(value)
+            | InfoGre::Tos(value)
+            | InfoGre::PMtuDisc(value)
+            | InfoGre::EncapLimit(value) => buffer[0] = *value,
+            InfoGre::CollectMetadata => {}
+        }
+    }
+}
+
+/// The IFLA_GRE_[IO]FLAGS and IFLA_GRE_[IO]KEY of a tunnel using the same key both ways.
+fn key_nlas(key: Option<u32>, checksum: bool, seq: bool) -> Vec<InfoGre> {
+    let mut flags = 0;
+    if checksum {
+        flags |= GRE_CSUM;
+    }
+    if seq {
+        flags |= GRE_SEQ;
+    }
+    if key.is_some() {
+        flags |= GRE_KEY;
+    }
+
+    let mut nlas = vec![InfoGre::IFlags(flags), InfoGre::OFlags(flags)];
+    if let Some(key) = key {
+        nlas.push(InfoGre::IKey(key));
+        nlas.push(InfoGre::OKey(key));
+    }
+    nlas
+}
+
+/// ip link add name type gre local local remote remote ttl ttl [key key] [csum] [seq]
+/// An IPv4 tunnel of IP packets.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Gre {
+    pub local: Ipv4Addr,
+    pub remote: Ipv4Addr,
+    pub ttl: u8,
+    pub key: Option<u32>,
+    pub checksum: bool,
+    pub seq: bool,
+    pub pmtudisc: bool,
+}
+
+impl Default for Gre {
+    fn default() -> Self {
+        Gre {
+            local: Ipv4Addr::UNSPECIFIED,
+            remote: Ipv4Addr::UNSPECIFIED,
+            ttl: 0,
+            key: None,
+            checksum: false,
+            seq: false,
+            pmtudisc: true,
+        }
+    }
+}
+
+impl Gre {
+    fn info_gre(&self) -> Vec<InfoGre> {
+        let mut nlas = key_nlas(self.key, self.checksum, self.seq);
+        nlas.push(InfoGre::Local(self.local));
+        nlas.push(InfoGre::Remote(self.remote));
+        nlas.push(InfoGre::Ttl(self.ttl));
+        nlas.push(InfoGre::PMtuDisc(self.pmtudisc as u8));
+        nlas
+    }
+}
+
+impl LinkTypeTrait for Gre {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::GreTun),
+            Info::Data(InfoData::GreTun(emit_nlas(&self.info_gre()))),
+        ]));
+        Ok(())
+    }
+}
+
+/// ip link add name type gretap ...
+/// An IPv4 tunnel of Ethernet frames, which can also run in metadata mode.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct GreTap {
+    pub local: Ipv4Addr,
+    pub remote: Ipv4Addr,
+    pub ttl: u8,
+    pub key: Option<u32>,
+    pub checksum: bool,
+    pub seq: bool,
+    pub pmtudisc: bool,
+    /// ip link add name type gretap external
+    /// In metadata mode the tunnel endpoints are supplied per packet (e.g. by an eBPF program),
+    /// so the addresses, key and flags above are not sent to the kernel.
+    pub collect_metadata: bool,
+}
+
+impl Default for GreTap {
+    fn default() -> Self {
+        GreTap {
+            local: Ipv4Addr::UNSPECIFIED,
+            remote: Ipv4Addr::UNSPECIFIED,
+            ttl: 0,
+            key: None,
+            checksum: false,
+            seq: false,
+            pmtudisc: true,
+            collect_metadata: false,
+        }
+    }
+}
+
+impl GreTap {
+    pub fn with_collect_metadata(mut self) -> Self {
+        self.collect_metadata = true;
+        self
+    }
+
+    fn info_gre(&self) -> Vec<InfoGre> {
+        if self.collect_metadata {
+            return vec![InfoGre::CollectMetadata];
+        }
+
+        Gre {
+            local: self.local,
+            remote: self.remote,
+            ttl: self.ttl,
+            key: self.key,
+            checksum: self.checksum,
+            seq: self.seq,
+            pmtudisc: self.pmtudisc,
+        }
+        .info_gre()
+    }
+}
+
+impl LinkTypeTrait for GreTap {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        let mut link_info_nlas = vec![Info::Kind(InfoKind::GreTap)];
+        link_info_nlas.push(Info::Data(InfoData::GreTap(emit_nlas(&self.info_gre()))));
+        message.nlas.push(Nla::Info(link_info_nlas));
+        Ok(())
+    }
+}
+
+/// ip link add name type ip6gre local local remote remote hoplimit hop_limit [key key] [csum]
+///     [seq] [encaplimit encap_limit]
+/// An IPv6 tunnel of IP packets. There is no path MTU discovery switch, IPv6 never fragments on
+/// the way.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Ip6Gre {
+    pub local: Ipv6Addr,
+    pub remote: Ipv6Addr,
+    pub hop_limit: u8,
+    pub key: Option<u32>,
+    pub checksum: bool,
+    pub seq: bool,
+    /// the tunnel encapsulation limit option, the kernel default of 4 when None
+    pub encap_limit: Option<u8>,
+}
+
+impl Default for Ip6Gre {
+    fn default() -> Self {
+        Ip6Gre {
+            local: Ipv6Addr::UNSPECIFIED,
+            remote: Ipv6Addr::UNSPECIFIED,
+            hop_limit: 64,
+            key: None,
+            checksum: false,
+            seq: false,
+            encap_limit: None,
+        }
+    }
+}
+
+impl Ip6Gre {
+    fn info_gre(&self) -> Vec<InfoGre> {
+        let mut nlas = key_nlas(self.key, self.checksum, self.seq);
+        nlas.push(InfoGre::Local6(self.local));
+        nlas.push(InfoGre::Remote6(self.remote));
+        nlas.push(InfoGre::Ttl(self.hop_limit));
+        if let Some(encap_limit) = self.encap_limit {
+            nlas.push(InfoGre::EncapLimit(encap_limit));
+        }
+        nlas
+    }
+}
+
+impl LinkTypeTrait for Ip6Gre {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::GreTun6),
+            Info::Data(InfoData::GreTun6(emit_nlas(&self.info_gre()))),
+        ]));
+        Ok(())
+    }
+}
+
+/// ip link add name type ip6gretap ...
+/// An IPv6 tunnel of Ethernet frames.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Ip6GreTap {
+    pub local: Ipv6Addr,
+    pub remote: Ipv6Addr,
+    pub hop_limit: u8,
+    pub key: Option<u32>,
+    pub checksum: bool,
+    pub seq: bool,
+    pub encap_limit: Option<u8>,
+}
+
+impl Default for Ip6GreTap {
+    fn default() -> Self {
+        Ip6GreTap {
+            local: Ipv6Addr::UNSPECIFIED,
+            remote: Ipv6Addr::UNSPECIFIED,
+            hop_limit: 64,
+            key: None,
+            checksum: false,
+            seq: false,
+            encap_limit: None,
+        }
+    }
+}
+
+impl LinkTypeTrait for Ip6GreTap {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        let info = Ip6Gre {
+            local: self.local,
+            remote: self.remote,
+            hop_limit: self.hop_limit,
+            key: self.key,
+            checksum: self.checksum,
+            seq: self.seq,
+            encap_limit: self.encap_limit,
+        }
+        .info_gre();
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::GreTap6),
+            Info::Data(InfoData::GreTap6(emit_nlas(&info))),
+        ]));
+        Ok(())
+    }
+}
+
+/// The configuration of an existing gre or gretap tunnel, as read back from the kernel.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct GreConfig {
+    pub local: Ipv4Addr,
+    pub remote: Ipv4Addr,
+    pub ttl: u8,
+    /// the output key, when GRE_KEY is set
+    pub key: Option<u32>,
+    pub iflags: u16,
+    pub oflags: u16,
+}
+
+impl GreConfig {
+    /// Parses the IFLA_GRE_* attributes of IFLA_INFO_DATA.
+    pub fn from_info_data(data: &[u8]) -> Result<GreConfig> {
+        let mut config = GreConfig::default();
+        let mut okey = 0;
+        for nla in NlasIterator::new(data) {
+            let nla = nla?;
+            let value = nla.value();
+            match (nla.kind(), value.len()) {
+                (IFLA_GRE_LOCAL, 4) => config.local = Ipv4Addr::from(<[u8; 4]>::try_from(value)?),
+                (IFLA_GRE_REMOTE, 4) => config.remote = Ipv4Addr::from(<[u8; 4]>::try_from(value)?),
+                (IFLA_GRE_TTL, 1) => config.ttl = value[0],
+                (IFLA_GRE_IFLAGS, 2) => config.iflags = u16::from_be_bytes(value.try_into()?),
+                (IFLA_GRE_OFLAGS, 2) => config.oflags = u16::from_be_bytes(value.try_into()?),
+                (IFLA_GRE_OKEY, 4) => okey = u32::from_be_bytes(value.try_into()?),
+                _ => {}
+            }
+        }
+        if config.oflags & GRE_KEY != 0 {
+            config.key = Some(okey);
+        }
+        Ok(config)
+    }
+}
+
+impl Default for GreConfig {
+    fn default() -> Self {
+        GreConfig {
+            local: Ipv4Addr::UNSPECIFIED,
+            remote: Ipv4Addr::UNSPECIFIED,
+            ttl: 0,
+            key: None,
+            iflags: 0,
+            oflags: 0,
+        }
+    }
+}
+
+/// ip -d link show name type gre|gretap
+/// The link is read with get_link_info_raw, in the network namespace of the calling thread.
+pub fn get_gre_config(name: &str) -> Result<GreConfig> {
+    let info = get_link_info_raw(name)?;
+    if info.kind != "gre" && info.kind != "gretap" {
+        return Err(anyhow!(
+            "{} is a {} link, not a gre tunnel",
+            name,
+            info.kind
+        ));
+    }
+
+    GreConfig::from_info_data(&info.data)
+}
+
+#[cfg(test)]
+mod test {
+    use futures::stream::TryStreamExt;
+    use netlink_packet_route::nlas::NlasIterator;
+    use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, Nla};
+    use netlink_packet_route::LinkMessage;
+    use rtnetlink::new_connection;
+
+    use crate::ip::gre::{
+        get_gre_config, Gre, GreConfig, GreTap, Ip6Gre, GRE_CSUM, GRE_KEY,
+        IFLA_GRE_COLLECT_METADATA, IFLA_GRE_ENCAP_LIMIT, IFLA_GRE_REMOTE,
+    };
+    use crate::ip::iplink::{Action, IPLink, LinkTypeEnum, LinkTypeTrait};
+    use crate::ip::testutil::del_link;
+
+    fn info_data(message: &LinkMessage) -> Vec<u8> {
+        message
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Info(infos) => infos.iter().find_map(|info| match info {
+                    Info::Data(InfoData::GreTun(data)) | Info::Data(InfoData::GreTun6(data)) => {
+                        Some(data.clone())
+                    }
+                    _ => None,
+                }),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_gre_info_data() {
+        let mut message = LinkMessage::default();
+        Gre {
+            local: "192.0.2.1".parse().unwrap(),
+            remote: "192.0.2.2".parse().unwrap(),
+            ttl: 16,
+            key: Some(42),
+            checksum: true,
+            ..Default::default()
+        }
+        .link_type(&mut message)
+        .unwrap();
+
+        let config = GreConfig::from_info_data(&info_data(&message)).unwrap();
+        assert_eq!(
+            config.remote,
+            "192.0.2.2".parse::<std::net::Ipv4Addr>().unwrap()
+        );
+        assert_eq!(config.ttl, 16);
+        assert_eq!(config.key, Some(42));
+        assert_eq!(config.oflags, GRE_CSUM | GRE_KEY);
+    }
+
+    #[test]
+    fn test_ip6gre_info_data() {
+        let remote: std::net::Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut message = LinkMessage::default();
+        Ip6Gre {
+            remote,
+            encap_limit: Some(0),
+            ..Default::default()
+        }
+        .link_type(&mut message)
+        .unwrap();
+
+        let data = info_data(&message);
+        let nlas = NlasIterator::new(data.as_slice())
+            .map(|nla| {
+                let nla = nla.unwrap();
+                (nla.kind(), nla.value().to_vec())
+            })
+            .collect::<Vec<_>>();
+        assert!(nlas.contains(&(IFLA_GRE_REMOTE, remote.octets().to_vec())));
+        assert!(nlas.contains(&(IFLA_GRE_ENCAP_LIMIT, vec![0])));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the ip_gre kernel module"]
+    async fn test_gretap_collect_metadata() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "gretap0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::GreTap(
+                GreTap::default().with_collect_metadata(),
+            )),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let link = handle
+            .link()
+            .get()
+            .match_name("gretap0".to_string())
+            .execute()
+            .try_next()
+            .await
+            .unwrap()
+            .unwrap();
+
+        del_link(&mut handle, "gretap0").await.unwrap();
+
+        let data = link
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Info(infos) => infos.iter().find_map(|info| match info {
+                    Info::Data(InfoData::GreTap(data)) => Some(data.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            })
+            .unwrap();
+        assert!(NlasIterator::new(data.as_slice())
+            .any(|nla| nla.unwrap().kind() == IFLA_GRE_COLLECT_METADATA));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the ip_gre kernel module"]
+    async fn test_get_gre_config() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "gretap1".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::GreTap(GreTap {
+                local: "192.0.2.1".parse().unwrap(),
+                remote: "192.0.2.2".parse().unwrap(),
+                ttl: 32,
+                key: Some(1234),
+                ..Default::default()
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let config = get_gre_config("gretap1");
+
+        del_link(&mut handle, "gretap1").await.unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.key, Some(1234));
+        assert_eq!(config.ttl, 32);
+    }
+}
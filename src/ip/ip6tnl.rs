@@ -0,0 +1,84 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+use anyhow::Result;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{emit_nlas, LinkTypeTrait};
+use super::iptun::{InfoIpTun, IP6_TNL_F_IGN_ENCAP_LIMIT, IP6_TNL_F_USE_ORIG_TCLASS};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Ip6Tnl {
+    pub local: Ipv6Addr,
+    pub remote: Ipv6Addr,
+    /// 0 means no encapsulation limit (encaplimit none)
+    pub encaplimit: u8,
+    pub flowinfo: u32,
+    pub use_orig_tclass: bool,
+}
+
+impl LinkTypeTrait for Ip6Tnl {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        let mut flags = 0;
+        if self.encaplimit == 0 {
+            flags |= IP6_TNL_F_IGN_ENCAP_LIMIT;
+        }
+        if self.use_orig_tclass {
+            flags |= IP6_TNL_F_USE_ORIG_TCLASS;
+        }
+        let info = vec![
+            InfoIpTun::Local(IpAddr::V6(self.local)),
+            InfoIpTun::Remote(IpAddr::V6(self.remote)),
+            InfoIpTun::EncapLimit(self.encaplimit),
+            InfoIpTun::FlowInfo(self.flowinfo),
+            InfoIpTun::Flags(flags),
+        ];
+
+        let mut link_info_nlas = vec![Info::Kind(InfoKind::Other("ip6tnl".to_string()))];
+        link_info_nlas.push(Info::Data(InfoData::Other(emit_nlas(&info))));
+        message.nlas.push(Nla::Info(link_info_nlas));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::rtnl::link::nlas::{Info, InfoKind, Nla};
+    use rtnetlink::new_connection;
+
+    use crate::ip::ip6tnl::Ip6Tnl;
+    use crate::ip::iplink::{get_link, Action, IPLink, LinkTypeEnum};
+    use crate::ip::testutil::del_link;
+
+    #[tokio::test]
+    #[ignore = "needs the ip6_tunnel kernel module"]
+    async fn test_ip6tnl() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "ip6tnl1".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Ip6Tnl(Ip6Tnl {
+                local: "fd00::1".parse().unwrap(),
+                remote: "fd00::2".parse().unwrap(),
+                encaplimit: 4,
+                flowinfo: 0,
+                use_orig_tclass: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let link = get_link(&handle, "ip6tnl1").await.unwrap();
+
+        del_link(&mut handle, "ip6tnl1").await.unwrap();
+
+        assert!(link.nlas.iter().any(|nla| matches!(
+            nla,
+            Nla::Info(infos) if infos.contains(&Info::Kind(InfoKind::Other("ip6tnl".to_string())))
+        )));
+    }
+}
@@ -0,0 +1,808 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::future::ready;
+use futures::{Stream, StreamExt, TryStreamExt};
+use ipnet::IpNet;
+use netlink_packet_route::constants::*;
+use netlink_packet_route::nlas::address::{CacheInfo, Nla};
+use netlink_packet_route::traits::Emitable;
+use netlink_packet_route::{AddressMessage, NetlinkMessage, NetlinkPayload, RtnlMessage};
+use rtnetlink::constants::{RTMGRP_IPV4_IFADDR, RTMGRP_IPV6_IFADDR};
+use rtnetlink::{Handle, IpVersion};
+use tokio::time::{sleep, Instant};
+
+use crate::error::{undone, IprouteError};
+use crate::ip::iplink::{
+    request_ack, resolve_link_index, retry_dump_on_enomem, subscribe, DumpHandle, NetlinkConnection,
+};
+
+/// ip addr add|del addr/prefix_len dev iface [label label] [broadcast broadcast]
+///     [preferred_lft preferred_lifetime] [valid_lft valid_lifetime] [flags]
+/// label and broadcast only apply to IPv4 addresses, and label must start with iface, e.g.
+/// eth0:1 on eth0.
+/// Lifetimes are in seconds, a missing one is forever. The kernel removes the address once
+/// valid_lifetime is over, and stops picking it as a source once preferred_lifetime is.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPAddr {
+    pub action: AddrAction,
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+    pub iface: String,
+    pub label: Option<String>,
+    pub broadcast: Option<Ipv4Addr>,
+    pub preferred_lifetime: Option<u32>,
+    pub valid_lifetime: Option<u32>,
+    pub flags: AddressFlags,
+}
+
+flags! {
+    /// IFA_F_* flags set when adding an address.
+    pub struct AddressFlags(u32) {
+        /// An IPv6 privacy address, see RFC 4941.
+        const TEMPORARY = IFA_F_TEMPORARY;
+        /// Skip duplicate address detection, so an IPv6 address is usable right away.
+        const NODAD = IFA_F_NODAD;
+    }
+}
+
+/// IFA_CACHEINFO lifetime meaning forever.
+const INFINITY_LIFE_TIME: u32 = u32::MAX;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum AddrAction {
+    Add,
+    Del,
+}
+
+impl IPAddr {
+    /// ip addr add|del addr/prefix_len dev iface, without label, broadcast, lifetimes or flags.
+    /// Those can be given with struct update syntax: IPAddr { label, ..IPAddr::new(...) }
+    pub fn new(action: AddrAction, addr: IpAddr, prefix_len: u8, iface: &str) -> IPAddr {
+        IPAddr {
+            action,
+            addr,
+            prefix_len,
+            iface: iface.to_string(),
+            label: None,
+            broadcast: None,
+            preferred_lifetime: None,
+            valid_lifetime: None,
+            flags: AddressFlags::default(),
+        }
+    }
+
+    pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
+        let mut message = AddressMessage::default();
+        message.header.prefix_len = self.prefix_len;
+        message.header.index = resolve_link_index(handle, &self.iface).await?;
+        match self.addr {
+            IpAddr::V4(addr) => {
+                message.header.family = AF_INET as u8;
+                message.nlas.push(Nla::Local(addr.octets().to_vec()));
+                message.nlas.push(Nla::Address(addr.octets().to_vec()));
+            }
+            IpAddr::V6(addr) => {
+                if self.label.is_some() || self.broadcast.is_some() {
+                    return Err(IprouteError::InvalidArgument(format!(
+                        "{} is IPv6, label and broadcast are IPv4 only",
+                        addr
+                    ))
+                    .into());
+                }
+                message.header.family = AF_INET6 as u8;
+                message.nlas.push(Nla::Address(addr.octets().to_vec()));
+            }
+        }
+        if let Some(label) = &self.label {
+            if !label.starts_with(&self.iface) {
+                return Err(IprouteError::InvalidArgument(format!(
+                    "label {} does not start with {}",
+                    label, self.iface
+                ))
+                .into());
+            }
+            message.nlas.push(Nla::Label(label.clone()));
+        }
+        if let Some(broadcast) = self.broadcast {
+            message
+                .nlas
+                .push(Nla::Broadcast(broadcast.octets().to_vec()));
+        }
+        if self.preferred_lifetime.is_some() || self.valid_lifetime.is_some() {
+            let preferred = self.preferred_lifetime.unwrap_or(INFINITY_LIFE_TIME);
+            let valid = self.valid_lifetime.unwrap_or(INFINITY_LIFE_TIME);
+            if valid == 0 || preferred > valid {
+                return Err(IprouteError::InvalidArgument(format!(
+                    "preferred lifetime {} and valid lifetime {} of {}",
+                    preferred, valid, self.addr
+                ))
+                .into());
+            }
+            let cache_info = CacheInfo {
+                ifa_preferred: preferred as i32,
+                ifa_valid: valid as i32,
+                cstamp: 0,
+                tstamp: 0,
+            };
+            let mut buffer = vec![0; cache_info.buffer_len()];
+            cache_info.emit(&mut buffer);
+            message.nlas.push(Nla::CacheInfo(buffer));
+        }
+        if self.flags.0 != 0 {
+            // the header only has room for the lower 8 bits
+            message.header.flags = self.flags.0 as u8;
+            message.nlas.push(Nla::Flags(self.flags.0));
+        }
+
+        let mut req = match self.action {
+            AddrAction::Del => NetlinkMessage::from(RtnlMessage::DelAddress(message)),
+            AddrAction::Add => NetlinkMessage::from(RtnlMessage::NewAddress(message)),
+        };
+
+        if self.action == AddrAction::Add {
+            req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE
+        } else {
+            req.header.flags = NLM_F_REQUEST | NLM_F_ACK
+        };
+
+        request_ack(handle, req).await
+    }
+}
+
+/// Address nlas carry 4 bytes for IPv4 and 16 bytes for IPv6.
+pub fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => None,
+    }
+}
+
+pub async fn get_addresses<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    name: &str,
+) -> Result<Vec<AddressMessage>> {
+    let handle = handle.into();
+    get_addresses_by_index(handle, resolve_link_index(handle.handle, name).await?).await
+}
+
+/// get_addresses_by_index, under the name ipaddr started with.
+pub async fn get_addrs<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    index: u32,
+) -> Result<Vec<AddressMessage>> {
+    get_addresses_by_index(handle, index).await
+}
+
+/// ip addr show dev index, without resolving the name first.
+/// The index is set in the dump request so the kernel can filter server-side. Kernels that do
+/// not (no strict checking on the socket) dump everything, so the result is filtered here too.
+pub async fn get_addresses_by_index<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    index: u32,
+) -> Result<Vec<AddressMessage>> {
+    let handle = handle.into();
+    retry_dump_on_enomem(handle.rcvbufs(), |rcvbuf| async move {
+        let mut message = AddressMessage::default();
+        message.header.index = index;
+        let mut req = NetlinkMessage::from(RtnlMessage::GetAddress(message));
+        req.header.flags = NLM_F_REQUEST | NLM_F_DUMP;
+
+        let mut addresses = vec![];
+        let mut response = handle.attempt(rcvbuf)?.request(req)?;
+        while let Some(message) = response.next().await {
+            match message.payload {
+                NetlinkPayload::InnerMessage(RtnlMessage::NewAddress(address)) => {
+                    if address.header.index == index {
+                        addresses.push(address);
+                    }
+                }
+                NetlinkPayload::Error(err) => {
+                    return Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err)))
+                }
+                _ => {}
+            }
+        }
+        Ok(addresses)
+    })
+    .await
+}
+
+/// ip -4|-6 addr show
+/// The addresses of version on every link, keyed by link index.
+pub async fn get_addrs_all<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    ip_version: IpVersion,
+) -> Result<HashMap<u32, Vec<AddressMessage>>> {
+    let handle = handle.into();
+    let family = match ip_version {
+        IpVersion::V4 => AF_INET,
+        IpVersion::V6 => AF_INET6,
+    } as u8;
+    let addresses: Vec<AddressMessage> =
+        retry_dump_on_enomem(handle.rcvbufs(), |rcvbuf| async move {
+            let addresses = handle
+                .attempt(rcvbuf)?
+                .address()
+                .get()
+                .execute()
+                .try_collect()
+                .await?;
+            Ok(addresses)
+        })
+        .await?;
+
+    let mut by_index: HashMap<u32, Vec<AddressMessage>> = HashMap::new();
+    for address in addresses {
+        if address.header.family == family {
+            by_index
+                .entry(address.header.index)
+                .or_default()
+                .push(address);
+        }
+    }
+    Ok(by_index)
+}
+
+/// ip addr show dev name, get_addrs_all for a single link: the name is resolved through handle,
+/// see get_addresses.
+pub async fn get_addrs_by_name<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    name: &str,
+) -> Result<Vec<AddressMessage>> {
+    let handle = handle.into();
+    get_addrs(handle, resolve_link_index(handle.handle, name).await?).await
+}
+
+/// ip addr add addr dev dev, unless dev already has addr.
+/// Returns whether the address was added.
+pub async fn addr_add_idempotent(handle: &mut Handle, dev: &str, addr: IpNet) -> Result<bool> {
+    let exists = get_addresses(&*handle, dev).await?.iter().any(|address| {
+        address.header.prefix_len == addr.prefix_len()
+            && address.nlas.iter().any(|nla| match nla {
+                Nla::Address(bytes) => ip_from_bytes(bytes) == Some(addr.addr()),
+                _ => false,
+            })
+    });
+    if exists {
+        return Ok(false);
+    }
+
+    IPAddr::new(AddrAction::Add, addr.addr(), addr.prefix_len(), dev)
+        .execute(handle)
+        .await?;
+    Ok(true)
+}
+
+/// How often addr_add_wait_dad looks at the address again.
+const DAD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The IFA_F_* flags of address, from IFA_FLAGS when present as it holds the flags that do not
+/// fit in the header.
+fn address_flags(address: &AddressMessage) -> u32 {
+    address
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Flags(flags) => Some(*flags),
+            _ => None,
+        })
+        .unwrap_or(address.header.flags as u32)
+}
+
+/// ip addr add addr dev dev, then waits until the address is no longer tentative.
+/// IPv6 addresses can only be bound once duplicate address detection is done; IPv4 addresses
+/// and links that skip DAD return right away. Fails when a duplicate is found, or when the
+/// address is still tentative after timeout, e.g. because dev is down.
+pub async fn addr_add_wait_dad(
+    handle: &mut Handle,
+    dev: &str,
+    addr: IpNet,
+    timeout: Duration,
+) -> Result<()> {
+    IPAddr::new(AddrAction::Add, addr.addr(), addr.prefix_len(), dev)
+        .execute(handle)
+        .await?;
+
+    let index = resolve_link_index(handle, dev).await?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let flags = get_addresses_by_index(&*handle, index)
+            .await?
+            .iter()
+            .find(|address| {
+                address.nlas.iter().any(|nla| match nla {
+                    Nla::Address(bytes) => ip_from_bytes(bytes) == Some(addr.addr()),
+                    _ => false,
+                })
+            })
+            .map(address_flags)
+            .ok_or_else(|| anyhow!("{} went away from {} during DAD", addr, dev))?;
+        if flags & IFA_F_DADFAILED != 0 {
+            return Err(anyhow!(
+                "duplicate address detected for {} on {}",
+                addr,
+                dev
+            ));
+        }
+        if flags & IFA_F_TENTATIVE == 0 {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "{} on {} is still tentative after {:?}",
+                addr,
+                dev,
+                timeout
+            ));
+        }
+        sleep(DAD_POLL_INTERVAL).await;
+    }
+}
+
+/// ip addr add addr dev to_dev && ip addr del addr dev from_dev
+///
+/// Netlink has no primitive to move an address, so there is always a window between the two requests.
+/// By default the address is added to to_dev before it is removed from from_dev: it stays reachable,
+/// but both interfaces own it for a moment. With strict the address is removed first, so it is never
+/// assigned twice, but neither interface owns it until the add completes.
+pub async fn addr_move(
+    handle: &mut Handle,
+    addr: IpNet,
+    from_dev: &str,
+    to_dev: &str,
+    strict: bool,
+) -> Result<()> {
+    let ip_addr = |action: AddrAction, iface: &str| {
+        IPAddr::new(action, addr.addr(), addr.prefix_len(), iface)
+    };
+
+    if strict {
+        ip_addr(AddrAction::Del, from_dev).execute(handle).await?;
+        if let Err(e) = ip_addr(AddrAction::Add, to_dev).execute(handle).await {
+            // put the address back where it was rather than losing it
+            let undo = ip_addr(AddrAction::Add, from_dev).execute(handle).await;
+            return Err(undone(
+                e,
+                undo,
+                &format!("the removal of {} from {}", addr, from_dev),
+            ));
+        }
+    } else {
+        ip_addr(AddrAction::Add, to_dev).execute(handle).await?;
+        ip_addr(AddrAction::Del, from_dev).execute(handle).await?;
+    }
+    Ok(())
+}
+
+/// A notification of ip monitor address.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum AddrEvent {
+    /// Also sent when its flags or lifetimes change, e.g. once DAD is over.
+    Added(AddressMessage),
+    Deleted(AddressMessage),
+}
+
+/// ip monitor address, of both IPv4 and IPv6, received as subscribe(connection,
+/// RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR) until the stream is dropped, in the network namespace
+/// connection was opened in.
+pub fn addr_event_stream(
+    connection: NetlinkConnection,
+) -> Result<impl Stream<Item = Result<AddrEvent>> + Unpin> {
+    let (_, messages) = subscribe(connection, RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR)?;
+    Ok(messages.filter_map(|(message, _)| {
+        ready(match message.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewAddress(address)) => {
+                Some(Ok(AddrEvent::Added(address)))
+            }
+            NetlinkPayload::InnerMessage(RtnlMessage::DelAddress(address)) => {
+                Some(Ok(AddrEvent::Deleted(address)))
+            }
+            NetlinkPayload::Error(err) => {
+                Some(Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err))))
+            }
+            _ => None,
+        })
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv6Addr;
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use netlink_packet_route::nlas::address::{CacheInfo, CacheInfoBuffer, Nla};
+    use netlink_packet_route::traits::Parseable;
+    use netlink_packet_route::{AddressMessage, AF_INET, IFA_F_NODAD, IFA_F_TENTATIVE};
+    use rtnetlink::{new_connection, IpVersion};
+    use serial_test::serial;
+
+    use crate::error::IprouteError;
+    use crate::ip::ipaddr::{
+        addr_add_idempotent, addr_add_wait_dad, addr_event_stream, addr_move, get_addresses,
+        get_addresses_by_index, get_addrs, get_addrs_all, get_addrs_by_name, AddrAction, AddrEvent,
+        AddressFlags, IPAddr,
+    };
+    use crate::ip::iplink::get_link;
+    use crate::ip::ipnetns::{
+        ip_net_ns_add, ip_net_ns_del, thread_net_ns_block_on, thread_net_ns_exec,
+    };
+    use crate::ip::testutil::{add_veth, del_link};
+
+    fn has_address(addresses: &[AddressMessage], octets: &[u8]) -> bool {
+        addresses.iter().any(|address| {
+            address
+                .nlas
+                .iter()
+                .any(|nla| matches!(nla, Nla::Address(addr) if addr == octets))
+        })
+    }
+
+    #[tokio::test]
+    async fn test_addr_move() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "addrmv0", "addrmv1", false)
+            .await
+            .unwrap();
+
+        IPAddr::new(
+            AddrAction::Add,
+            "192.0.2.10".parse().unwrap(),
+            24,
+            "addrmv0",
+        )
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        addr_move(
+            &mut handle,
+            "192.0.2.10/24".parse().unwrap(),
+            "addrmv0",
+            "addrmv1",
+            false,
+        )
+        .await
+        .unwrap();
+
+        let from = get_addresses(&handle, "addrmv0").await.unwrap();
+        let to = get_addresses(&handle, "addrmv1").await.unwrap();
+
+        del_link(&mut handle, "addrmv0").await.unwrap();
+
+        assert!(!has_address(&from, &[192, 0, 2, 10]));
+        assert!(has_address(&to, &[192, 0, 2, 10]));
+    }
+
+    #[tokio::test]
+    async fn test_addr_add_idempotent() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "addridem0", "addridem1", false)
+            .await
+            .unwrap();
+
+        let addr = "192.0.2.20/24".parse().unwrap();
+        let first = addr_add_idempotent(&mut handle, "addridem0", addr).await;
+        let second = addr_add_idempotent(&mut handle, "addridem0", addr).await;
+        let addresses = get_addresses(&handle, "addridem0").await.unwrap();
+
+        del_link(&mut handle, "addridem0").await.unwrap();
+
+        assert!(first.unwrap());
+        assert!(!second.unwrap());
+        let copies = addresses
+            .iter()
+            .filter(|address| {
+                address
+                    .nlas
+                    .iter()
+                    .any(|nla| matches!(nla, Nla::Address(addr) if addr == &[192, 0, 2, 20]))
+            })
+            .count();
+        assert_eq!(copies, 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_addresses_by_index() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "addridx0", "addridx1", false)
+            .await
+            .unwrap();
+
+        for addr in ["192.0.2.30", "192.0.2.31"] {
+            IPAddr::new(AddrAction::Add, addr.parse().unwrap(), 24, "addridx0")
+                .execute(&mut handle)
+                .await
+                .unwrap();
+        }
+        let index = get_link(&handle, "addridx0").await.unwrap().header.index;
+        let addresses = get_addresses_by_index(&handle, index).await;
+        let addrs = get_addrs(&handle, index).await;
+
+        del_link(&mut handle, "addridx0").await.unwrap();
+
+        let addresses = addresses.unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert!(addresses
+            .iter()
+            .all(|address| address.header.index == index));
+        assert_eq!(addrs.unwrap(), addresses);
+    }
+
+    #[tokio::test]
+    async fn test_addr_add_wait_dad() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        for (name, peer_name, auto_up) in [("dad0", "dad1", true), ("dad2", "dad3", false)] {
+            add_veth(&mut handle, name, peer_name, auto_up)
+                .await
+                .unwrap();
+        }
+
+        let done = addr_add_wait_dad(
+            &mut handle,
+            "dad0",
+            "2001:db8::1/64".parse().unwrap(),
+            Duration::from_secs(5),
+        )
+        .await;
+        // DAD does not start before the link is up
+        let timed_out = addr_add_wait_dad(
+            &mut handle,
+            "dad2",
+            "2001:db8::2/64".parse().unwrap(),
+            Duration::from_millis(300),
+        )
+        .await;
+
+        for name in ["dad0", "dad2"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        done.unwrap();
+        assert!(timed_out.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_addr_label_broadcast() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "addrlbl0", "addrlbl1", false)
+            .await
+            .unwrap();
+
+        let ip_addr = |addr: &str, label: &str| IPAddr {
+            label: Some(label.to_string()),
+            broadcast: Some("192.0.2.255".parse().unwrap()),
+            ..IPAddr::new(AddrAction::Add, addr.parse().unwrap(), 24, "addrlbl0")
+        };
+        let added = ip_addr("192.0.2.40", "addrlbl0:1")
+            .execute(&mut handle)
+            .await;
+        let wrong_label = ip_addr("192.0.2.41", "other:1").execute(&mut handle).await;
+        let ipv6 = ip_addr("2001:db8::40", "addrlbl0:2")
+            .execute(&mut handle)
+            .await;
+        let addresses = get_addresses(&handle, "addrlbl0").await;
+
+        del_link(&mut handle, "addrlbl0").await.unwrap();
+
+        added.unwrap();
+        for res in [wrong_label, ipv6] {
+            assert!(matches!(
+                res.unwrap_err().downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+        let addresses = addresses.unwrap();
+        assert_eq!(addresses.len(), 1);
+        assert!(addresses[0]
+            .nlas
+            .contains(&Nla::Label("addrlbl0:1".to_string())));
+        assert!(addresses[0]
+            .nlas
+            .contains(&Nla::Broadcast(vec![192, 0, 2, 255])));
+    }
+
+    #[tokio::test]
+    async fn test_addr_lifetimes_nodad() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        // down, so DAD would leave the address tentative
+        add_veth(&mut handle, "addrlft0", "addrlft1", false)
+            .await
+            .unwrap();
+
+        let ip_addr = |preferred_lifetime, valid_lifetime| IPAddr {
+            preferred_lifetime,
+            valid_lifetime,
+            flags: AddressFlags::NODAD,
+            ..IPAddr::new(
+                AddrAction::Add,
+                "2001:db8::50".parse().unwrap(),
+                64,
+                "addrlft0",
+            )
+        };
+        let invalid = ip_addr(Some(300), Some(100)).execute(&mut handle).await;
+        let added = ip_addr(Some(100), Some(300)).execute(&mut handle).await;
+        let addresses = get_addresses(&handle, "addrlft0").await;
+
+        del_link(&mut handle, "addrlft0").await.unwrap();
+
+        assert!(matches!(
+            invalid.unwrap_err().downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        added.unwrap();
+        let octets = "2001:db8::50"
+            .parse::<Ipv6Addr>()
+            .unwrap()
+            .octets()
+            .to_vec();
+        let address = addresses
+            .unwrap()
+            .into_iter()
+            .find(|address| address.nlas.contains(&Nla::Address(octets.clone())))
+            .unwrap();
+        let flags = address
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Flags(flags) => Some(*flags),
+                _ => None,
+            })
+            .unwrap();
+        assert_ne!(flags & IFA_F_NODAD, 0);
+        assert_eq!(flags & IFA_F_TENTATIVE, 0);
+        let cache_info = address
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::CacheInfo(bytes) => CacheInfo::parse(&CacheInfoBuffer::new(bytes)).ok(),
+                _ => None,
+            })
+            .unwrap();
+        assert!(cache_info.ifa_preferred > 0 && cache_info.ifa_preferred <= 100);
+        assert!(cache_info.ifa_valid > 100 && cache_info.ifa_valid <= 300);
+    }
+
+    #[tokio::test]
+    async fn test_get_addrs_all() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "addrall0", "addrall1", false)
+            .await
+            .unwrap();
+
+        for (iface, addr, prefix_len) in [
+            ("addrall0", "192.0.2.60", 24),
+            ("addrall1", "192.0.2.61", 24),
+            ("addrall1", "2001:db8::61", 64),
+        ] {
+            IPAddr {
+                flags: AddressFlags::NODAD,
+                ..IPAddr::new(AddrAction::Add, addr.parse().unwrap(), prefix_len, iface)
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+        }
+        let index0 = get_link(&handle, "addrall0").await.unwrap().header.index;
+        let index1 = get_link(&handle, "addrall1").await.unwrap().header.index;
+        let v4 = get_addrs_all(&handle, IpVersion::V4).await;
+        let v6 = get_addrs_all(&handle, IpVersion::V6).await;
+        let by_name = get_addrs_by_name(&handle, "addrall1").await;
+
+        del_link(&mut handle, "addrall0").await.unwrap();
+
+        let (v4, v6) = (v4.unwrap(), v6.unwrap());
+        assert!(has_address(&v4[&index0], &[192, 0, 2, 60]));
+        assert!(has_address(&v4[&index1], &[192, 0, 2, 61]));
+        assert!(v4
+            .values()
+            .flatten()
+            .all(|address| address.header.family == AF_INET as u8));
+        assert_eq!(v6[&index1].len(), 1);
+        assert!(!v6.contains_key(&index0));
+        let by_name = by_name.unwrap();
+        assert_eq!(by_name.len(), 2);
+        assert!(by_name.iter().all(|address| address.header.index == index1));
+    }
+
+    #[tokio::test]
+    async fn test_addr_event_stream() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "aevt0", "aevt1", false)
+            .await
+            .unwrap();
+        let index = get_link(&handle, "aevt0").await.unwrap().header.index;
+
+        let mut events = addr_event_stream(new_connection().unwrap()).unwrap();
+        let added = IPAddr::new(AddrAction::Add, "10.14.0.1".parse().unwrap(), 24, "aevt0")
+            .execute(&mut handle)
+            .await;
+        // other tests add and delete addresses meanwhile
+        let event = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = events.next().await {
+                match event? {
+                    AddrEvent::Added(address)
+                        if address.header.index == index
+                            && has_address(std::slice::from_ref(&address), &[10, 14, 0, 1]) =>
+                    {
+                        return Ok(address)
+                    }
+                    _ => {}
+                }
+            }
+            Err(anyhow::anyhow!("address events stopped"))
+        })
+        .await;
+
+        del_link(&mut handle, "aevt0").await.unwrap();
+
+        added.unwrap();
+        let address = event.unwrap().unwrap();
+        assert_eq!(address.header.prefix_len, 24);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_addr_in_handle_namespace() {
+        ip_net_ns_add("vnetns32".to_string()).unwrap();
+        thread_net_ns_block_on("vnetns32".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "nsaddr0", "nsaddr1", false).await
+        })
+        .unwrap();
+        // a handle on the namespace, used from a thread outside of it
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns32".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        let added = IPAddr::new(AddrAction::Add, "10.15.0.1".parse().unwrap(), 24, "nsaddr0")
+            .execute(&mut handle)
+            .await;
+        let addresses = get_addresses(&handle, "nsaddr0").await;
+        // DAD does not start before the link is up, the address is polled until the timeout
+        let timed_out = addr_add_wait_dad(
+            &mut handle,
+            "nsaddr0",
+            "2001:db8::3/64".parse().unwrap(),
+            Duration::from_millis(300),
+        )
+        .await;
+        ip_net_ns_del("vnetns32".to_string(), false).unwrap();
+
+        added.unwrap();
+        assert!(has_address(&addresses.unwrap(), &[10, 15, 0, 1]));
+        assert!(timed_out
+            .unwrap_err()
+            .to_string()
+            .contains("still tentative"));
+    }
+}
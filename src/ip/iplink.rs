@@ -1,172 +1,2735 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use enum_dispatch::enum_dispatch;
-use futures::stream::{StreamExt, TryStreamExt};
-use netlink_packet_route::rtnl::link::nlas::Nla;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::future::ready;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
+use ipnet::IpNet;
+use netlink_packet_route::nlas::{Nla as NlaTrait, NlasIterator};
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoKind, Nla, State, Stats64, Stats64Buffer};
+use netlink_packet_route::traits::{Emitable, Parseable, ParseableParametrized};
 use netlink_packet_route::{
-    LinkMessage, NetlinkMessage, NetlinkPayload, RtnlMessage, IFF_UP, NLM_F_ACK, NLM_F_CREATE,
-    NLM_F_EXCL, NLM_F_REQUEST,
+    LinkHeader, LinkMessage, LinkMessageBuffer, NetlinkBuffer, NetlinkMessage, NetlinkPayload,
+    RtnlMessage, IFF_ALLMULTI, IFF_NOARP, IFF_PROMISC, IFF_UP, IFLA_INFO_DATA, IFLA_INFO_KIND,
+    IFLA_LINKINFO, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL, NLM_F_REQUEST, RTM_GETSTATS,
+    RTM_NEWLINK, RTM_NEWSTATS,
 };
+use netlink_sys::protocols::NETLINK_ROUTE;
+use netlink_sys::{AsyncSocket, Socket, SocketAddr};
+use nix::errno::Errno;
 use nix::fcntl::OFlag;
+use nix::sys::socket::{setsockopt, sockopt};
 use nix::sys::stat::Mode;
+use nix::unistd::close;
+use rtnetlink::constants::RTMGRP_LINK;
+use rtnetlink::proto::Connection;
 use rtnetlink::{new_connection, Handle, NETNS_PATH};
+use tokio::time::{sleep, Instant};
 
+use crate::error::{undone, IprouteError};
+use crate::ip::bareudp::BareUdp;
+use crate::ip::bond::Bond;
 use crate::ip::bridge::Bridge;
+use crate::ip::dummy::Dummy;
+use crate::ip::ethtool::{set_link_settings, Duplex};
+use crate::ip::geneve::Geneve;
+use crate::ip::gre::{Gre, GreTap, Ip6Gre, Ip6GreTap};
+use crate::ip::ip6tnl::Ip6Tnl;
+use crate::ip::ipaddr::{AddrAction, IPAddr};
+use crate::ip::ipnetns::{ip_net_ns_list, thread_net_ns_exec};
+use crate::ip::ipvlan::IpVlan;
+use crate::ip::macvlan::MacVlan;
+use crate::ip::macvtap::MacVtap;
+use crate::ip::sit::Sit;
+use crate::ip::tuntap::TunTap;
 use crate::ip::veth::Veth;
+use crate::ip::vlan::Vlan;
+use crate::ip::vrf::Vrf;
+use crate::ip::vxlan::Vxlan;
+use crate::ip::wireguard::{wg_set_device, WireGuard};
 
 pub fn get_link_name(name: &str) -> Result<LinkMessage> {
     let (connection, handle, _) = new_connection()?;
     tokio::spawn(connection);
 
+    futures::executor::block_on(get_link(&handle, name))
+}
+
+/// The inverse of get_link_name, e.g. for the index of a netlink notification.
+/// Like get_link_name it blocks on a connection of its own, so it needs a multi-threaded runtime.
+pub fn get_link_by_index(index: u32) -> Result<LinkMessage> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+
     futures::executor::block_on(async {
-        let mut links = handle.link().get().match_name(name.parse()?).execute();
-        if let Some(link) = links.try_next().await? {
-            Ok(link)
-        } else {
-            Err(anyhow::anyhow!("no link named {}", name))
+        let mut links = handle.link().get().match_index(index).execute();
+        match links.try_next().await? {
+            Some(link) => Ok(link),
+            None => Err(anyhow!("no link with index {}", index)),
         }
     })
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub struct IPLink {
-    pub action: Action,
-    pub name: String,
-    pub options: Vec<Opt>,
-    pub link_type: Option<LinkTypeEnum>,
+pub async fn get_link(handle: &Handle, name: &str) -> Result<LinkMessage> {
+    let mut links = handle.link().get().match_name(name.parse()?).execute();
+    if let Some(link) = links.try_next().await? {
+        Ok(link)
+    } else {
+        Err(anyhow::anyhow!("no link named {}", name))
+    }
 }
 
-pub fn name(name: &str, message: &mut LinkMessage) {
-    message.nlas.push(Nla::IfName(String::from(name)))
+/// ip link show name succeeding, without failing when there is no such link.
+/// The link is asked through handle, so that it is looked up in the network namespace of the
+/// handle. Errors other than ENODEV, e.g. for names the kernel rejects, are returned.
+pub async fn link_exists(handle: &Handle, name: &str) -> Result<bool> {
+    match request_link_index(handle, name).await {
+        Ok(_) => Ok(true),
+        Err(e) if netlink_errno(&e) == Some(Errno::ENODEV) => Ok(false),
+        Err(e) => Err(e),
+    }
 }
 
-impl IPLink {
-    pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
-        let mut message = LinkMessage::default();
-        name(&self.name, &mut message);
-        options(self.options.clone(), &mut message)?;
+/// link_exists without a handle.
+/// The link is read as get_link_without_info_data does, and thus looked up in the network
+/// namespace of the calling thread.
+pub fn link_exists_checked(name: &str) -> Result<bool> {
+    match get_link_message_raw(name) {
+        Ok(_) => Ok(true),
+        Err(e) if e.downcast_ref::<Errno>() == Some(&Errno::ENODEV) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
 
-        self.link_type
-            .as_ref()
-            .map_or(Ok(()), |link_type| link_type.link_type(&mut message))?;
+/// Whether the link name exists, see link_exists_checked.
+/// Any failure counts as a missing link: this does not tell "does not exist" from "permission
+/// denied" or an invalid name.
+pub fn link_exists_sync(name: &str) -> bool {
+    link_exists_checked(name).unwrap_or(false)
+}
 
-        let mut req = match self.action {
-            Action::Delete => NetlinkMessage::from(RtnlMessage::DelLink(message)),
-            Action::Add | Action::Set => NetlinkMessage::from(RtnlMessage::NewLink(message)),
-        };
-        if self.action == Action::Add {
-            req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE
-        } else {
-            req.header.flags = NLM_F_REQUEST | NLM_F_ACK
-        }
+/// link_exists_sync for async callers, the link is looked up in the network namespace of handle.
+/// Like link_exists_sync, any failure counts as a missing link.
+pub async fn link_exists_async(handle: &Handle, name: &str) -> bool {
+    link_exists(handle, name).await.unwrap_or(false)
+}
+
+/// The index of name, asked through handle so that it is resolved in the network namespace of
+/// the handle rather than of the calling thread.
+/// netlink_packet_route 0.11 cannot parse the answer for some kinds (see get_link_message_raw):
+/// the kernel acknowledges the request but the link never comes through. Nothing tells which
+/// network namespace the handle belongs to, so such links are an error rather than being looked
+/// up elsewhere; get_link_index finds them in the network namespace of the calling thread.
+pub async fn resolve_link_index(handle: &Handle, name: &str) -> Result<u32> {
+    request_link_index(handle, name).await?.ok_or_else(|| {
+        anyhow!(
+            "cannot resolve link {}: its kind cannot be parsed from a handle",
+            name
+        )
+    })
+}
+
+/// The index of name resolved through handle, checked to be the same link in the network
+/// namespace of the calling thread. For the requests that cannot go through an rtnetlink handle
+/// (ioctls, generic netlink) and so are sent from the calling thread.
+pub async fn resolve_local_link_index(handle: &Handle, name: &str) -> Result<u32> {
+    let index = resolve_link_index(handle, name).await?;
+    if get_link_index(name).ok() != Some(index) {
+        return Err(IprouteError::InvalidArgument(format!(
+            "link {} of the handle is not in the network namespace of the calling thread",
+            name
+        ))
+        .into());
+    }
+    Ok(index)
+}
+
+/// The index of name asked through handle, None when the link exists but its answer was dropped.
+async fn request_link_index(handle: &Handle, name: &str) -> Result<Option<u32>> {
+    let mut message = LinkMessage::default();
+    self::name(name, &mut message);
+    let mut req = NetlinkMessage::from(RtnlMessage::GetLink(message));
+    // the ack ends the request even when the answer is dropped
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
 
-        let mut response = handle.request(req)?;
-        while let Some(message) = response.next().await {
-            if let NetlinkPayload::Error(err) = message.payload {
-                return Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err)));
+    let mut response = handle.clone().request(req)?;
+    while let Some(message) = response.next().await {
+        match message.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewLink(link)) => {
+                return Ok(Some(link.header.index))
             }
+            NetlinkPayload::Error(err) => {
+                return Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err))
+                    .context(format!("no link named {}", name)))
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// if_nametoindex(name)
+/// Unlike get_link_name this needs no runtime, and it also works for the links
+/// netlink_packet_route cannot parse (see get_link_info_raw).
+pub fn get_link_index(name: &str) -> Result<u32> {
+    nix::net::if_::if_nametoindex(name).map_err(|e| anyhow!("no link named {}: {}", name, e))
+}
+
+/// if_indextoname(index), the reverse of get_link_index.
+pub fn get_link_name_by_index(index: u32) -> Result<String> {
+    let mut name = [0u8; nix::libc::IF_NAMESIZE];
+    let res =
+        unsafe { nix::libc::if_indextoname(index, name.as_mut_ptr() as *mut nix::libc::c_char) };
+    if res.is_null() {
+        return Err(anyhow!("no link with index {}: {}", index, Errno::last()));
+    }
+    let len = name
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(name.len());
+    Ok(String::from_utf8_lossy(&name[..len]).to_string())
+}
+
+/// The name of the master of slave, e.g. the bridge it is a port of. None when it has no master.
+/// The slave is read as get_link_without_info_data does, so that links of any kind are supported,
+/// in the network namespace of the calling thread.
+pub fn get_master(slave: &str) -> Result<Option<String>> {
+    let link = get_link_without_info_data(slave)?;
+    link.nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Master(index) => Some(*index),
+            _ => None,
+        })
+        .map(get_link_name_by_index)
+        .transpose()
+}
+
+/// The errno of a netlink error answered by the kernel, None for any other error.
+fn netlink_errno(e: &anyhow::Error) -> Option<Errno> {
+    match e.downcast_ref::<rtnetlink::Error>() {
+        Some(rtnetlink::Error::NetlinkError(msg)) => Some(Errno::from_i32(-msg.code)),
+        _ => None,
+    }
+}
+
+/// SO_RCVBUF sizes of the fresh sockets a dump is retried on after ENOMEM, see
+/// retry_dump_on_enomem. The first attempt runs on the caller's socket with the default of
+/// net.core.rmem_default (usually 208 KiB); each retry quadruples the buffer, from 1 MiB up to
+/// 16 MiB. The kernel caps the size at net.core.rmem_max, so the later steps may not all take
+/// effect.
+pub const DUMP_RCVBUF_SIZES: [usize; 3] = [1 << 20, 4 << 20, 16 << 20];
+
+/// Opens a connection whose socket receive buffer is the given size, see DumpHandle.
+pub type Connect = dyn Fn(usize) -> Result<Handle> + Send + Sync;
+
+/// The handle a dump runs on, and how to open the larger sockets it is retried on after ENOMEM.
+/// A bare &Handle converts into one without connect, whose dumps are tried once. connect must
+/// open its connections in the network namespace of handle: new_connection_with_rcvbuf does
+/// when handle belongs to the namespace of the calling thread.
+/// get_routes(DumpHandle { handle: &handle, connect: Some(&new_connection_with_rcvbuf) }, ..)
+#[derive(Clone, Copy)]
+pub struct DumpHandle<'a> {
+    pub handle: &'a Handle,
+    pub connect: Option<&'a Connect>,
+}
+
+impl<'a> From<&'a Handle> for DumpHandle<'a> {
+    fn from(handle: &'a Handle) -> Self {
+        DumpHandle {
+            handle,
+            connect: None,
+        }
+    }
+}
+
+impl DumpHandle<'_> {
+    /// The receive buffer sizes the dump may be retried with.
+    pub fn rcvbufs(&self) -> &'static [usize] {
+        match self.connect {
+            Some(_) => &DUMP_RCVBUF_SIZES,
+            None => &[],
+        }
+    }
+
+    /// The handle to run a retry_dump_on_enomem attempt on: handle itself for the first one, a
+    /// new connection with a larger receive buffer for the others.
+    pub fn attempt(&self, rcvbuf: Option<usize>) -> Result<Handle> {
+        match (rcvbuf, self.connect) {
+            (Some(rcvbuf), Some(connect)) => connect(rcvbuf),
+            _ => Ok(self.handle.clone()),
+        }
+    }
+}
+
+/// A handle on a new netlink connection whose socket receive buffer is rcvbuf bytes, in the
+/// network namespace of the calling thread.
+pub fn new_connection_with_rcvbuf(rcvbuf: usize) -> Result<Handle> {
+    let (mut connection, handle, _) = new_connection()?;
+    setsockopt(
+        connection.socket_mut().socket_ref().as_raw_fd(),
+        sockopt::RcvBuf,
+        &rcvbuf,
+    )?;
+    tokio::spawn(connection);
+    Ok(handle)
+}
+
+/// Runs dump with None, then with each of rcvbufs for as long as it fails with ENOMEM, which the
+/// kernel returns when a large dump does not fit the socket. The error of the last attempt is
+/// returned once the sizes run out.
+pub async fn retry_dump_on_enomem<T, F, Fut>(rcvbufs: &[usize], mut dump: F) -> Result<T>
+where
+    F: FnMut(Option<usize>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut res = dump(None).await;
+    for rcvbuf in rcvbufs {
+        match &res {
+            Err(e) if netlink_errno(e) == Some(Errno::ENOMEM) => res = dump(Some(*rcvbuf)).await,
+            _ => break,
         }
+    }
+    res
+}
+
+/// ip link show master index
+pub async fn get_links_by_master(handle: &Handle, master: u32) -> Result<Vec<LinkMessage>> {
+    let links: Vec<LinkMessage> = handle.link().get().execute().try_collect().await?;
+    Ok(links
+        .into_iter()
+        .filter(|link| link.nlas.contains(&Nla::Master(master)))
+        .collect())
+}
+
+/// ip link show master master, the ports of a bridge or the slaves of a bond.
+pub async fn get_slaves(handle: &Handle, master: &str) -> Result<Vec<LinkMessage>> {
+    get_links_by_master(handle, get_link_index(master)?).await
+}
+
+/// Index/name lookups served from a link dump instead of one request each.
+/// The cache does not follow the kernel: populate it again or invalidate entries after
+/// links are created, renamed or deleted.
+#[derive(Debug, Default, Clone)]
+pub struct IfIndexCache {
+    names: HashMap<u32, String>,
+    indexes: HashMap<String, u32>,
+}
+
+impl IfIndexCache {
+    pub fn get_name(&self, index: u32) -> Option<&str> {
+        self.names.get(&index).map(|name| name.as_str())
+    }
+
+    pub fn get_index(&self, name: &str) -> Option<u32> {
+        self.indexes.get(name).copied()
+    }
 
+    /// Replace the cache content with a full link dump.
+    pub async fn populate(&mut self, handle: &Handle) -> Result<()> {
+        self.names.clear();
+        self.indexes.clear();
+        let mut links = handle.link().get().execute();
+        while let Some(link) = links.try_next().await? {
+            let index = link.header.index;
+            if let Some(name) = link.nlas.iter().find_map(|nla| match nla {
+                Nla::IfName(name) => Some(name.clone()),
+                _ => None,
+            }) {
+                self.names.insert(index, name.clone());
+                self.indexes.insert(name, index);
+            }
+        }
         Ok(())
     }
+
+    pub fn invalidate(&mut self, name: &str) {
+        if let Some(index) = self.indexes.remove(name) {
+            self.names.remove(&index);
+        }
+    }
+}
+
+/// ip -all netns exec ip link
+pub fn get_links_all_namespaces() -> Result<HashMap<String, Vec<LinkMessage>>> {
+    let mut links = HashMap::new();
+    for ns_name in ip_net_ns_list()? {
+        let ns_links = match thread_net_ns_exec(ns_name.clone(), get_links_all_raw).join() {
+            Ok(links) => links?,
+            Err(_) => return Err(anyhow!("thread in network namespace {} panicked", ns_name)),
+        };
+        links.insert(ns_name, ns_links);
+    }
+    Ok(links)
 }
 
+const NLMSG_ERROR: u16 = nix::libc::NLMSG_ERROR as u16;
+
+/// IFLA_INFO_KIND and the unparsed IFLA_INFO_DATA of a link.
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub enum Action {
-    Add,
-    Delete,
-    Set,
+pub struct RawLinkInfo {
+    pub kind: String,
+    pub data: Vec<u8>,
 }
 
-#[enum_dispatch]
-pub trait LinkTypeTrait {
-    fn link_type(&self, message: &mut LinkMessage) -> Result<()>;
+/// Sends the serialized request over a plain netlink socket and returns the payload of the
+/// reply_type answer. what names the requested object in errors.
+fn request_raw(request: &[u8], reply_type: u16, what: &str) -> Result<Vec<u8>> {
+    request_raw_protocol(NETLINK_ROUTE, request, reply_type)
+        .map_err(|e| e.context(format!("cannot get {}", what)))
 }
 
-#[enum_dispatch(LinkTypeTrait)]
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub enum LinkTypeEnum {
-    Veth(Veth),
-    Bridge(Bridge),
+/// Sends the serialized request over a plain netlink socket of protocol, in the network namespace
+/// of the calling thread, and returns the payload of the reply_type answer, empty when the kernel
+/// only acknowledged the request. A NLMSG_ERROR answer is returned as its Errno.
+pub(crate) fn request_raw_protocol(
+    protocol: isize,
+    request: &[u8],
+    reply_type: u16,
+) -> Result<Vec<u8>> {
+    let socket = Socket::new(protocol)?;
+    socket.connect(&SocketAddr::new(0, 0))?;
+    socket.send(request, 0)?;
+    let (response, _) = socket.recv_from_full()?;
+
+    let buffer = NetlinkBuffer::new_checked(response.as_slice())?;
+    match buffer.message_type() {
+        NLMSG_ERROR => {
+            let mut code = [0; 4];
+            code.copy_from_slice(&buffer.payload()[..4]);
+            match i32::from_ne_bytes(code) {
+                0 => Ok(vec![]),
+                code => Err(anyhow::Error::new(Errno::from_i32(-code))),
+            }
+        }
+        message_type if message_type == reply_type => Ok(buffer.payload().to_vec()),
+        message_type => Err(anyhow!("unexpected netlink message type {}", message_type)),
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub enum Opt {
-    Up,
+const NLMSG_DONE: u16 = nix::libc::NLMSG_DONE as u16;
+
+/// Sends the serialized dump request over a plain netlink socket in the network namespace of the
+/// calling thread and returns the payloads of its reply_type answers. The socket is reopened with
+/// each of DUMP_RCVBUF_SIZES for as long as the dump fails with ENOMEM, as in
+/// retry_dump_on_enomem.
+fn dump_raw(request: &[u8], reply_type: u16) -> Result<Vec<Vec<u8>>> {
+    let mut res = dump_raw_once(request, reply_type, None);
+    for rcvbuf in DUMP_RCVBUF_SIZES {
+        match &res {
+            Err(e) if e.downcast_ref::<Errno>() == Some(&Errno::ENOMEM) => {
+                res = dump_raw_once(request, reply_type, Some(rcvbuf))
+            }
+            _ => break,
+        }
+    }
+    res
+}
+
+fn dump_raw_once(request: &[u8], reply_type: u16, rcvbuf: Option<usize>) -> Result<Vec<Vec<u8>>> {
+    let socket = Socket::new(NETLINK_ROUTE)?;
+    if let Some(rcvbuf) = rcvbuf {
+        setsockopt(socket.as_raw_fd(), sockopt::RcvBuf, &rcvbuf)?;
+    }
+    socket.connect(&SocketAddr::new(0, 0))?;
+    socket.send(request, 0)?;
+
+    let mut payloads = vec![];
+    loop {
+        let (response, _) = socket.recv_from_full()?;
+        let mut offset = 0;
+        while offset < response.len() {
+            let buffer = NetlinkBuffer::new_checked(&response[offset..])?;
+            match buffer.message_type() {
+                NLMSG_DONE => return Ok(payloads),
+                NLMSG_ERROR => {
+                    let mut code = [0; 4];
+                    code.copy_from_slice(&buffer.payload()[..4]);
+                    let code = i32::from_ne_bytes(code);
+                    return Err(anyhow::Error::new(Errno::from_i32(-code)));
+                }
+                message_type if message_type == reply_type => {
+                    payloads.push(buffer.payload().to_vec())
+                }
+                _ => {}
+            }
+            // messages are aligned to 4 bytes
+            offset += (buffer.length() as usize + 3) & !3;
+        }
+    }
+}
+
+/// The RTM_NEWLINK message the kernel answers for name, still serialized.
+/// netlink_packet_route 0.11 rejects the info data recent kernels send for some kinds (e.g. vxlan
+/// and bridge), and netlink_proto then drops the whole message, so the link is read over a plain
+/// netlink socket instead of a Handle.
+fn get_link_message_raw(name: &str) -> Result<Vec<u8>> {
+    let mut message = LinkMessage::default();
+    self::name(name, &mut message);
+    let mut req = NetlinkMessage::from(RtnlMessage::GetLink(message));
+    req.header.flags = NLM_F_REQUEST;
+    req.header.sequence_number = 1;
+    req.finalize();
+    let mut buffer = vec![0; req.buffer_len()];
+    req.serialize(&mut buffer);
+
+    request_raw(&buffer, RTM_NEWLINK, &format!("link {}", name))
+}
+
+const IFLA_STATS_LINK_64: u16 = 1;
+/// sizeof(struct if_stats_msg)
+const IF_STATS_MSG_LEN: usize = 12;
+
+/// ip -s link show name, through RTM_GETSTATS (Linux 4.8+). The kernel sums its per-CPU counters
+/// before answering, neither RTM_GETSTATS nor any other rtnetlink request reports them per CPU.
+/// netlink_packet_route 0.11 has no RTM_GETSTATS message, so the request is sent over a plain
+/// netlink socket in the network namespace of the calling thread, after checking that name is
+/// the link of handle there.
+pub async fn get_link_stats64(handle: &Handle, name: &str) -> Result<Stats64> {
+    let index = resolve_local_link_index(handle, name).await?;
+    let mut request = vec![0; 16 + IF_STATS_MSG_LEN];
+    let mut buffer = NetlinkBuffer::new(&mut request);
+    buffer.set_length((16 + IF_STATS_MSG_LEN) as u32);
+    buffer.set_message_type(RTM_GETSTATS);
+    buffer.set_flags(NLM_F_REQUEST);
+    buffer.set_sequence_number(1);
+    let header = buffer.payload_mut();
+    // family, padding, ifindex, filter_mask
+    header[4..8].copy_from_slice(&index.to_ne_bytes());
+    header[8..12].copy_from_slice(&(1u32 << (IFLA_STATS_LINK_64 - 1)).to_ne_bytes());
+
+    let payload = request_raw(&request, RTM_NEWSTATS, &format!("stats of link {}", name))?;
+    if payload.len() < IF_STATS_MSG_LEN {
+        return Err(anyhow!("truncated stats of link {}", name));
+    }
+    for nla in NlasIterator::new(&payload[IF_STATS_MSG_LEN..]) {
+        let nla = nla?;
+        if nla.kind() == IFLA_STATS_LINK_64 {
+            return Ok(Stats64::parse(&Stats64Buffer::new_checked(nla.value())?)?);
+        }
+    }
+    Err(anyhow!("no IFLA_STATS_LINK_64 for link {}", name))
+}
+
+/// The counters of ip -s link show, see get_link_stats.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct LinkStats64 {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+}
+
+impl From<&Stats64> for LinkStats64 {
+    fn from(stats: &Stats64) -> Self {
+        LinkStats64 {
+            rx_packets: stats.rx_packets,
+            tx_packets: stats.tx_packets,
+            rx_bytes: stats.rx_bytes,
+            tx_bytes: stats.tx_bytes,
+            rx_errors: stats.rx_errors,
+            tx_errors: stats.tx_errors,
+            rx_dropped: stats.rx_dropped,
+            tx_dropped: stats.tx_dropped,
+        }
+    }
+}
+
+/// ip -s link show name, from the IFLA_STATS64 of the link (Linux 2.6.35+).
+pub async fn get_link_stats(handle: &Handle, name: &str) -> Result<LinkStats64> {
+    get_link(handle, name)
+        .await?
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Stats64(bytes) => Some(bytes),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no IFLA_STATS64 for link {}", name))
+        .and_then(|bytes| {
+            let stats = Stats64::parse(&Stats64Buffer::new_checked(bytes.as_slice())?)?;
+            Ok(LinkStats64::from(&stats))
+        })
+}
+
+/// IF_OPER_*, the RFC 2863 operational state of a link.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum OperState {
+    /// Also used by drivers that do not track it, e.g. loopback and tun.
+    Unknown,
+    NotPresent,
     Down,
-    Master(String),
-    NetNS(String),
+    LowerLayerDown,
+    Testing,
+    Dormant,
+    Up,
 }
 
-impl Opt {
-    pub fn opt(&self, message: &mut LinkMessage) -> Result<()> {
-        match self {
-            Opt::Up => {
-                message.header.change_mask |= IFF_UP;
-                message.header.flags |= IFF_UP;
+/// ip link show name | grep state
+/// States the kernel may add later are reported as Unknown.
+pub async fn get_link_operstate(handle: &Handle, name: &str) -> Result<OperState> {
+    let state = get_link(handle, name)
+        .await?
+        .nlas
+        .into_iter()
+        .find_map(|nla| match nla {
+            Nla::OperState(state) => Some(state),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no IFLA_OPERSTATE for link {}", name))?;
+    Ok(match state {
+        State::NotPresent => OperState::NotPresent,
+        State::Down => OperState::Down,
+        State::LowerLayerDown => OperState::LowerLayerDown,
+        State::Testing => OperState::Testing,
+        State::Dormant => OperState::Dormant,
+        State::Up => OperState::Up,
+        State::Unknown | State::Other(_) => OperState::Unknown,
+    })
+}
+
+/// How often wait_for_link_up looks at the link again.
+const OPERSTATE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits until the operational state of name is Up, e.g. once a veth peer is up too or a cable is
+/// plugged in. Setting the link up administratively is left to the caller.
+/// Links whose driver leaves the state Unknown never get there, and fail after timeout.
+pub async fn wait_for_link_up(handle: &Handle, name: &str, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let state = get_link_operstate(handle, name).await?;
+        if state == OperState::Up {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "link {} is still {:?} after {:?}",
+                name,
+                state,
+                timeout
+            ));
+        }
+        sleep(OPERSTATE_POLL_INTERVAL).await;
+    }
+}
+
+/// Sends req, which asks for an ack (NLM_F_ACK), and returns the error the kernel answers with,
+/// if any.
+pub async fn request_ack(handle: &mut Handle, req: NetlinkMessage<RtnlMessage>) -> Result<()> {
+    let mut response = handle.request(req)?;
+    while let Some(message) = response.next().await {
+        if let NetlinkPayload::Error(err) = message.payload {
+            return Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err)));
+        }
+    }
+    Ok(())
+}
+
+/// The messages of a connection the kernel sends unasked, i.e. the notifications of its groups.
+pub type Notifications = UnboundedReceiver<(NetlinkMessage<RtnlMessage>, SocketAddr)>;
+
+/// A connection as new_connection returns it, e.g. opened in thread_net_ns_exec for the network
+/// namespace of another thread.
+pub type NetlinkConnection = (Connection<RtnlMessage>, Handle, Notifications);
+
+/// Joins connection to the RTMGRP_* groups and spawns it on the tokio runtime: the notifications
+/// of the network namespace it was opened in then come through the receiver until it is dropped.
+pub fn subscribe(connection: NetlinkConnection, groups: u32) -> Result<(Handle, Notifications)> {
+    let (mut connection, handle, messages) = connection;
+    connection
+        .socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, groups))?;
+    tokio::spawn(connection);
+    Ok((handle, messages))
+}
+
+/// A notification of ip monitor link.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum LinkEvent {
+    Added(LinkMessage),
+    Deleted(LinkMessage),
+    /// Its flags, state, name, addresses, master... changed.
+    Changed(LinkMessage),
+}
+
+/// ip monitor link, received as subscribe(connection, RTMGRP_LINK) until the stream is dropped,
+/// in the network namespace connection was opened in.
+/// The kernel sends RTM_NEWLINK for new and changed links alike, so the links are dumped with
+/// get_links_all on the handle of connection once subscribed, and an index not seen before makes
+/// an Added event. A link created while the dump runs is thus reported as Changed.
+/// Links netlink_proto cannot parse, e.g. some vxlan or bridge links, are missing from both the
+/// dump and the notifications.
+pub async fn link_event_stream(
+    connection: NetlinkConnection,
+) -> Result<impl Stream<Item = Result<LinkEvent>> + Unpin> {
+    let (handle, messages) = subscribe(connection, RTMGRP_LINK)?;
+    let mut known: HashSet<u32> = get_links_all(&handle)
+        .await?
+        .iter()
+        .map(|link| link.header.index)
+        .collect();
+    Ok(messages.filter_map(move |(message, _)| {
+        let event = match message.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewLink(link)) => {
+                if known.insert(link.header.index) {
+                    Some(Ok(LinkEvent::Added(link)))
+                } else {
+                    Some(Ok(LinkEvent::Changed(link)))
+                }
             }
-            Opt::Down => {
-                message.header.change_mask |= IFF_UP;
-                message.header.flags &= !IFF_UP;
+            NetlinkPayload::InnerMessage(RtnlMessage::DelLink(link)) => {
+                known.remove(&link.header.index);
+                Some(Ok(LinkEvent::Deleted(link)))
             }
-            Opt::Master(master_name) => {
-                let link = get_link_name(master_name)?;
-                message.nlas.push(Nla::Master(link.header.index));
+            NetlinkPayload::Error(err) => {
+                Some(Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err))))
             }
-            Opt::NetNS(netns_name) => {
-                let mut path_string = String::from(NETNS_PATH);
-                path_string.push_str(netns_name.as_str());
-                let path = Path::new(&path_string);
-                // TODO : ERR HANDLING
-                let fd = nix::fcntl::open(path, OFlag::O_RDONLY, Mode::empty())?;
-                message.nlas.push(Nla::NetNsFd(fd));
+            _ => None,
+        };
+        ready(event)
+    }))
+}
+
+/// ip -d link show name, with the link info left unparsed.
+pub fn get_link_info_raw(name: &str) -> Result<RawLinkInfo> {
+    let payload = get_link_message_raw(name)?;
+    let mut kind = String::new();
+    let mut data = vec![];
+    for nla in LinkMessageBuffer::new(payload.as_slice()).nlas() {
+        let nla = nla?;
+        if nla.kind() != IFLA_LINKINFO {
+            continue;
+        }
+        for info in NlasIterator::new(nla.value()) {
+            let info = info?;
+            match info.kind() {
+                IFLA_INFO_KIND => {
+                    kind = String::from_utf8_lossy(info.value())
+                        .trim_end_matches('\0')
+                        .to_string()
+                }
+                IFLA_INFO_DATA => data = info.value().to_vec(),
+                _ => {}
             }
         }
-        Ok(())
     }
+    Ok(RawLinkInfo { kind, data })
 }
 
-pub fn options(opts: Vec<Opt>, message: &mut LinkMessage) -> Result<()> {
-    for opt in opts {
-        opt.opt(message)?;
+/// ip link show name, for any kind of link.
+/// Unlike get_link, the IFLA_LINKINFO of the result only holds the kind: the rest is dropped
+/// rather than failing the parse (see get_link_message_raw). Use get_link_info_raw for it.
+pub fn get_link_without_info_data(name: &str) -> Result<LinkMessage> {
+    parse_link_without_info_data(&get_link_message_raw(name)?)
+}
+
+/// Parses a serialized RTM_NEWLINK message as get_link_without_info_data does.
+fn parse_link_without_info_data(payload: &[u8]) -> Result<LinkMessage> {
+    let buffer = LinkMessageBuffer::new(payload);
+    let mut link = LinkMessage {
+        header: LinkHeader::parse(&buffer)?,
+        nlas: vec![],
+    };
+    for nla in buffer.nlas() {
+        let nla = nla?;
+        if nla.kind() == IFLA_LINKINFO {
+            let mut infos = vec![];
+            for info in NlasIterator::new(nla.value()) {
+                let info = info?;
+                if info.kind() == IFLA_INFO_KIND {
+                    infos.push(Info::Kind(InfoKind::parse(&info)?));
+                }
+            }
+            link.nlas.push(Nla::Info(infos));
+        } else {
+            link.nlas.push(Nla::parse_with_param(
+                &nla,
+                link.header.interface_family as u16,
+            )?);
+        }
     }
-    Ok(())
+    Ok(link)
 }
 
-#[cfg(test)]
-mod test {
-    use rtnetlink::new_connection;
+/// IFLA_INFO_KIND of a link, None for hardware interfaces and loopback.
+pub fn link_kind(link: &LinkMessage) -> Option<&InfoKind> {
+    link.nlas.iter().find_map(|nla| match nla {
+        Nla::Info(infos) => infos.iter().find_map(|info| match info {
+            Info::Kind(kind) => Some(kind),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
 
-    use crate::ip::iplink::{Action, IPLink, LinkTypeEnum};
-    use crate::ip::veth::Veth;
+/// ip -d link, dumped through handle in its network namespace. Like get_routes, the dump is
+/// retried on larger sockets after ENOMEM when handle comes with a connect.
+/// Links whose info data netlink_packet_route cannot parse (e.g. bridges and vxlans) are dropped
+/// by netlink_proto and thus missing from the result, get_links_all_raw keeps them.
+pub async fn get_links_all<'a>(handle: impl Into<DumpHandle<'a>>) -> Result<Vec<LinkMessage>> {
+    let handle = handle.into();
+    retry_dump_on_enomem(handle.rcvbufs(), |rcvbuf| async move {
+        let links_exec = handle.attempt(rcvbuf)?.link().get().execute();
+        let links: Vec<LinkMessage> = links_exec.try_collect().await?;
+        Ok(links)
+    })
+    .await
+}
 
-    #[tokio::test]
-    async fn test_veth() {
-        let (connection, mut handle, _) = new_connection().unwrap();
-        tokio::spawn(connection);
+/// ip -d link show type kind, with kind as kind_name returns it, e.g. "veth". See get_links_all.
+pub async fn get_links_by_kind<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    kind: &str,
+) -> Result<Vec<LinkMessage>> {
+    Ok(filter_links_by_kind(get_links_all(handle).await?, kind))
+}
 
-        IPLink {
+/// get_links_all over a plain netlink socket in the network namespace of the calling thread.
+/// As with get_link_without_info_data, the IFLA_LINKINFO of each link only holds the kind, so
+/// that links whose info data netlink_packet_route cannot parse (e.g. bridges) are not lost.
+pub fn get_links_all_raw() -> Result<Vec<LinkMessage>> {
+    let mut req = NetlinkMessage::from(RtnlMessage::GetLink(LinkMessage::default()));
+    req.header.flags = NLM_F_REQUEST | NLM_F_DUMP;
+    req.header.sequence_number = 1;
+    req.finalize();
+    let mut buffer = vec![0; req.buffer_len()];
+    req.serialize(&mut buffer);
+
+    dump_raw(&buffer, RTM_NEWLINK)
+        .map_err(|e| e.context("cannot dump links"))?
+        .iter()
+        .map(|payload| parse_link_without_info_data(payload))
+        .collect()
+}
+
+/// get_links_by_kind over a plain netlink socket, see get_links_all_raw.
+pub fn get_links_by_kind_raw(kind: &str) -> Result<Vec<LinkMessage>> {
+    Ok(filter_links_by_kind(get_links_all_raw()?, kind))
+}
+
+fn filter_links_by_kind(links: Vec<LinkMessage>, kind: &str) -> Vec<LinkMessage> {
+    links
+        .into_iter()
+        .filter(|link| link_kind(link).map(kind_name).as_deref() == Some(kind))
+        .collect()
+}
+
+/// ip -d link, grouped by kind. Links without a kind are grouped under "ethernet". See
+/// get_links_all.
+pub async fn get_links_by_all_types<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+) -> Result<HashMap<String, Vec<LinkMessage>>> {
+    Ok(group_links_by_type(get_links_all(handle).await?))
+}
+
+/// get_links_by_all_types over a plain netlink socket, see get_links_all_raw.
+pub fn get_links_by_all_types_raw() -> Result<HashMap<String, Vec<LinkMessage>>> {
+    Ok(group_links_by_type(get_links_all_raw()?))
+}
+
+fn group_links_by_type(all: Vec<LinkMessage>) -> HashMap<String, Vec<LinkMessage>> {
+    let mut links: HashMap<String, Vec<LinkMessage>> = HashMap::new();
+    for link in all {
+        let kind = link_kind(&link)
+            .map(kind_name)
+            .unwrap_or_else(|| "ethernet".to_string());
+        links.entry(kind).or_default().push(link);
+    }
+    links
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPLink {
+    pub action: Action,
+    pub name: String,
+    pub options: Vec<Opt>,
+    pub link_type: Option<LinkTypeEnum>,
+}
+
+/// Builds an IPLink, adding the link unless another action is set:
+/// IPLinkBuilder::new("br0").link_type(Bridge::default()).opt(Opt::Up).build()
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPLinkBuilder {
+    action: Action,
+    name: String,
+    options: Vec<Opt>,
+    link_type: Option<LinkTypeEnum>,
+}
+
+impl IPLinkBuilder {
+    pub fn new(name: &str) -> IPLinkBuilder {
+        IPLinkBuilder {
             action: Action::Add,
-            name: "v0".to_string(),
+            name: name.to_string(),
             options: vec![],
-            link_type: Some(LinkTypeEnum::Veth(Veth {
-                peer_name: "v1".to_string(),
-                options: vec![],
-            })),
+            link_type: None,
         }
-        .execute(&mut handle)
-        .await
-        .unwrap();
+    }
+
+    pub fn action(mut self, action: Action) -> IPLinkBuilder {
+        self.action = action;
+        self
+    }
+
+    /// Appends opt, options are applied in the order they were given.
+    pub fn opt(mut self, opt: Opt) -> IPLinkBuilder {
+        self.options.push(opt);
+        self
+    }
+
+    pub fn link_type(mut self, link_type: impl Into<LinkTypeEnum>) -> IPLinkBuilder {
+        self.link_type = Some(link_type.into());
+        self
+    }
 
+    pub fn build(self) -> IPLink {
         IPLink {
-            action: Action::Delete,
-            name: "v0".to_string(),
-            options: vec![],
-            link_type: None,
+            action: self.action,
+            name: self.name,
+            options: self.options,
+            link_type: self.link_type,
+        }
+    }
+}
+
+impl From<IPLinkBuilder> for IPLink {
+    fn from(builder: IPLinkBuilder) -> Self {
+        builder.build()
+    }
+}
+
+pub fn name(name: &str, message: &mut LinkMessage) {
+    message.nlas.push(Nla::IfName(String::from(name)))
+}
+
+/// Serialize nlas for the link kinds whose IFLA_INFO_DATA is kept as raw bytes by netlink_packet_route.
+pub fn emit_nlas<T: NlaTrait>(nlas: &[T]) -> Vec<u8> {
+    let mut buffer = vec![0; nlas.buffer_len()];
+    nlas.emit(&mut buffer);
+    buffer
+}
+
+impl IPLink {
+    pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
+        if let (Action::Add, Some(LinkTypeEnum::Geneve(geneve))) = (&self.action, &self.link_type) {
+            geneve.check_reachability(handle).await?;
+        }
+        match (&self.action, &self.link_type) {
+            (Action::Add, Some(LinkTypeEnum::TunTap(tuntap))) => {
+                // the file is dropped right away, so a link without persist would be gone
+                // before execute returns
+                if !tuntap.persist {
+                    return Err(IprouteError::InvalidArgument(format!(
+                        "tun {} must persist to be added through IPLink, see TunTap::create",
+                        self.name
+                    ))
+                    .into());
+                }
+                tuntap.create(&self.name)?;
+                if !self.options.is_empty() {
+                    IPLink {
+                        action: Action::Set,
+                        name: self.name.clone(),
+                        options: self.options.clone(),
+                        link_type: None,
+                    }
+                    .request(handle)
+                    .await?;
+                }
+            }
+            _ => self.request(handle).await?,
+        }
+
+        let speed = self.options.iter().find_map(|opt| match opt {
+            Opt::Speed(speed) => Some(*speed),
+            _ => None,
+        });
+        let duplex = self.options.iter().find_map(|opt| match opt {
+            Opt::Duplex(duplex) => Some(*duplex),
+            _ => None,
+        });
+        if matches!(self.action, Action::Add | Action::Set) && (speed.is_some() || duplex.is_some())
+        {
+            // the ioctl is sent from the calling thread, so the link must be the one of handle
+            let res = match resolve_local_link_index(handle, &self.name).await {
+                Ok(_) => set_link_settings(&self.name, speed, duplex),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = res {
+                return Err(self.undo_add(handle, err).await);
+            }
+        }
+
+        if let (Action::Add, Some(LinkTypeEnum::WireGuard(wireguard))) =
+            (&self.action, &self.link_type)
+        {
+            if let Some(config) = wireguard.config() {
+                if let Err(err) = wg_set_device(handle, &self.name, config).await {
+                    // do not leave an unconfigured tunnel behind
+                    return Err(self.undo_add(handle, err).await);
+                }
+            }
+        }
+
+        if let (Action::Add, Some(LinkTypeEnum::Veth(veth))) = (&self.action, &self.link_type) {
+            if veth.auto_up {
+                for name in [&self.name, &veth.peer_name] {
+                    IPLink {
+                        action: Action::Set,
+                        name: name.clone(),
+                        options: vec![Opt::Up],
+                        link_type: None,
+                    }
+                    .request(handle)
+                    .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the link an Add created before err stopped it, and returns err, with the failure to
+    /// delete the link as context if any. Other actions return err as is.
+    async fn undo_add(&self, handle: &mut Handle, err: anyhow::Error) -> anyhow::Error {
+        if self.action != Action::Add {
+            return err;
+        }
+        let rollback = IPLink {
+            action: Action::Delete,
+            name: self.name.clone(),
+            options: vec![],
+            link_type: None,
+        }
+        .request(handle)
+        .await;
+        undone(err, rollback, &format!("the creation of {}", self.name))
+    }
+
+    async fn request(&self, handle: &mut Handle) -> Result<()> {
+        let mut message = LinkMessage::default();
+        name(&self.name, &mut message);
+        // speed and duplex are applied through ethtool by execute
+        let netlink_options = self
+            .options
+            .iter()
+            .filter(|opt| !matches!(opt, Opt::Speed(_) | Opt::Duplex(_)))
+            .cloned()
+            .collect();
+        let res = options(netlink_options, &mut message).and_then(|_| {
+            self.link_type
+                .as_ref()
+                .map_or(Ok(()), |link_type| link_type.link_type(&mut message))
+        });
+
+        // the namespace files opened for Opt::NetNS are only needed until the kernel has the
+        // request, an open one keeps ip_net_ns_del from unmounting the namespace
+        let fds: Vec<RawFd> = message
+            .nlas
+            .iter()
+            .filter_map(|nla| match nla {
+                Nla::NetNsFd(fd) => Some(*fd),
+                _ => None,
+            })
+            .collect();
+        let res = match res {
+            Ok(()) => self.send(handle, message).await,
+            Err(e) => Err(e),
+        };
+        for fd in fds {
+            let _ = close(fd);
+        }
+        res
+    }
+
+    async fn send(&self, handle: &mut Handle, message: LinkMessage) -> Result<()> {
+        let mut req = match self.action {
+            Action::Delete => NetlinkMessage::from(RtnlMessage::DelLink(message)),
+            Action::Add | Action::Set => NetlinkMessage::from(RtnlMessage::NewLink(message)),
+        };
+        if self.action == Action::Add {
+            req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE
+        } else {
+            req.header.flags = NLM_F_REQUEST | NLM_F_ACK
+        }
+
+        request_ack(handle, req).await
+    }
+}
+
+/// ip link add ... && ip addr add addr dev link.name && ip link set link.name up
+/// The link is deleted again when the address cannot be added, so a failed call leaves nothing
+/// half configured behind.
+pub async fn create_link_with_addr(handle: &mut Handle, link: &IPLink, addr: IpNet) -> Result<()> {
+    if link.action != Action::Add {
+        return Err(IprouteError::InvalidArgument(format!(
+            "create_link_with_addr needs an add action, got {:?}",
+            link.action
+        ))
+        .into());
+    }
+    link.execute(handle).await?;
+
+    let delete = IPLink {
+        action: Action::Delete,
+        name: link.name.clone(),
+        options: vec![],
+        link_type: None,
+    };
+    let res = IPAddr::new(AddrAction::Add, addr.addr(), addr.prefix_len(), &link.name)
+        .execute(handle)
+        .await;
+    if let Err(e) = res {
+        let undo = delete.execute(handle).await;
+        return Err(undone(e, undo, &format!("the creation of {}", link.name)));
+    }
+
+    IPLink {
+        action: Action::Set,
+        name: link.name.clone(),
+        options: vec![Opt::Up],
+        link_type: None,
+    }
+    .execute(handle)
+    .await
+}
+
+/// ip link set name down && ip link del name
+/// Some tunnel drivers refuse to delete a link that is still up. A link that is already gone
+/// is not an error.
+pub async fn delete_link_safe(handle: &mut Handle, name: &str) -> Result<()> {
+    let link = |action: Action, options: Vec<Opt>| IPLink {
+        action,
+        name: name.to_string(),
+        options,
+        link_type: None,
+    };
+    match link(Action::Set, vec![Opt::Down]).execute(handle).await {
+        Err(e) if netlink_errno(&e) == Some(Errno::ENODEV) => return Ok(()),
+        res => res?,
+    }
+    link(Action::Delete, vec![]).execute(handle).await
+}
+
+/// ip link set old_name down && ip link set old_name name new_name && ip link set new_name up
+/// There is no IFLA_NEW_IFNAME in the kernel, a rename is an IFLA_IFNAME sent for the index of
+/// the link. The kernel refuses to rename a link that is up, so it is brought down first and up
+/// again afterwards, including when the rename fails.
+pub async fn rename_link(handle: &mut Handle, old_name: &str, new_name: &str) -> Result<()> {
+    let link = get_link_without_info_data(old_name)?;
+    let is_up = link.header.flags & IFF_UP != 0;
+    let set = |name: &str, opt: Opt| IPLink {
+        action: Action::Set,
+        name: name.to_string(),
+        options: vec![opt],
+        link_type: None,
+    };
+
+    if is_up {
+        set(old_name, Opt::Down).execute(handle).await?;
+    }
+
+    let mut message = LinkMessage::default();
+    message.header.index = link.header.index;
+    name(new_name, &mut message);
+    let mut req = NetlinkMessage::from(RtnlMessage::NewLink(message));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+
+    let mut res = Ok(());
+    let mut response = handle.request(req)?;
+    while let Some(message) = response.next().await {
+        if let NetlinkPayload::Error(err) = message.payload {
+            res = Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err)));
+        }
+    }
+
+    if is_up {
+        let name = if res.is_ok() { new_name } else { old_name };
+        let undo = set(name, Opt::Up).execute(handle).await;
+        return match res {
+            Ok(()) => undo,
+            Err(e) => Err(undone(e, undo, &format!("bringing {} down", old_name))),
+        };
+    }
+    res
+}
+
+/// ip link set group group up|down
+/// The kernel applies a group request to every member at once, so no link can be missed
+/// between a dump and the per-link requests.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPLinkGroupOp {
+    pub group: u32,
+    pub up: bool,
+}
+
+impl IPLinkGroupOp {
+    pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
+        let mut message = LinkMessage::default();
+        message.nlas.push(Nla::Group(self.group));
+        if self.up {
+            Opt::Up.opt(&mut message)?;
+        } else {
+            Opt::Down.opt(&mut message)?;
+        }
+
+        let mut req = NetlinkMessage::from(RtnlMessage::NewLink(message));
+        req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+
+        request_ack(handle, req).await
+    }
+}
+
+/// ip link set group group up
+pub async fn set_link_group_up(handle: &mut Handle, group: u32) -> Result<()> {
+    IPLinkGroupOp { group, up: true }.execute(handle).await
+}
+
+/// ip link set group group down
+pub async fn set_link_group_down(handle: &mut Handle, group: u32) -> Result<()> {
+    IPLinkGroupOp { group, up: false }.execute(handle).await
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Action {
+    Add,
+    Delete,
+    Set,
+}
+
+#[enum_dispatch]
+pub trait LinkTypeTrait {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()>;
+}
+
+#[enum_dispatch(LinkTypeTrait)]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum LinkTypeEnum {
+    Veth(Veth),
+    Bridge(Bridge),
+    GreTap(GreTap),
+    MacVlan(MacVlan),
+    Ip6Tnl(Ip6Tnl),
+    Geneve(Geneve),
+    Vxlan(Vxlan),
+    BareUdp(BareUdp),
+    Vlan(Vlan),
+    MacVtap(MacVtap),
+    IpVlan(IpVlan),
+    Dummy(Dummy),
+    TunTap(TunTap),
+    Gre(Gre),
+    Ip6Gre(Ip6Gre),
+    Ip6GreTap(Ip6GreTap),
+    Sit(Sit),
+    WireGuard(WireGuard),
+    Bond(Bond),
+    Vrf(Vrf),
+}
+
+/// Kernel link kinds, without the data needed to create them.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LinkTypeName {
+    Dummy,
+    Ifb,
+    Bridge,
+    Tun,
+    Nlmon,
+    Vlan,
+    Veth,
+    Vxlan,
+    Bond,
+    IpVlan,
+    MacVlan,
+    MacVtap,
+    GreTap,
+    Ip6GreTap,
+    IpIp,
+    Sit,
+    Gre,
+    Ip6Gre,
+    Ip6Tnl,
+    Vti,
+    Vrf,
+    Gtp,
+    Ipoib,
+    WireGuard,
+    Geneve,
+    BareUdp,
+}
+
+impl FromStr for LinkTypeName {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        Ok(match kind {
+            "dummy" => LinkTypeName::Dummy,
+            "ifb" => LinkTypeName::Ifb,
+            "bridge" => LinkTypeName::Bridge,
+            "tun" => LinkTypeName::Tun,
+            "nlmon" => LinkTypeName::Nlmon,
+            "vlan" => LinkTypeName::Vlan,
+            "veth" => LinkTypeName::Veth,
+            "vxlan" => LinkTypeName::Vxlan,
+            "bond" => LinkTypeName::Bond,
+            "ipvlan" => LinkTypeName::IpVlan,
+            "macvlan" => LinkTypeName::MacVlan,
+            "macvtap" => LinkTypeName::MacVtap,
+            "gretap" => LinkTypeName::GreTap,
+            "ip6gretap" => LinkTypeName::Ip6GreTap,
+            "ipip" => LinkTypeName::IpIp,
+            "sit" => LinkTypeName::Sit,
+            "gre" => LinkTypeName::Gre,
+            "ip6gre" => LinkTypeName::Ip6Gre,
+            "ip6tnl" => LinkTypeName::Ip6Tnl,
+            "vti" => LinkTypeName::Vti,
+            "vrf" => LinkTypeName::Vrf,
+            "gtp" => LinkTypeName::Gtp,
+            "ipoib" => LinkTypeName::Ipoib,
+            "wireguard" => LinkTypeName::WireGuard,
+            "geneve" => LinkTypeName::Geneve,
+            "bareudp" => LinkTypeName::BareUdp,
+            _ => return Err(anyhow!("unknown link kind {}", kind)),
+        })
+    }
+}
+
+impl TryFrom<&InfoKind> for LinkTypeName {
+    type Error = anyhow::Error;
+
+    fn try_from(kind: &InfoKind) -> Result<Self> {
+        kind_name(kind).parse()
+    }
+}
+
+/// The kind string the kernel uses for kind, e.g. "bridge".
+pub fn kind_name(kind: &InfoKind) -> String {
+    let mut buffer = vec![0; kind.value_len()];
+    kind.emit_value(&mut buffer);
+    // drop the trailing nul
+    buffer.pop();
+    String::from_utf8_lossy(&buffer).to_string()
+}
+
+/// The link attributes link_nlas_as_map names, anything else is keyed by its attribute type.
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum NlaKey {
+    IfName,
+    IfAlias,
+    AltIfName,
+    Qdisc,
+    Kind,
+    Address,
+    Broadcast,
+    PermAddress,
+    Mtu,
+    MinMtu,
+    MaxMtu,
+    Link,
+    Master,
+    Group,
+    TxQueueLen,
+    NumTxQueues,
+    NumRxQueues,
+    GsoMaxSegs,
+    GsoMaxSize,
+    Promiscuity,
+    Carrier,
+    CarrierChanges,
+    OperState,
+    Mode,
+    NetnsId,
+    Other(u16),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum NlaValue {
+    Num(i64),
+    Str(String),
+    /// hardware addresses
+    Bytes(Vec<u8>),
+}
+
+impl std::fmt::Display for NlaValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NlaValue::Num(value) => write!(f, "{}", value),
+            NlaValue::Str(value) => write!(f, "{}", value),
+            NlaValue::Bytes(bytes) => {
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                write!(f, "{}", hex.join(":"))
+            }
+        }
+    }
+}
+
+/// The attributes of link flattened into a map, for inspection and serialization without
+/// matching every Nla variant. The kind nested in IFLA_LINKINFO is exposed as NlaKey::Kind.
+pub fn link_nlas_as_map(link: &LinkMessage) -> HashMap<NlaKey, NlaValue> {
+    let mut map = HashMap::new();
+    for nla in &link.nlas {
+        let (key, value) = match nla {
+            Nla::IfName(name) => (NlaKey::IfName, NlaValue::Str(name.clone())),
+            Nla::IfAlias(alias) => (NlaKey::IfAlias, NlaValue::Str(alias.clone())),
+            Nla::AltIfName(name) => (NlaKey::AltIfName, NlaValue::Str(name.clone())),
+            Nla::Qdisc(qdisc) => (NlaKey::Qdisc, NlaValue::Str(qdisc.clone())),
+            Nla::Address(addr) => (NlaKey::Address, NlaValue::Bytes(addr.clone())),
+            Nla::Broadcast(addr) => (NlaKey::Broadcast, NlaValue::Bytes(addr.clone())),
+            Nla::PermAddress(addr) => (NlaKey::PermAddress, NlaValue::Bytes(addr.clone())),
+            Nla::Mtu(value) => (NlaKey::Mtu, NlaValue::Num(*value as i64)),
+            Nla::MinMtu(value) => (NlaKey::MinMtu, NlaValue::Num(*value as i64)),
+            Nla::MaxMtu(value) => (NlaKey::MaxMtu, NlaValue::Num(*value as i64)),
+            Nla::Link(value) => (NlaKey::Link, NlaValue::Num(*value as i64)),
+            Nla::Master(value) => (NlaKey::Master, NlaValue::Num(*value as i64)),
+            Nla::Group(value) => (NlaKey::Group, NlaValue::Num(*value as i64)),
+            Nla::TxQueueLen(value) => (NlaKey::TxQueueLen, NlaValue::Num(*value as i64)),
+            Nla::NumTxQueues(value) => (NlaKey::NumTxQueues, NlaValue::Num(*value as i64)),
+            Nla::NumRxQueues(value) => (NlaKey::NumRxQueues, NlaValue::Num(*value as i64)),
+            Nla::GsoMaxSegs(value) => (NlaKey::GsoMaxSegs, NlaValue::Num(*value as i64)),
+            Nla::GsoMaxSize(value) => (NlaKey::GsoMaxSize, NlaValue::Num(*value as i64)),
+            Nla::Promiscuity(value) => (NlaKey::Promiscuity, NlaValue::Num(*value as i64)),
+            Nla::Carrier(value) => (NlaKey::Carrier, NlaValue::Num(*value as i64)),
+            Nla::CarrierChanges(value) => (NlaKey::CarrierChanges, NlaValue::Num(*value as i64)),
+            Nla::Mode(value) => (NlaKey::Mode, NlaValue::Num(*value as i64)),
+            Nla::NetnsId(value) => (NlaKey::NetnsId, NlaValue::Num(*value as i64)),
+            Nla::OperState(state) => (
+                NlaKey::OperState,
+                NlaValue::Str(format!("{:?}", state).to_uppercase()),
+            ),
+            Nla::Info(_) => {
+                if let Some(kind) = link_kind(link) {
+                    map.insert(NlaKey::Kind, NlaValue::Str(kind_name(kind)));
+                }
+                continue;
+            }
+            // netlink_packet_route 0.11 panics emitting some of these back,
+            // so they are kept in their Debug form
+            nla => (
+                NlaKey::Other(nla.kind()),
+                NlaValue::Str(format!("{:?}", nla)),
+            ),
+        };
+        map.insert(key, value);
+    }
+    map
+}
+
+/// The commonly needed attributes of a link, see describe_link.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LinkDescription {
+    pub name: String,
+    pub index: u32,
+    /// IFLA_INFO_KIND, None for hardware interfaces and loopback
+    pub kind: Option<String>,
+    pub is_up: bool,
+    pub mtu: u32,
+    /// colon separated hex, as ip link prints it
+    pub mac: Option<String>,
+    /// index of the master link
+    pub master: Option<u32>,
+    pub stats: Option<Stats64>,
+}
+
+/// The name, kind, state, mtu, mac, master and counters of link, read in a single pass over its
+/// attributes.
+pub fn describe_link(link: &LinkMessage) -> LinkDescription {
+    let mut description = LinkDescription {
+        name: String::new(),
+        index: link.header.index,
+        kind: None,
+        is_up: link.header.flags & IFF_UP != 0,
+        mtu: 0,
+        mac: None,
+        master: None,
+        stats: None,
+    };
+    for nla in &link.nlas {
+        match nla {
+            Nla::IfName(name) => description.name = name.clone(),
+            Nla::Mtu(mtu) => description.mtu = *mtu,
+            Nla::Address(addr) => description.mac = Some(NlaValue::Bytes(addr.clone()).to_string()),
+            Nla::Master(index) => description.master = Some(*index),
+            Nla::Stats64(bytes) => {
+                description.stats = Stats64Buffer::new_checked(bytes.as_slice())
+                    .and_then(|buffer| Stats64::parse(&buffer))
+                    .ok()
+            }
+            Nla::Info(infos) => {
+                for info in infos {
+                    if let Info::Kind(kind) = info {
+                        description.kind = Some(kind_name(kind));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    description
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Opt {
+    Up,
+    Down,
+    /// Enslaves the link to a bridge or bond, or to a VRF, whose table then holds the routes of
+    /// the link. A bridge does not separate routing: use a vrf link to isolate routes.
+    Master(String),
+    NetNS(String),
+    Group(u32),
+    Mtu(u32),
+    /// Multicast addresses are passed through, but most link types, veth and other ethernet
+    /// devices included, reject them with EADDRNOTAVAIL.
+    HwAddr([u8; 6]),
+    /// Packets the qdisc of the link may queue. Drivers with byte queue limits (BQL) also bound
+    /// the bytes queued in the device ring, so the latency added by a long queue then mostly
+    /// comes from the qdisc.
+    TxQueueLen(u32),
+    /// Receive every frame rather than only those addressed to the link. Like every flag, it
+    /// needs CAP_NET_ADMIN.
+    Promisc(bool),
+    /// Receive every multicast frame. Needs CAP_NET_ADMIN.
+    AllMulticast(bool),
+    /// Neither send nor answer ARP. Every flag opt only touches its own bit of flags and
+    /// change_mask, so NoArp and Up in the same request are applied together, and the link never
+    /// sends ARP while up.
+    NoArp(bool),
+    /// ifalias, at most IFALIASZ - 1 bytes. An empty alias removes it.
+    Alias(String),
+    /// Mb/s. rtnetlink has no attribute for it: IPLink::execute sets it with an ethtool ioctl
+    /// (see set_link_settings) once the netlink request is done, and opt refuses it. Support
+    /// depends on the driver.
+    Speed(u32),
+    /// Set like Speed through ethtool by IPLink::execute, opt refuses it.
+    Duplex(Duplex),
+}
+
+/// The size of the kernel ifalias buffer, nul included.
+const IFALIASZ: usize = 256;
+
+impl Opt {
+    pub fn opt(&self, message: &mut LinkMessage) -> Result<()> {
+        match self {
+            Opt::Up => {
+                message.header.change_mask |= IFF_UP;
+                message.header.flags |= IFF_UP;
+            }
+            Opt::Down => {
+                message.header.change_mask |= IFF_UP;
+                message.header.flags &= !IFF_UP;
+            }
+            Opt::Master(master_name) => {
+                message.nlas.push(Nla::Master(get_link_index(master_name)?));
+            }
+            Opt::NetNS(netns_name) => {
+                let mut path_string = String::from(NETNS_PATH);
+                path_string.push_str(netns_name.as_str());
+                let path = Path::new(&path_string);
+                // TODO : ERR HANDLING
+                let fd = nix::fcntl::open(path, OFlag::O_RDONLY, Mode::empty())?;
+                message.nlas.push(Nla::NetNsFd(fd));
+            }
+            Opt::Group(group) => message.nlas.push(Nla::Group(*group)),
+            Opt::Mtu(0) => {
+                return Err(IprouteError::InvalidArgument("mtu 0".to_string()).into());
+            }
+            Opt::Mtu(mtu) => message.nlas.push(Nla::Mtu(*mtu)),
+            Opt::HwAddr(mac) => message.nlas.push(Nla::Address(mac.to_vec())),
+            Opt::TxQueueLen(len) => message.nlas.push(Nla::TxQueueLen(*len)),
+            Opt::Promisc(promisc) => {
+                message.header.change_mask |= IFF_PROMISC;
+                if *promisc {
+                    message.header.flags |= IFF_PROMISC;
+                } else {
+                    message.header.flags &= !IFF_PROMISC;
+                }
+            }
+            Opt::AllMulticast(all_multicast) => {
+                message.header.change_mask |= IFF_ALLMULTI;
+                if *all_multicast {
+                    message.header.flags |= IFF_ALLMULTI;
+                } else {
+                    message.header.flags &= !IFF_ALLMULTI;
+                }
+            }
+            Opt::Alias(alias) if alias.len() >= IFALIASZ => {
+                return Err(IprouteError::InvalidArgument(format!(
+                    "alias of {} bytes, at most {} are kept",
+                    alias.len(),
+                    IFALIASZ - 1
+                ))
+                .into());
+            }
+            Opt::Alias(alias) => message.nlas.push(Nla::IfAlias(alias.clone())),
+            Opt::NoArp(no_arp) => {
+                message.header.change_mask |= IFF_NOARP;
+                if *no_arp {
+                    message.header.flags |= IFF_NOARP;
+                } else {
+                    message.header.flags &= !IFF_NOARP;
+                }
+            }
+            Opt::Speed(_) | Opt::Duplex(_) => {
+                return Err(IprouteError::InvalidArgument(format!(
+                    "{:?} has no netlink attribute, it is set through ethtool by IPLink::execute",
+                    self
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a mac address written as colon separated hex, e.g. de:ad:be:ef:00:01.
+pub fn parse_mac(s: &str) -> Result<[u8; 6]> {
+    let invalid = || -> anyhow::Error {
+        IprouteError::InvalidArgument(format!("invalid mac address {}", s)).into()
+    };
+    let mut mac = [0; 6];
+    let mut octets = s.split(':');
+    for byte in mac.iter_mut() {
+        let octet = octets.next().ok_or_else(invalid)?;
+        if octet.is_empty() || octet.len() > 2 {
+            return Err(invalid());
+        }
+        *byte = u8::from_str_radix(octet, 16).map_err(|_| invalid())?;
+    }
+    if octets.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(mac)
+}
+
+pub fn options(opts: Vec<Opt>, message: &mut LinkMessage) -> Result<()> {
+    for opt in opts {
+        opt.opt(message)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use netlink_packet_route::nlas::address::Nla as AddressNla;
+    use netlink_packet_route::rtnl::link::nlas::{InfoKind, Nla};
+    use netlink_packet_route::{
+        ErrorMessage, LinkMessage, IFF_ALLMULTI, IFF_NOARP, IFF_PROMISC, IFF_UP,
+    };
+    use nix::errno::Errno;
+    use rtnetlink::{new_connection, IpVersion};
+    use serial_test::serial;
+
+    use crate::capture::PacketSocket;
+    use crate::error::IprouteError;
+    use crate::ip::bridge::Bridge;
+    use crate::ip::ipaddr::get_addresses;
+    use crate::ip::iplink::{
+        create_link_with_addr, delete_link_safe, describe_link, get_link, get_link_by_index,
+        get_link_index, get_link_operstate, get_link_stats, get_link_stats64,
+        get_link_without_info_data, get_links_all, get_links_all_namespaces, get_links_all_raw,
+        get_links_by_all_types, get_links_by_all_types_raw, get_links_by_kind,
+        get_links_by_kind_raw, get_master, link_event_stream, link_exists, link_exists_async,
+        link_exists_checked, link_exists_sync, link_nlas_as_map, new_connection_with_rcvbuf,
+        options, parse_mac, rename_link, resolve_link_index, retry_dump_on_enomem,
+        set_link_group_down, wait_for_link_up, Action, DumpHandle, IPLink, IPLinkBuilder,
+        IPLinkGroupOp, IfIndexCache, LinkEvent, LinkTypeEnum, LinkTypeName, NlaKey, NlaValue,
+        OperState, Opt, DUMP_RCVBUF_SIZES,
+    };
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_exec};
+    use crate::ip::iproute::get_routes;
+    use crate::ip::testutil::{add_veth, del_link};
+    use crate::ip::veth::Veth;
+    use crate::ip::vxlan::Vxlan;
+
+    #[tokio::test]
+    async fn test_veth() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "v0", "v1", false).await.unwrap();
+
+        del_link(&mut handle, "v0").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_veth_auto_up() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "autoup0", "autoup1", true)
+            .await
+            .unwrap();
+
+        let link = get_link(&handle, "autoup0").await.unwrap();
+        let peer = get_link(&handle, "autoup1").await.unwrap();
+
+        del_link(&mut handle, "autoup0").await.unwrap();
+
+        assert_ne!(link.header.flags & IFF_UP, 0);
+        assert_ne!(peer.header.flags & IFF_UP, 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_links_all_namespaces() {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let (connection, mut handle, _) = new_connection().unwrap();
+                tokio::spawn(connection);
+                ip_net_ns_add("vnetns2".to_string()).unwrap();
+                ip_net_ns_add("vnetns3".to_string()).unwrap();
+
+                IPLink {
+                    action: Action::Add,
+                    name: "allns0".to_string(),
+                    options: vec![Opt::NetNS("vnetns2".to_string())],
+                    link_type: Some(LinkTypeEnum::Veth(Veth {
+                        peer_name: "allns1".to_string(),
+                        options: vec![],
+                        auto_up: false,
+                    })),
+                }
+                .execute(&mut handle)
+                .await
+                .unwrap();
+                IPLink {
+                    action: Action::Set,
+                    name: "allns1".to_string(),
+                    options: vec![Opt::NetNS("vnetns3".to_string())],
+                    link_type: None,
+                }
+                .execute(&mut handle)
+                .await
+                .unwrap();
+
+                let links = get_links_all_namespaces().unwrap();
+                ip_net_ns_del("vnetns2".to_string(), false).unwrap();
+                ip_net_ns_del("vnetns3".to_string(), false).unwrap();
+
+                let has_link = |ns_name: &str, name: &str| {
+                    links[ns_name].iter().any(|link| {
+                        link.nlas
+                            .iter()
+                            .any(|nla| matches!(nla, Nla::IfName(n) if n == name))
+                    })
+                };
+                assert!(has_link("vnetns2", "allns0"));
+                assert!(!has_link("vnetns2", "allns1"));
+                assert!(has_link("vnetns3", "allns1"));
+                assert!(!has_link("vnetns3", "allns0"));
+            });
+    }
+
+    #[test]
+    fn test_link_type_name() {
+        assert_eq!(
+            "bridge".parse::<LinkTypeName>().unwrap(),
+            LinkTypeName::Bridge
+        );
+        assert_eq!("veth".parse::<LinkTypeName>().unwrap(), LinkTypeName::Veth);
+        assert_eq!(
+            "vxlan".parse::<LinkTypeName>().unwrap(),
+            LinkTypeName::Vxlan
+        );
+        assert!("no-such-kind".parse::<LinkTypeName>().is_err());
+
+        assert_eq!(
+            LinkTypeName::try_from(&InfoKind::Bridge).unwrap(),
+            LinkTypeName::Bridge
+        );
+        assert_eq!(
+            LinkTypeName::try_from(&InfoKind::Other("geneve".to_string())).unwrap(),
+            LinkTypeName::Geneve
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_links_by_all_types() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "bytype0", "bytype1", false)
+            .await
+            .unwrap();
+        IPLink {
+            action: Action::Add,
+            name: "bytypebr0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let links = get_links_by_all_types(&handle).await;
+        let raw_links = get_links_by_all_types_raw();
+
+        for name in ["bytype0", "bytypebr0"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        let has_link = |links: &HashMap<String, Vec<LinkMessage>>, kind: &str, name: &str| {
+            links.get(kind).into_iter().flatten().any(|link| {
+                link.nlas
+                    .iter()
+                    .any(|nla| matches!(nla, Nla::IfName(n) if n == name))
+            })
+        };
+        let links = links.unwrap();
+        assert!(has_link(&links, "veth", "bytype0"));
+        assert!(has_link(&links, "veth", "bytype1"));
+        assert!(has_link(&links, "ethernet", "lo"));
+        let raw_links = raw_links.unwrap();
+        assert!(has_link(&raw_links, "veth", "bytype0"));
+        assert!(has_link(&raw_links, "bridge", "bytypebr0"));
+        assert!(has_link(&raw_links, "ethernet", "lo"));
+    }
+
+    #[tokio::test]
+    async fn test_link_group_up() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        for (name, peer_name) in [
+            ("group0", "group1"),
+            ("group2", "group3"),
+            ("group4", "group5"),
+        ] {
+            add_veth(&mut handle, name, peer_name, false).await.unwrap();
+        }
+        let members = ["group0", "group1", "group2", "group3", "group4"];
+        for name in members {
+            IPLink {
+                action: Action::Set,
+                name: name.to_string(),
+                options: vec![Opt::Group(3)],
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+        }
+
+        IPLinkGroupOp { group: 3, up: true }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+
+        let mut flags = vec![];
+        for name in members.iter().chain(&["group5"]) {
+            flags.push(get_link(&handle, name).await.unwrap().header.flags);
+        }
+        set_link_group_down(&mut handle, 3).await.unwrap();
+        let mut down_flags = vec![];
+        for name in members {
+            down_flags.push(get_link(&handle, name).await.unwrap().header.flags);
+        }
+
+        for name in ["group0", "group2", "group4"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        for flag in &flags[..5] {
+            assert_ne!(flag & IFF_UP, 0);
+        }
+        assert_eq!(flags[5] & IFF_UP, 0);
+        for flag in down_flags {
+            assert_eq!(flag & IFF_UP, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_link_nlas_as_map() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "nlamap0", "nlamap1", false)
+            .await
+            .unwrap();
+
+        let link = get_link(&handle, "nlamap0").await.unwrap();
+
+        del_link(&mut handle, "nlamap0").await.unwrap();
+
+        let map = link_nlas_as_map(&link);
+        assert_eq!(map[&NlaKey::Mtu], NlaValue::Num(1500));
+        assert_eq!(map[&NlaKey::Mtu].to_string(), "1500");
+        assert_eq!(map[&NlaKey::IfName], NlaValue::Str("nlamap0".to_string()));
+        assert_eq!(map[&NlaKey::Kind], NlaValue::Str("veth".to_string()));
+        assert_eq!(map[&NlaKey::Address].to_string().len(), 17);
+    }
+
+    #[tokio::test]
+    async fn test_if_index_cache() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        for (name, peer_name) in [("ifcache0", "ifcache1"), ("ifcache2", "ifcache3")] {
+            add_veth(&mut handle, name, peer_name, false).await.unwrap();
+        }
+
+        let mut cache = IfIndexCache::default();
+        cache.populate(&handle).await.unwrap();
+        let mut hits = vec![];
+        for name in ["lo", "ifcache0", "ifcache1", "ifcache2", "ifcache3"] {
+            let index = get_link(&handle, name).await.unwrap().header.index;
+            hits.push(cache.get_index(name) == Some(index) && cache.get_name(index) == Some(name));
+        }
+
+        add_veth(&mut handle, "ifcache4", "ifcache5", false)
+            .await
+            .unwrap();
+        let miss = cache.get_index("ifcache4");
+        cache.populate(&handle).await.unwrap();
+        let hit = cache.get_index("ifcache4");
+        cache.invalidate("ifcache4");
+        let invalidated = cache.get_index("ifcache4");
+
+        for name in ["ifcache0", "ifcache2", "ifcache4"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        assert_eq!(hits, vec![true; 5]);
+        assert_eq!(miss, None);
+        assert!(hit.is_some());
+        assert_eq!(invalidated, None);
+    }
+
+    #[tokio::test]
+    async fn test_describe_link() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let links = [
+            (
+                "desc0",
+                LinkTypeEnum::Veth(Veth {
+                    peer_name: "desc1".to_string(),
+                    options: vec![],
+                    auto_up: false,
+                }),
+            ),
+            ("descbr0", LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+            (
+                "descvx0",
+                LinkTypeEnum::Vxlan(Vxlan {
+                    vni: 43,
+                    remote: Some("192.0.2.2".parse().unwrap()),
+                    ..Default::default()
+                }),
+            ),
+        ];
+        for (name, link_type) in links.iter() {
+            IPLink {
+                action: Action::Add,
+                name: name.to_string(),
+                options: vec![],
+                link_type: Some(link_type.clone()),
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+        }
+
+        let veth = get_link(&handle, "desc0")
+            .await
+            .map(|link| describe_link(&link));
+        // bridge and vxlan info data does not parse, see get_link_without_info_data
+        let bridge = get_link_without_info_data("descbr0").map(|link| describe_link(&link));
+        let vxlan = get_link_without_info_data("descvx0").map(|link| describe_link(&link));
+
+        for (name, _) in links.iter() {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        let veth = veth.unwrap();
+        assert_eq!(veth.name, "desc0");
+        assert_eq!(veth.kind.as_deref(), Some("veth"));
+        assert!(!veth.is_up);
+        assert_eq!(veth.mtu, 1500);
+        assert_eq!(veth.mac.unwrap().len(), 17);
+        assert!(veth.stats.is_some());
+        assert_eq!(bridge.unwrap().kind.as_deref(), Some("bridge"));
+        assert_eq!(vxlan.unwrap().kind.as_deref(), Some("vxlan"));
+    }
+
+    #[tokio::test]
+    async fn test_create_link_with_addr() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let link = IPLink {
+            action: Action::Add,
+            name: "withaddr0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Veth(Veth {
+                peer_name: "withaddr1".to_string(),
+                options: vec![],
+                auto_up: false,
+            })),
+        };
+        create_link_with_addr(&mut handle, &link, "192.0.2.40/24".parse().unwrap())
+            .await
+            .unwrap();
+        let addresses = get_addresses(&handle, "withaddr0").await.unwrap();
+        let flags = get_link(&handle, "withaddr0").await.unwrap().header.flags;
+
+        del_link(&mut handle, "withaddr0").await.unwrap();
+
+        assert!(addresses.iter().any(|address| {
+            address.header.prefix_len == 24
+                && address
+                    .nlas
+                    .iter()
+                    .any(|nla| matches!(nla, AddressNla::Address(addr) if addr == &[192, 0, 2, 40]))
+        }));
+        assert_ne!(flags & IFF_UP, 0);
+    }
+
+    #[tokio::test]
+    async fn test_retry_dump_on_enomem() {
+        let error = |errno: Errno| -> anyhow::Error {
+            rtnetlink::Error::NetlinkError(ErrorMessage {
+                code: -(errno as i32),
+                header: vec![],
+            })
+            .into()
+        };
+
+        // stands in for a socket whose dumps fail until the buffer is large enough
+        let mut attempts = vec![];
+        let res = retry_dump_on_enomem(&DUMP_RCVBUF_SIZES, |rcvbuf| {
+            attempts.push(rcvbuf);
+            let res = match rcvbuf {
+                Some(rcvbuf) if rcvbuf >= 4 << 20 => Ok(rcvbuf),
+                _ => Err(error(Errno::ENOMEM)),
+            };
+            async move { res }
+        })
+        .await;
+        assert_eq!(res.unwrap(), 4 << 20);
+        assert_eq!(attempts, vec![None, Some(1 << 20), Some(4 << 20)]);
+
+        let mut attempts = 0;
+        let res: anyhow::Result<()> = retry_dump_on_enomem(&DUMP_RCVBUF_SIZES, |_| {
+            attempts += 1;
+            let res = Err(error(Errno::ENOMEM));
+            async move { res }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1 + DUMP_RCVBUF_SIZES.len());
+
+        let mut attempts = 0;
+        let res: anyhow::Result<()> = retry_dump_on_enomem(&DUMP_RCVBUF_SIZES, |_| {
+            attempts += 1;
+            let res = Err(error(Errno::EPERM));
+            async move { res }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let res: anyhow::Result<()> = retry_dump_on_enomem(&[], |_| {
+            attempts += 1;
+            let res = Err(error(Errno::ENOMEM));
+            async move { res }
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+
+        let handle = new_connection_with_rcvbuf(DUMP_RCVBUF_SIZES[0]).unwrap();
+        let dump = DumpHandle {
+            handle: &handle,
+            connect: Some(&new_connection_with_rcvbuf),
+        };
+        assert_eq!(dump.rcvbufs(), DUMP_RCVBUF_SIZES);
+        assert!(DumpHandle::from(&handle).rcvbufs().is_empty());
+        assert!(!get_routes(dump, IpVersion::V4).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rename_link() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        // a veth rather than a dummy, which this kernel may not have
+        add_veth(&mut handle, "rename0", "rename1", true)
+            .await
+            .unwrap();
+
+        let res = rename_link(&mut handle, "rename0", "renamed0").await;
+        let old = link_exists_checked("rename0");
+        let link = get_link_without_info_data("renamed0");
+
+        delete_link_safe(&mut handle, "renamed0").await.unwrap();
+        delete_link_safe(&mut handle, "rename0").await.unwrap();
+
+        res.unwrap();
+        assert!(!old.unwrap());
+        assert_ne!(link.unwrap().header.flags & IFF_UP, 0);
+    }
+
+    #[tokio::test]
+    async fn test_delete_link_safe() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "delsafe0", "delsafe1", true)
+            .await
+            .unwrap();
+
+        delete_link_safe(&mut handle, "delsafe0").await.unwrap();
+        assert!(get_link(&handle, "delsafe0").await.is_err());
+        delete_link_safe(&mut handle, "delsafe0").await.unwrap();
+    }
+
+    #[test]
+    fn test_link_exists() {
+        assert!(link_exists_checked("lo").unwrap());
+        assert!(!link_exists_checked("nosuchlink0").unwrap());
+        // longer than IFNAMSIZ, refused by the kernel
+        assert!(link_exists_checked("averyverylonglinkname").is_err());
+        assert!(link_exists_sync("lo"));
+        assert!(!link_exists_sync("nosuchlink0"));
+        assert!(!link_exists_sync("averyverylonglinkname"));
+    }
+
+    #[tokio::test]
+    async fn test_link_exists_handle() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "existsbr0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let bridge = link_exists(&handle, "existsbr0").await;
+        delete_link_safe(&mut handle, "existsbr0").await.unwrap();
+
+        assert!(link_exists(&handle, "lo").await.unwrap());
+        // found even though its answer cannot be parsed
+        assert!(bridge.unwrap());
+        assert!(!link_exists(&handle, "nosuchlink0").await.unwrap());
+        // longer than IFNAMSIZ, refused by the kernel
+        assert!(link_exists(&handle, "averyverylonglinkname").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_link_exists_async() {
+        let (connection, handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        assert!(link_exists_async(&handle, "lo").await);
+        assert!(!link_exists_async(&handle, "nosuchlink0").await);
+        assert!(!link_exists_async(&handle, "averyverylonglinkname").await);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_link_exists_async_in_handle_namespace() {
+        ip_net_ns_add("vnetns37".to_string()).unwrap();
+        // a handle on the namespace, used from a thread outside of it
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns37".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        let added = add_veth(&mut handle, "nsexists0", "nsexists1", false).await;
+        let exists = link_exists_async(&handle, "nsexists0").await;
+        ip_net_ns_del("vnetns37".to_string(), false).unwrap();
+
+        added.unwrap();
+        assert!(exists);
+        assert!(!link_exists_sync("nsexists0"));
+    }
+
+    #[tokio::test]
+    async fn test_get_link_stats64() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "stats0", "stats1", false)
+            .await
+            .unwrap();
+
+        let stats = get_link_stats64(&handle, "stats0").await;
+
+        del_link(&mut handle, "stats0").await.unwrap();
+
+        assert_eq!(stats.unwrap().tx_packets, 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_link_stats() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "lstats0", "lstats1", true)
+            .await
+            .unwrap();
+
+        let before = get_link_stats(&handle, "lstats0").await;
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame.extend_from_slice(&0x88b5u16.to_be_bytes());
+        frame.resize(60, 0);
+        let sent = PacketSocket::bind(get_link_index("lstats0").unwrap()).map(|socket| {
+            (0..3)
+                .map(|_| socket.send(&frame))
+                .collect::<anyhow::Result<Vec<_>>>()
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let after = get_link_stats(&handle, "lstats0").await;
+
+        del_link(&mut handle, "lstats0").await.unwrap();
+
+        sent.unwrap().unwrap();
+        let (before, after) = (before.unwrap(), after.unwrap());
+        // IPv6 may send solicitations on the link meanwhile
+        assert!(after.tx_packets >= before.tx_packets + 3);
+        assert!(after.tx_bytes >= before.tx_bytes + 180);
+        assert_eq!(after.tx_errors, 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_link_up() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "oper0".to_string(),
+            options: vec![Opt::Up],
+            link_type: Some(LinkTypeEnum::Veth(Veth {
+                peer_name: "oper1".to_string(),
+                options: vec![],
+                auto_up: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        // the peer is down, so there is no carrier
+        let lower_down = get_link_operstate(&handle, "oper0").await;
+        let timed_out = wait_for_link_up(&handle, "oper0", Duration::from_millis(300)).await;
+        let peer_up = IPLink {
+            action: Action::Set,
+            name: "oper1".to_string(),
+            options: vec![Opt::Up],
+            link_type: None,
+        }
+        .execute(&mut handle)
+        .await;
+        let up = wait_for_link_up(&handle, "oper0", Duration::from_secs(5)).await;
+
+        del_link(&mut handle, "oper0").await.unwrap();
+
+        assert_eq!(lower_down.unwrap(), OperState::LowerLayerDown);
+        assert!(timed_out.is_err());
+        peer_up.unwrap();
+        up.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_get_link_by_index() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "byidx0", "byidx1", false)
+            .await
+            .unwrap();
+
+        let index = get_link_index("byidx1").unwrap();
+        let link = get_link_by_index(index);
+
+        del_link(&mut handle, "byidx0").await.unwrap();
+
+        let link = link.unwrap();
+        assert_eq!(link.header.index, index);
+        assert!(link.nlas.contains(&Nla::IfName("byidx1".to_string())));
+        assert!(get_link_by_index(index).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_links_by_kind_raw() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "bykind0", "bykind1", false)
+            .await
+            .unwrap();
+        IPLink {
+            action: Action::Add,
+            name: "bykindbr0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let all = get_links_all_raw();
+        let veths = get_links_by_kind_raw("veth");
+        let bridges = get_links_by_kind_raw("bridge");
+
+        for name in ["bykind0", "bykindbr0"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        let names = |links: Vec<LinkMessage>| -> Vec<String> {
+            links
+                .into_iter()
+                .map(|link| describe_link(&link).name)
+                .collect()
+        };
+        let all = names(all.unwrap());
+        let veths = names(veths.unwrap());
+        let bridges = names(bridges.unwrap());
+        for name in ["lo", "bykind0", "bykind1", "bykindbr0"] {
+            assert!(all.contains(&name.to_string()));
+        }
+        assert!(veths.contains(&"bykind0".to_string()));
+        assert!(veths.contains(&"bykind1".to_string()));
+        assert!(!veths.contains(&"lo".to_string()));
+        assert!(!veths.contains(&"bykindbr0".to_string()));
+        assert_eq!(bridges, vec!["bykindbr0".to_string()]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_get_links_by_kind() {
+        ip_net_ns_add("vnetns38".to_string()).unwrap();
+        // a handle on the namespace, used from a thread outside of it
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns38".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        let added = add_veth(&mut handle, "nskind0", "nskind1", false).await;
+        let all = get_links_all(&handle).await;
+        let veths = get_links_by_kind(&handle, "veth").await;
+        ip_net_ns_del("vnetns38".to_string(), false).unwrap();
+
+        added.unwrap();
+        let names = |links: Vec<LinkMessage>| -> Vec<String> {
+            links
+                .into_iter()
+                .map(|link| describe_link(&link).name)
+                .collect()
+        };
+        let mut all = names(all.unwrap());
+        all.sort();
+        let mut veths = names(veths.unwrap());
+        veths.sort();
+        assert_eq!(all, vec!["lo", "nskind0", "nskind1"]);
+        assert_eq!(veths, vec!["nskind0", "nskind1"]);
+    }
+
+    #[test]
+    fn test_iplink_builder() {
+        let link = IPLinkBuilder::new("br0")
+            .link_type(Bridge::default())
+            .opt(Opt::Up)
+            .opt(Opt::Mtu(1400))
+            .build();
+        assert_eq!(
+            link,
+            IPLink {
+                action: Action::Add,
+                name: "br0".to_string(),
+                options: vec![Opt::Up, Opt::Mtu(1400)],
+                link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+            }
+        );
+
+        let link: IPLink = IPLinkBuilder::new("br0").action(Action::Delete).into();
+        assert_eq!(
+            link,
+            IPLink {
+                action: Action::Delete,
+                name: "br0".to_string(),
+                options: vec![],
+                link_type: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_master() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "masterbr0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        IPLink {
+            action: Action::Add,
+            name: "master0".to_string(),
+            options: vec![Opt::Master("masterbr0".to_string())],
+            link_type: Some(LinkTypeEnum::Veth(Veth {
+                peer_name: "master1".to_string(),
+                options: vec![],
+                auto_up: false,
+            })),
         }
         .execute(&mut handle)
         .await
         .unwrap();
+
+        let port = get_master("master0");
+        let peer = get_master("master1");
+
+        for name in ["master0", "masterbr0"] {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        assert_eq!(port.unwrap(), Some("masterbr0".to_string()));
+        assert_eq!(peer.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_link_set_mtu() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "mtu0", "mtu1", false).await.unwrap();
+
+        let set = |mtu| IPLink {
+            action: Action::Set,
+            name: "mtu0".to_string(),
+            options: vec![Opt::Mtu(mtu)],
+            link_type: None,
+        };
+        let res = set(1400).execute(&mut handle).await;
+        let zero = set(0).execute(&mut handle).await;
+        let mut mtus = vec![];
+        for name in ["mtu0", "mtu1"] {
+            mtus.push(describe_link(&get_link(&handle, name).await.unwrap()).mtu);
+        }
+
+        del_link(&mut handle, "mtu0").await.unwrap();
+
+        res.unwrap();
+        assert!(matches!(
+            zero.unwrap_err().downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        assert_eq!(mtus, vec![1400, 1500]);
+    }
+
+    #[test]
+    fn test_parse_mac() {
+        assert_eq!(
+            parse_mac("de:ad:be:ef:00:01").unwrap(),
+            [0xde, 0xad, 0xbe, 0xef, 0, 1]
+        );
+        assert_eq!(parse_mac("1:2:3:4:5:6").unwrap(), [1, 2, 3, 4, 5, 6]);
+        for invalid in [
+            "",
+            "de:ad:be:ef:00",
+            "de:ad:be:ef:00:01:02",
+            "de:ad:be:ef:00:1g",
+        ] {
+            assert!(matches!(
+                parse_mac(invalid)
+                    .unwrap_err()
+                    .downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_link_set_hwaddr() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "hwaddr0", "hwaddr1", false)
+            .await
+            .unwrap();
+
+        let set = |mac: &str| IPLink {
+            action: Action::Set,
+            name: "hwaddr0".to_string(),
+            options: vec![Opt::HwAddr(parse_mac(mac).unwrap())],
+            link_type: None,
+        };
+        let res = set("02:ad:be:ef:00:01").execute(&mut handle).await;
+        let multicast = set("03:ad:be:ef:00:01").execute(&mut handle).await;
+        let link = get_link(&handle, "hwaddr0").await;
+
+        del_link(&mut handle, "hwaddr0").await.unwrap();
+
+        res.unwrap();
+        assert!(multicast.is_err());
+        assert_eq!(
+            describe_link(&link.unwrap()).mac,
+            Some("02:ad:be:ef:00:01".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_tx_queue_len() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let created = IPLink {
+            action: Action::Add,
+            name: "txqlen0".to_string(),
+            options: vec![Opt::TxQueueLen(2000)],
+            link_type: Some(LinkTypeEnum::Veth(Veth {
+                peer_name: "txqlen1".to_string(),
+                options: vec![],
+                auto_up: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await;
+        let link = get_link(&handle, "txqlen0").await;
+
+        del_link(&mut handle, "txqlen0").await.unwrap();
+
+        created.unwrap();
+        assert!(link.unwrap().nlas.contains(&Nla::TxQueueLen(2000)));
+    }
+
+    #[tokio::test]
+    async fn test_link_promisc() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "promisc0", "promisc1", false)
+            .await
+            .unwrap();
+
+        let mut flags = vec![];
+        for options in [
+            vec![Opt::Promisc(true), Opt::AllMulticast(true)],
+            vec![Opt::Promisc(false)],
+        ] {
+            IPLink {
+                action: Action::Set,
+                name: "promisc0".to_string(),
+                options,
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+            flags.push(get_link(&handle, "promisc0").await.unwrap().header.flags);
+        }
+
+        del_link(&mut handle, "promisc0").await.unwrap();
+
+        assert_ne!(flags[0] & IFF_PROMISC, 0);
+        assert_ne!(flags[0] & IFF_ALLMULTI, 0);
+        assert_eq!(flags[1] & IFF_PROMISC, 0);
+        assert_ne!(flags[1] & IFF_ALLMULTI, 0);
+    }
+
+    #[test]
+    fn test_no_arp_with_up() {
+        let mut message = LinkMessage::default();
+        options(vec![Opt::Up, Opt::NoArp(true)], &mut message).unwrap();
+        assert_eq!(message.header.flags, IFF_UP | IFF_NOARP);
+        assert_eq!(message.header.change_mask, IFF_UP | IFF_NOARP);
+
+        let mut message = LinkMessage::default();
+        options(vec![Opt::NoArp(false), Opt::Up], &mut message).unwrap();
+        assert_eq!(message.header.flags, IFF_UP);
+        assert_eq!(message.header.change_mask, IFF_UP | IFF_NOARP);
+    }
+
+    #[tokio::test]
+    async fn test_link_alias() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "alias0", "alias1", false)
+            .await
+            .unwrap();
+
+        let set = |alias: String| IPLink {
+            action: Action::Set,
+            name: "alias0".to_string(),
+            options: vec![Opt::Alias(alias)],
+            link_type: None,
+        };
+        let res = set("uplink to rack 3".to_string())
+            .execute(&mut handle)
+            .await;
+        let too_long = set("a".repeat(256)).execute(&mut handle).await;
+        let link = get_link(&handle, "alias0").await;
+
+        del_link(&mut handle, "alias0").await.unwrap();
+
+        res.unwrap();
+        assert!(matches!(
+            too_long.unwrap_err().downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        let alias = link.unwrap().nlas.into_iter().find_map(|nla| match nla {
+            Nla::IfAlias(alias) => Some(alias),
+            _ => None,
+        });
+        assert_eq!(alias, Some("uplink to rack 3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_link_event_stream() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+        let mut events = Box::pin(link_event_stream(new_connection().unwrap()).await.unwrap());
+        let is_evt0 = |link: &LinkMessage| link.nlas.contains(&Nla::IfName("evt0".to_string()));
+
+        add_veth(&mut handle, "evt0", "evt1", false).await.unwrap();
+        // other tests add and delete links meanwhile
+        let added = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = events.next().await {
+                match event? {
+                    LinkEvent::Added(link) if is_evt0(&link) => return Ok(link),
+                    _ => {}
+                }
+            }
+            Err(anyhow::anyhow!("link events stopped"))
+        })
+        .await;
+
+        del_link(&mut handle, "evt0").await.unwrap();
+        let deleted = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = events.next().await {
+                match event? {
+                    LinkEvent::Deleted(link) if is_evt0(&link) => return Ok(link),
+                    _ => {}
+                }
+            }
+            Err(anyhow::anyhow!("link events stopped"))
+        })
+        .await;
+
+        let added = added.unwrap().unwrap();
+        let deleted = deleted.unwrap().unwrap();
+        assert_eq!(added.header.index, deleted.header.index);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_link_event_stream_in_connection_namespace() {
+        ip_net_ns_add("vnetns40".to_string()).unwrap();
+        // a connection on the namespace, used from a thread outside of it
+        let runtime = tokio::runtime::Handle::current();
+        let connection = thread_net_ns_exec("vnetns40".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        let mut handle = connection.1.clone();
+        let events = link_event_stream(connection).await;
+
+        let added = add_veth(&mut handle, "nsevt0", "nsevt1", false).await;
+        let event = match events {
+            Ok(mut events) => tokio::time::timeout(Duration::from_secs(5), events.next()).await,
+            Err(e) => Ok(Some(Err(e))),
+        };
+        ip_net_ns_del("vnetns40".to_string(), false).unwrap();
+
+        added.unwrap();
+        match event.unwrap() {
+            Some(Ok(LinkEvent::Added(link))) => assert!(
+                link.nlas.contains(&Nla::IfName("nsevt0".to_string()))
+                    || link.nlas.contains(&Nla::IfName("nsevt1".to_string()))
+            ),
+            event => panic!("unexpected link event {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_link_index() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        for (name, link_type) in [
+            (
+                "resolve0",
+                LinkTypeEnum::Veth(Veth {
+                    peer_name: "resolve1".to_string(),
+                    options: vec![],
+                    auto_up: false,
+                }),
+            ),
+            ("resolvebr0", LinkTypeEnum::Bridge(Bridge { info: vec![] })),
+        ] {
+            IPLink {
+                action: Action::Add,
+                name: name.to_string(),
+                options: vec![],
+                link_type: Some(link_type),
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+        }
+
+        let veth = resolve_link_index(&handle, "resolve0").await;
+        let bridge = resolve_link_index(&handle, "resolvebr0").await;
+        let missing = resolve_link_index(&handle, "resolve2").await;
+        let indexes = (get_link_index("resolve0"), get_link_index("resolvebr0"));
+
+        for name in ["resolve0", "resolvebr0"] {
+            delete_link_safe(&mut handle, name).await.unwrap();
+        }
+
+        assert_eq!(veth.unwrap(), indexes.0.unwrap());
+        // never guessed from the network namespace of the calling thread
+        assert!(indexes.1.is_ok());
+        assert!(bridge.is_err());
+        assert!(missing.is_err());
     }
 }
@@ -0,0 +1,480 @@
+use std::fs::{read_to_string, write};
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::TryStreamExt;
+use netlink_packet_route::constants::*;
+use netlink_packet_route::nlas::neighbour::Nla;
+use netlink_packet_route::{NeighbourMessage, NetlinkMessage, RtnlMessage};
+use nix::sys::socket::AddressFamily;
+use rtnetlink::{Handle, IpVersion};
+
+use crate::error::IprouteError;
+use crate::ip::iplink::{
+    get_link, request_ack, resolve_link_index, retry_dump_on_enomem, DumpHandle,
+};
+
+/// ip neigh add|del|replace dst [lladdr lladdr] dev iface [nud state]
+/// flags are NTF_*, e.g. NTF_ROUTER for an IPv6 router.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPNeigh {
+    pub action: NeighAction,
+    pub dst: IpAddr,
+    pub lladdr: Option<[u8; 6]>,
+    pub iface: String,
+    pub state: NeighState,
+    pub flags: u8,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum NeighAction {
+    Add,
+    Del,
+    /// Add, or overwrite the entry of dst.
+    Replace,
+}
+
+/// NUD_*, the state of a neighbour entry.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum NeighState {
+    /// Static, never resolved again nor garbage collected.
+    Permanent,
+    /// No resolution is needed, e.g. on a point to point link.
+    NoArp,
+    Reachable,
+    /// Usable, but confirmed again before the next packet is sent.
+    Stale,
+    Delay,
+    Probe,
+    Incomplete,
+    Failed,
+    None,
+}
+
+impl NeighState {
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            NeighState::Permanent => NUD_PERMANENT,
+            NeighState::NoArp => NUD_NOARP,
+            NeighState::Reachable => NUD_REACHABLE,
+            NeighState::Stale => NUD_STALE,
+            NeighState::Delay => NUD_DELAY,
+            NeighState::Probe => NUD_PROBE,
+            NeighState::Incomplete => NUD_INCOMPLETE,
+            NeighState::Failed => NUD_FAILED,
+            NeighState::None => NUD_NONE,
+        }
+    }
+}
+
+impl IPNeigh {
+    pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
+        let ifindex = resolve_link_index(handle, &self.iface).await?;
+        let mut message = neigh_message(ifindex, self.dst, self.state.as_u16());
+        message.header.flags = self.flags;
+        if let Some(lladdr) = self.lladdr {
+            message.nlas.push(Nla::LinkLocalAddress(lladdr.to_vec()));
+        }
+
+        let mut req = match self.action {
+            NeighAction::Del => NetlinkMessage::from(RtnlMessage::DelNeighbour(message)),
+            NeighAction::Add | NeighAction::Replace => {
+                NetlinkMessage::from(RtnlMessage::NewNeighbour(message))
+            }
+        };
+        req.header.flags = match self.action {
+            NeighAction::Add => NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+            NeighAction::Replace => NLM_F_REQUEST | NLM_F_ACK | NLM_F_REPLACE | NLM_F_CREATE,
+            NeighAction::Del => NLM_F_REQUEST | NLM_F_ACK,
+        };
+
+        request_ack(handle, req).await
+    }
+}
+
+/// The entry for dst on the link ifindex in state, shared by IPNeigh, neigh_evict and the ndp
+/// proxies.
+fn neigh_message(ifindex: u32, dst: IpAddr, state: u16) -> NeighbourMessage {
+    let mut message = NeighbourMessage::default();
+    message.header.ifindex = ifindex;
+    message.header.state = state;
+    match dst {
+        IpAddr::V4(dst) => {
+            message.header.family = AF_INET as u8;
+            message.nlas.push(Nla::Destination(dst.octets().to_vec()));
+        }
+        IpAddr::V6(dst) => {
+            message.header.family = AF_INET6 as u8;
+            message.nlas.push(Nla::Destination(dst.octets().to_vec()));
+        }
+    }
+    message
+}
+
+/// ip -4|-6 neigh show
+/// Proxy entries are not included, see get_ndp_proxies.
+pub async fn get_neighbours<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    ip_version: IpVersion,
+) -> Result<Vec<NeighbourMessage>> {
+    let handle = handle.into();
+    retry_dump_on_enomem(handle.rcvbufs(), |rcvbuf| {
+        let ip_version = ip_version.clone();
+        async move {
+            let neighbours = handle
+                .attempt(rcvbuf)?
+                .neighbours()
+                .get()
+                .set_family(ip_version)
+                .execute()
+                .try_collect()
+                .await?;
+            Ok(neighbours)
+        }
+    })
+    .await
+}
+
+async fn ndp_proxy_request(
+    handle: &mut Handle,
+    addr: Ipv6Addr,
+    dev: &str,
+    add: bool,
+) -> Result<()> {
+    let link = get_link(handle, dev).await?;
+
+    let mut message = neigh_message(link.header.index, IpAddr::V6(addr), NUD_PERMANENT);
+    message.header.flags = NTF_PROXY;
+
+    let mut req = if add {
+        NetlinkMessage::from(RtnlMessage::NewNeighbour(message))
+    } else {
+        NetlinkMessage::from(RtnlMessage::DelNeighbour(message))
+    };
+
+    if add {
+        req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE
+    } else {
+        req.header.flags = NLM_F_REQUEST | NLM_F_ACK
+    };
+
+    request_ack(handle, req).await
+}
+
+/// ip -6 neigh add proxy addr dev dev
+/// The entry is only answered for once net.ipv6.conf.<dev>.proxy_ndp is enabled.
+pub async fn ndp_proxy_add(handle: &mut Handle, addr: Ipv6Addr, dev: &str) -> Result<()> {
+    ndp_proxy_request(handle, addr, dev, true).await
+}
+
+/// ip -6 neigh del proxy addr dev dev
+pub async fn ndp_proxy_del(handle: &mut Handle, addr: Ipv6Addr, dev: &str) -> Result<()> {
+    ndp_proxy_request(handle, addr, dev, false).await
+}
+
+/// ip -6 neigh show proxy
+pub async fn get_ndp_proxies(handle: &Handle) -> Result<Vec<NeighbourMessage>> {
+    let proxies = handle
+        .neighbours()
+        .get()
+        .set_family(IpVersion::V6)
+        .proxies()
+        .execute()
+        .try_collect()
+        .await?;
+    Ok(proxies)
+}
+
+/// ip neigh change addr dev dev nud failed
+/// Marks the entry as failed instead of deleting it, so the next packet to addr starts a new
+/// resolution. No link layer address is sent, the kernel keeps the one it had.
+pub async fn neigh_evict(handle: &mut Handle, dev: &str, addr: IpAddr) -> Result<()> {
+    let ifindex = resolve_link_index(handle, dev).await?;
+    let message = neigh_message(ifindex, addr, NUD_FAILED);
+
+    let mut req = NetlinkMessage::from(RtnlMessage::NewNeighbour(message));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_REPLACE;
+
+    request_ack(handle, req).await
+}
+
+/// How long a neighbour stays reachable after a confirmation, and how long to wait between
+/// solicitations. The kernel picks the actual reachable time at random in
+/// [base_reachable / 2, 3 * base_reachable / 2].
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct NeighTimeout {
+    pub base_reachable: Duration,
+    pub retrans: Duration,
+}
+
+fn neigh_sysctl_dir(dev: &str, family: AddressFamily) -> Result<String> {
+    let family = match family {
+        AddressFamily::Inet => "ipv4",
+        AddressFamily::Inet6 => "ipv6",
+        family => {
+            return Err(IprouteError::InvalidArgument(format!(
+                "no neighbour table for {:?}",
+                family
+            ))
+            .into())
+        }
+    };
+    if dev.is_empty() || dev.contains('/') || dev == "." || dev == ".." {
+        return Err(IprouteError::InvalidArgument(format!("invalid device name {:?}", dev)).into());
+    }
+    Ok(format!("/proc/sys/net/{}/neigh/{}", family, dev))
+}
+
+fn read_ms(path: &str) -> Result<Duration> {
+    let ms = read_to_string(path)?;
+    let ms = ms
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("invalid value in {}: {}", path, e))?;
+    Ok(Duration::from_millis(ms))
+}
+
+/// sysctl net.ipv{4,6}.neigh.dev.base_reachable_time_ms=base_reachable
+///        net.ipv{4,6}.neigh.dev.retrans_time_ms=retrans
+pub fn set_neigh_timeout(
+    dev: &str,
+    family: AddressFamily,
+    base_reachable: Duration,
+    retrans: Duration,
+) -> Result<()> {
+    let dir = neigh_sysctl_dir(dev, family)?;
+    write(
+        format!("{}/base_reachable_time_ms", dir),
+        base_reachable.as_millis().to_string(),
+    )?;
+    write(
+        format!("{}/retrans_time_ms", dir),
+        retrans.as_millis().to_string(),
+    )?;
+    Ok(())
+}
+
+/// sysctl net.ipv{4,6}.neigh.dev.base_reachable_time_ms net.ipv{4,6}.neigh.dev.retrans_time_ms
+pub fn get_neigh_timeout(dev: &str, family: AddressFamily) -> Result<NeighTimeout> {
+    let dir = neigh_sysctl_dir(dev, family)?;
+    Ok(NeighTimeout {
+        base_reachable: read_ms(&format!("{}/base_reachable_time_ms", dir))?,
+        retrans: read_ms(&format!("{}/retrans_time_ms", dir))?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv6Addr};
+    use std::time::Duration;
+
+    use futures::TryStreamExt;
+    use netlink_packet_route::constants::{NTF_PROXY, NUD_FAILED, NUD_PERMANENT, NUD_STALE};
+    use netlink_packet_route::nlas::neighbour::Nla;
+    use netlink_packet_route::NeighbourMessage;
+    use nix::sys::socket::AddressFamily;
+    use rtnetlink::{new_connection, IpVersion};
+    use serial_test::serial;
+
+    use crate::ip::iplink::get_link_index;
+    use crate::ip::ipneigh::{
+        get_ndp_proxies, get_neigh_timeout, get_neighbours, ndp_proxy_add, ndp_proxy_del,
+        neigh_evict, set_neigh_timeout, IPNeigh, NeighAction, NeighState, NeighTimeout,
+    };
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_exec};
+    use crate::ip::testutil::{add_veth, del_link};
+
+    #[tokio::test]
+    async fn test_ip_neigh() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "neigh0", "neigh1", false)
+            .await
+            .unwrap();
+
+        let index = get_link_index("neigh0").unwrap();
+        let destination = Nla::Destination(vec![192, 0, 2, 30]);
+        let entry = |neighbours: Vec<NeighbourMessage>| {
+            neighbours
+                .into_iter()
+                .find(|neigh| neigh.header.ifindex == index && neigh.nlas.contains(&destination))
+        };
+        let mut neigh = IPNeigh {
+            action: NeighAction::Add,
+            dst: "192.0.2.30".parse().unwrap(),
+            lladdr: Some([0x02, 0, 0, 0, 0, 0x30]),
+            iface: "neigh0".to_string(),
+            state: NeighState::Permanent,
+            flags: 0,
+        };
+        let added = neigh.execute(&mut handle).await;
+        let permanent = get_neighbours(&handle, IpVersion::V4).await;
+        neigh.action = NeighAction::Replace;
+        neigh.lladdr = Some([0x02, 0, 0, 0, 0, 0x31]);
+        neigh.state = NeighState::Stale;
+        let replaced = neigh.execute(&mut handle).await;
+        let stale = get_neighbours(&handle, IpVersion::V4).await;
+        neigh.action = NeighAction::Del;
+        let deleted = neigh.execute(&mut handle).await;
+        let remaining = get_neighbours(&handle, IpVersion::V4).await;
+
+        del_link(&mut handle, "neigh0").await.unwrap();
+
+        added.unwrap();
+        replaced.unwrap();
+        deleted.unwrap();
+        let permanent = entry(permanent.unwrap()).unwrap();
+        assert_eq!(permanent.header.state, NUD_PERMANENT);
+        assert!(permanent
+            .nlas
+            .contains(&Nla::LinkLocalAddress(vec![0x02, 0, 0, 0, 0, 0x30])));
+        let stale = entry(stale.unwrap()).unwrap();
+        assert_eq!(stale.header.state, NUD_STALE);
+        assert!(stale
+            .nlas
+            .contains(&Nla::LinkLocalAddress(vec![0x02, 0, 0, 0, 0, 0x31])));
+        assert!(entry(remaining.unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ndp_proxy() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "ndpx0", "ndpx1", false)
+            .await
+            .unwrap();
+
+        let addr: Ipv6Addr = "2001:db8::10".parse().unwrap();
+        ndp_proxy_add(&mut handle, addr, "ndpx0").await.unwrap();
+        let added = get_ndp_proxies(&handle).await.unwrap();
+        ndp_proxy_del(&mut handle, addr, "ndpx0").await.unwrap();
+        let deleted = get_ndp_proxies(&handle).await.unwrap();
+
+        del_link(&mut handle, "ndpx0").await.unwrap();
+
+        let destination = Nla::Destination(addr.octets().to_vec());
+        let entry = added
+            .iter()
+            .find(|neigh| neigh.nlas.contains(&destination))
+            .unwrap();
+        assert_ne!(entry.header.flags & NTF_PROXY, 0);
+        assert!(!deleted
+            .iter()
+            .any(|neigh| neigh.nlas.contains(&destination)));
+    }
+
+    #[tokio::test]
+    async fn test_neigh_timeout() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "neighto0", "neighto1", false)
+            .await
+            .unwrap();
+
+        let timeout = NeighTimeout {
+            base_reachable: Duration::from_secs(10),
+            retrans: Duration::from_millis(500),
+        };
+        let mut read = vec![];
+        for family in [AddressFamily::Inet, AddressFamily::Inet6] {
+            set_neigh_timeout("neighto0", family, timeout.base_reachable, timeout.retrans).unwrap();
+            read.push(get_neigh_timeout("neighto0", family));
+        }
+
+        del_link(&mut handle, "neighto0").await.unwrap();
+
+        for timeouts in read {
+            assert_eq!(timeouts.unwrap(), timeout);
+        }
+        assert!(get_neigh_timeout("../all", AddressFamily::Inet).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_neigh_evict() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "evict0", "evict1", true)
+            .await
+            .unwrap();
+
+        let index = get_link_index("evict0").unwrap();
+        let addr: IpAddr = "192.0.2.20".parse().unwrap();
+        let destination = Nla::Destination(vec![192, 0, 2, 20]);
+        let state = |neighbours: Vec<NeighbourMessage>| {
+            neighbours
+                .into_iter()
+                .find(|neigh| neigh.header.ifindex == index && neigh.nlas.contains(&destination))
+                .map(|neigh| neigh.header.state)
+        };
+
+        handle
+            .neighbours()
+            .add(index, addr)
+            .link_local_address(&[0x02, 0, 0, 0, 0, 0x20])
+            .state(NUD_PERMANENT)
+            .execute()
+            .await
+            .unwrap();
+        let added = handle.neighbours().get().execute().try_collect().await;
+        let res = neigh_evict(&mut handle, "evict0", addr).await;
+        let evicted = handle.neighbours().get().execute().try_collect().await;
+
+        del_link(&mut handle, "evict0").await.unwrap();
+
+        res.unwrap();
+        assert_eq!(state(added.unwrap()), Some(NUD_PERMANENT));
+        // either way the next lookup resolves addr again
+        assert!(matches!(state(evicted.unwrap()), None | Some(NUD_FAILED)));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_ip_neigh_in_handle_namespace() {
+        ip_net_ns_add("vnetns44".to_string()).unwrap();
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns44".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        // nsneigh0 only exists in the namespace of handle
+        let added = add_veth(&mut handle, "nsneigh0", "nsneigh1", true).await;
+        let addr: IpAddr = "192.0.2.40".parse().unwrap();
+        let neigh = IPNeigh {
+            action: NeighAction::Add,
+            dst: addr,
+            lladdr: Some([0x02, 0, 0, 0, 0, 0x40]),
+            iface: "nsneigh0".to_string(),
+            state: NeighState::Permanent,
+            flags: 0,
+        }
+        .execute(&mut handle)
+        .await;
+        let neighbours = get_neighbours(&handle, IpVersion::V4).await;
+        let evicted = neigh_evict(&mut handle, "nsneigh0", addr).await;
+        let index = thread_net_ns_exec("vnetns44".to_string(), || get_link_index("nsneigh0"))
+            .join()
+            .unwrap();
+
+        ip_net_ns_del("vnetns44".to_string(), false).unwrap();
+
+        added.unwrap();
+        neigh.unwrap();
+        evicted.unwrap();
+        let index = index.unwrap();
+        assert!(neighbours
+            .unwrap()
+            .iter()
+            .any(|neigh| neigh.header.ifindex == index
+                && neigh.nlas.contains(&Nla::Destination(vec![192, 0, 2, 40]))));
+    }
+}
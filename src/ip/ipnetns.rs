@@ -1,36 +1,70 @@
-use std::fs::read_dir;
+use std::collections::HashMap;
+use std::fs::{read, read_dir, remove_dir_all, remove_file, write, DirBuilder, File};
+use std::future::Future;
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::FromRawFd;
 use std::path::Path;
 use std::process::exit;
 use std::thread::JoinHandle;
 
 use anyhow::{anyhow, Result};
+use futures::channel::oneshot;
+use futures::TryStreamExt;
+use netlink_packet_route::nlas::address::Nla as AddressNla;
+use netlink_packet_route::nlas::link::Nla as LinkNla;
+use netlink_packet_route::nlas::route::{NextHopBuffer, Nla as RouteNla};
+use netlink_packet_route::{AddressMessage, LinkMessage, IFF_UP, RTPROT_KERNEL, RT_TABLE_LOCAL};
+use nix::errno::Errno;
 use nix::fcntl::{open, OFlag};
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
-use nix::sched::CloneFlags;
+use nix::sched::{unshare, CloneFlags};
 use nix::sys::stat::Mode;
 use nix::sys::statvfs::{statvfs, FsFlags};
-use nix::unistd::{close, fork, ForkResult};
-use rtnetlink::NetworkNamespace;
+use nix::unistd::{close, fork, pipe, ForkResult};
+use rtnetlink::{new_connection, IpVersion, NetworkNamespace};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{undone, IprouteError};
+use crate::ip::ipaddr::{ip_from_bytes, AddrAction, IPAddr};
+use crate::ip::iplink::{Action as LinkAction, IPLink, Opt};
+use crate::ip::iproute::{get_routes, Action as RouteAction, IPRoute};
 
 pub const NETNS_RUN_DIR: &str = "/var/run/netns/";
 
+/// Namespace names end up in paths under NETNS_RUN_DIR and /etc/netns, so anything that could
+/// leave those directories or be mistaken for an option is refused.
+fn validate_ns_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('-') || name.contains(['/', '.', '\0']) {
+        return Err(IprouteError::InvalidArgument(format!(
+            "invalid network namespace name {:?}",
+            name
+        ))
+        .into());
+    }
+    Ok(())
+}
+
 /// Fatal : Never add device or do something that change files related with network
 /// in filesystem after set_net_ns.
 pub fn set_net_ns(ns_name: String) -> Result<()> {
+    validate_ns_name(&ns_name)?;
+    set_net_ns_path(Path::new(&format!("{}{}", NETNS_RUN_DIR, ns_name)))
+}
+
+/// set_net_ns for any namespace file, e.g. /proc/pid/ns/net.
+fn set_net_ns_path(path: &Path) -> Result<()> {
     let mut open_flags = OFlag::empty();
     open_flags.insert(OFlag::O_RDONLY);
     open_flags.insert(OFlag::O_CLOEXEC);
 
-    let fd = match open(
-        Path::new(&format!("{}{}", NETNS_RUN_DIR, &ns_name)),
-        open_flags,
-        Mode::empty(),
-    ) {
+    let fd = match open(path, open_flags, Mode::empty()) {
         Ok(raw_fd) => raw_fd,
         Err(e) => {
             return Err(anyhow!(
                 "Cannot open network namespace \"{}\": {}\n",
-                ns_name,
+                path.display(),
                 e.to_string()
             ))
         }
@@ -40,7 +74,7 @@ pub fn set_net_ns(ns_name: String) -> Result<()> {
         close(fd)?;
         return Err(anyhow!(
             "setting the network namespace {} failed: {}",
-            ns_name,
+            path.display(),
             e.to_string()
         ));
     };
@@ -48,21 +82,70 @@ pub fn set_net_ns(ns_name: String) -> Result<()> {
     Ok(())
 }
 
+/// nsenter --net --target pid f()
+/// f runs on a thread of its own that enters the network namespace of process pid, and is
+/// joined before returning.
+pub fn ip_net_ns_exec_by_pid<F, T>(pid: u32, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    let proc_dir = format!("/proc/{}", pid);
+    if !Path::new(&proc_dir).exists() {
+        return Err(anyhow!("no process with pid {}", pid));
+    }
+    let ns_path = format!("{}/ns/net", proc_dir);
+    let thread = std::thread::spawn(move || {
+        set_net_ns_path(Path::new(&ns_path))?;
+        f()
+    });
+    match thread.join() {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!(
+            "thread in the network namespace of pid {} panicked",
+            pid
+        )),
+    }
+}
+
 /// just setns & exec f()
 /// Fatal : Never add device or do something that change files related with network
 /// in filesystem in thread_netns_exec.
+/// The errors of f are returned through the JoinHandle like those of set_net_ns.
 pub fn thread_net_ns_exec<F, T>(ns_name: String, f: F) -> JoinHandle<Result<T>>
 where
-    F: FnOnce() -> T,
+    F: FnOnce() -> Result<T>,
     F: Send + 'static,
     T: Send + 'static,
 {
     std::thread::spawn(|| {
         set_net_ns(ns_name)?;
-        Ok(f())
+        f()
     })
 }
 
+/// thread_net_ns_exec for async work: the future returned by f is driven by a runtime
+/// owned by the thread, so netlink sockets opened in it belong to the namespace.
+pub fn thread_net_ns_block_on<F, Fut, T>(ns_name: String, f: F) -> Result<T>
+where
+    F: FnOnce() -> Fut,
+    F: Send + 'static,
+    Fut: Future<Output = Result<T>>,
+    T: Send + 'static,
+{
+    let thread = thread_net_ns_exec(ns_name.clone(), || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(f())
+    });
+    match thread.join() {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("thread in network namespace {} panicked", ns_name)),
+    }
+}
+
 fn bind_etc(ns_name: String) {
     if ns_name.len() > 255 {
         return;
@@ -134,29 +217,58 @@ fn netns_switch(ns_name: String) -> Result<()> {
 
 /// It seems using both tokio & fork will bring a lot of error.
 /// ip netns exec name f()
-pub fn ip_net_ns_exec<F, T>(ns_name: String, f: F) -> Result<()>
+/// f runs in a forked child. Its result comes back to the parent as JSON over a pipe: the Ok value
+/// is deserialized into T, and an Err is returned as an error with the same message.
+pub fn ip_net_ns_exec<F, T>(ns_name: String, f: F) -> Result<T>
 where
     F: FnOnce() -> Result<T>,
     F: Send + 'static,
-    T: Send + 'static,
+    T: Serialize + DeserializeOwned + Send + 'static,
 {
+    validate_ns_name(&ns_name)?;
+    let (reader, writer) = pipe()?;
     match unsafe { fork() } {
-        Ok(ForkResult::Parent { child, .. }) => Ok(NetworkNamespace::parent_process(child)?),
-        Ok(ForkResult::Child) => {
-            if netns_switch(ns_name).is_err() {
-                exit(1);
+        Ok(ForkResult::Parent { child, .. }) => {
+            close(writer)?;
+            let mut output = String::new();
+            let read = unsafe { File::from_raw_fd(reader) }.read_to_string(&mut output);
+            let waited = NetworkNamespace::parent_process(child);
+            read?;
+            match serde_json::from_str::<std::result::Result<T, String>>(&output) {
+                Ok(Ok(value)) => Ok(value),
+                Ok(Err(e)) => Err(anyhow!(e)),
+                Err(e) => {
+                    waited?;
+                    Err(anyhow!(
+                        "invalid result from network namespace {}: {}",
+                        ns_name,
+                        e
+                    ))
+                }
             }
-            if f().is_err() {
-                exit(1);
+        }
+        Ok(ForkResult::Child) => {
+            let _ = close(reader);
+            let res = netns_switch(ns_name)
+                .and_then(|_| f())
+                .map_err(|e| format!("{:#}", e));
+            let code = res.is_err() as i32;
+            match serde_json::to_writer(unsafe { File::from_raw_fd(writer) }, &res) {
+                Ok(()) => exit(code),
+                Err(_) => exit(1),
             }
-            exit(0)
         }
-        Err(_) => Err(anyhow!("Fork failed")),
+        Err(_) => {
+            let _ = close(reader);
+            let _ = close(writer);
+            Err(anyhow!("Fork failed"))
+        }
     }
 }
 
 /// ip netns add name
 pub fn ip_net_ns_add(ns_name: String) -> Result<()> {
+    validate_ns_name(&ns_name)?;
     match unsafe { fork() } {
         Ok(ForkResult::Parent { child, .. }) => Ok(NetworkNamespace::parent_process(child)?),
         Ok(ForkResult::Child) => {
@@ -173,12 +285,158 @@ pub fn ip_net_ns_add(ns_name: String) -> Result<()> {
     }
 }
 
+/// ip netns add name, without forking: safe to call from a runtime whose threads are running.
+/// The namespace is created by a thread of its own, since the thread is left in the namespace,
+/// which rules out runtime workers and the reused threads of the blocking pool.
+/// Prefer it over ip_net_ns_add inside a tokio runtime.
+pub async fn ip_net_ns_add_async(ns_name: String) -> Result<()> {
+    validate_ns_name(&ns_name)?;
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(create_net_ns_in_thread(ns_name));
+    });
+    receiver
+        .await
+        .map_err(|_| anyhow!("thread creating the network namespace panicked"))?
+}
+
+fn create_net_ns_in_thread(ns_name: String) -> Result<()> {
+    let netns_path = NetworkNamespace::child_process(ns_name)?;
+    // /proc/self would be the namespace of the main thread
+    let res = unshare(CloneFlags::CLONE_NEWNET).and_then(|_| {
+        mount(
+            Some("/proc/thread-self/ns/net"),
+            netns_path.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+    });
+    if let Err(e) = res {
+        let _ = nix::unistd::unlink(netns_path.as_str());
+        return Err(anyhow!(
+            "Cannot create network namespace \"{}\": {}",
+            netns_path,
+            e
+        ));
+    }
+    Ok(())
+}
+
+/// mkdir -p /etc/netns/name && ip netns add name
+/// etc_files maps file names to their content, e.g. {"resolv.conf": "nameserver 8.8.8.8\n"}.
+/// They are written to /etc/netns/name/ and bind mounted over /etc by ip_net_ns_exec. A file name
+/// must not contain / or .., so that it stays within that directory.
+/// The namespace is only added once every file is written. If that or the namespace fails, the
+/// directory is removed if it was created here. Otherwise the files created here are removed and
+/// the ones overwritten get their previous content back.
+pub fn ip_net_ns_add_with_etc(ns_name: String, etc_files: HashMap<String, String>) -> Result<()> {
+    validate_ns_name(&ns_name)?;
+    if let Some(file_name) = etc_files
+        .keys()
+        .find(|name| name.is_empty() || name.contains(['/', '\0']) || name.contains(".."))
+    {
+        return Err(IprouteError::InvalidArgument(format!(
+            "invalid file name {:?} for /etc/netns/{}",
+            file_name, ns_name
+        ))
+        .into());
+    }
+
+    let etc_dir = format!("/etc/netns/{}", ns_name);
+    let created = !Path::new(&etc_dir).exists();
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o755)
+        .create(&etc_dir)?;
+    // every file touched with what it held before, None when this call created it
+    let mut written: Vec<(String, Option<Vec<u8>>)> = vec![];
+    let mut res = Ok(());
+    for (file_name, content) in etc_files {
+        let path = format!("{}/{}", etc_dir, file_name);
+        let previous = match read(&path) {
+            Ok(previous) => Some(previous),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                res = Err(e.into());
+                break;
+            }
+        };
+        // recorded before writing, a failed write may have truncated the file already
+        written.push((path.clone(), previous));
+        res = write(&path, content).map_err(anyhow::Error::from);
+        if res.is_err() {
+            break;
+        }
+    }
+    if res.is_ok() {
+        res = ip_net_ns_add(ns_name);
+    }
+    if res.is_err() {
+        if created {
+            let _ = remove_dir_all(&etc_dir);
+        } else {
+            for (path, previous) in written {
+                let _ = match previous {
+                    Some(previous) => write(path, previous),
+                    None => remove_file(path),
+                };
+            }
+        }
+    }
+    res
+}
+
+/// ip_net_ns_del on the blocking pool, for callers inside a tokio runtime.
+pub async fn ip_net_ns_del_async(ns_name: String, force: bool) -> Result<()> {
+    tokio::task::spawn_blocking(move || ip_net_ns_del(ns_name, force)).await?
+}
+
+/// ip netns list
+/// The names of the namespace files in NETNS_RUN_DIR, sorted. No namespace was ever added when
+/// the directory does not exist, so that gives an empty list. Names that are not UTF-8 cannot
+/// have been added through this crate and are skipped with a warning.
+pub fn ip_net_ns_list() -> Result<Vec<String>> {
+    let entries = match read_dir(NETNS_RUN_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut names = vec![];
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        match entry.file_name().into_string() {
+            Ok(name) => names.push(name),
+            Err(name) => println!("Skipping namespace with a non UTF-8 name {:?}", name),
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
 /// just ip netns del name
-pub fn ip_net_ns_del(ns_name: String) -> Result<()> {
+/// Fails with IprouteError::NamespaceBusy while the namespace file itself is held open, e.g. by a
+/// process that has not yet entered it. A process merely living in the namespace, e.g. forked by
+/// ip netns exec, does not keep it busy: the file is removed and the namespace lives on, unnamed,
+/// until that process exits. With force the mount is detached anyway (MNT_FORCE | MNT_DETACH),
+/// and goes away once the last user does.
+pub fn ip_net_ns_del(ns_name: String, force: bool) -> Result<()> {
+    validate_ns_name(&ns_name)?;
     let netns_path = format!("{}{}", NETNS_RUN_DIR, ns_name);
 
-    if let Err(e) = nix::mount::umount2(netns_path.as_str(), nix::mount::MntFlags::MNT_DETACH) {
-        println!("Cannot umount namespace file \" {} \": {}", netns_path, e);
+    let flags = if force {
+        MntFlags::MNT_FORCE | MntFlags::MNT_DETACH
+    } else {
+        MntFlags::empty()
+    };
+    match umount2(netns_path.as_str(), flags) {
+        Ok(()) => {}
+        Err(Errno::EBUSY) => return Err(IprouteError::NamespaceBusy(ns_name).into()),
+        Err(e) => println!("Cannot umount namespace file \" {} \": {}", netns_path, e),
     }
 
     if let Err(e) = nix::unistd::unlink(netns_path.as_str()) {
@@ -192,18 +450,176 @@ pub fn ip_net_ns_del(ns_name: String) -> Result<()> {
     Ok(())
 }
 
+fn link_name(link: &LinkMessage) -> Option<&str> {
+    link.nlas.iter().find_map(|nla| match nla {
+        LinkNla::IfName(name) => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+fn ignore_exists(result: Result<()>) -> Result<()> {
+    match result {
+        Err(e)
+            if matches!(
+                e.downcast_ref::<rtnetlink::Error>(),
+                Some(rtnetlink::Error::NetlinkError(msg)) if msg.code == -(Errno::EEXIST as i32)
+            ) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Rewrites the interface index of every rtnexthop of an RTA_MULTIPATH payload from source to
+/// dest index, false when one of them has no counterpart in dest.
+fn remap_nexthops(nexthops: &mut [u8], indexes: &HashMap<u32, (&str, u32)>) -> Result<bool> {
+    let mut offset = 0;
+    while offset < nexthops.len() {
+        let mut nexthop = NextHopBuffer::new_checked(&mut nexthops[offset..])?;
+        let length = nexthop.length() as usize;
+        if length < NEXTHOP_LEN {
+            return Err(anyhow!("invalid nexthop of {} bytes", length));
+        }
+        match indexes.get(&nexthop.interface_id()) {
+            Some((_, dest_index)) => nexthop.set_interface_id(*dest_index),
+            None => return Ok(false),
+        }
+        // rtnexthop entries are aligned to 4 bytes
+        offset += (length + 3) & !3;
+    }
+    Ok(true)
+}
+
+/// sizeof(struct rtnexthop)
+const NEXTHOP_LEN: usize = 8;
+
+/// Create dest_ns and copy the addresses and routes of source_ns into it.
+/// Devices cannot be cloned, so only interfaces that also exist in a fresh namespace (e.g. lo)
+/// get their state, addresses and routes copied. Routes added by the kernel are left to the kernel.
+pub fn ip_net_ns_clone(source_ns: &str, dest_ns: &str) -> Result<()> {
+    validate_ns_name(source_ns)?;
+    validate_ns_name(dest_ns)?;
+    let (links, addresses, routes) = thread_net_ns_block_on(source_ns.to_string(), || async {
+        let (connection, handle, _) = new_connection()?;
+        tokio::spawn(connection);
+        let links: Vec<LinkMessage> = handle.link().get().execute().try_collect().await?;
+        let addresses: Vec<AddressMessage> = handle.address().get().execute().try_collect().await?;
+        let mut routes = get_routes(&handle, IpVersion::V4).await?;
+        routes.extend(get_routes(&handle, IpVersion::V6).await?);
+        Ok((links, addresses, routes))
+    })?;
+
+    ip_net_ns_add(dest_ns.to_string())?;
+
+    let res = thread_net_ns_block_on(dest_ns.to_string(), move || async move {
+        let (connection, mut handle, _) = new_connection()?;
+        tokio::spawn(connection);
+        let dest_links: Vec<LinkMessage> = handle.link().get().execute().try_collect().await?;
+
+        // source index -> (name, dest index)
+        let mut indexes = HashMap::new();
+        for link in &links {
+            let name = match link_name(link) {
+                Some(name) => name,
+                None => continue,
+            };
+            let dest_link = match dest_links.iter().find(|l| link_name(l) == Some(name)) {
+                Some(dest_link) => dest_link,
+                None => continue,
+            };
+            indexes.insert(link.header.index, (name, dest_link.header.index));
+            if link.header.flags & IFF_UP != 0 {
+                IPLink {
+                    action: LinkAction::Set,
+                    name: name.to_string(),
+                    options: vec![Opt::Up],
+                    link_type: None,
+                }
+                .execute(&mut handle)
+                .await?;
+            }
+        }
+
+        for address in addresses {
+            let name = match indexes.get(&address.header.index) {
+                Some((name, _)) => name,
+                None => continue,
+            };
+            let addr = address.nlas.iter().find_map(|nla| match nla {
+                AddressNla::Address(bytes) => ip_from_bytes(bytes),
+                _ => None,
+            });
+            if let Some(addr) = addr {
+                let ip_addr = IPAddr::new(AddrAction::Add, addr, address.header.prefix_len, name);
+                ignore_exists(ip_addr.execute(&mut handle).await)?;
+            }
+        }
+
+        for mut route in routes {
+            if route.header.table == RT_TABLE_LOCAL || route.header.protocol == RTPROT_KERNEL {
+                continue;
+            }
+            let mut routable = true;
+            for nla in route.nlas.iter_mut() {
+                match nla {
+                    RouteNla::Oif(index) | RouteNla::Iif(index) => match indexes.get(index) {
+                        Some((_, dest_index)) => *index = *dest_index,
+                        None => routable = false,
+                    },
+                    RouteNla::MultiPath(nexthops) => {
+                        routable &= remap_nexthops(nexthops, &indexes)?;
+                    }
+                    _ => {}
+                }
+            }
+            if routable {
+                let ip_route = IPRoute {
+                    action: RouteAction::Add,
+                    msg: route,
+                };
+                ignore_exists(ip_route.execute(&mut handle).await)?;
+            }
+        }
+        Ok(())
+    });
+    // a half copied namespace is no clone
+    res.map_err(|err| {
+        let undo = ip_net_ns_del(dest_ns.to_string(), false);
+        undone(
+            err,
+            undo,
+            &format!("the creation of network namespace {}", dest_ns),
+        )
+    })
+}
+
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
     use std::ffi::OsString;
     use std::path::Path;
 
+    use anyhow::anyhow;
     use futures::stream::TryStreamExt;
+    use netlink_packet_route::nlas::address::Nla as AddressNla;
+    use netlink_packet_route::nlas::route::Nla as RouteNla;
     use netlink_packet_route::LinkMessage;
-    use rtnetlink::{new_connection, Error, Handle};
+    use nix::errno::Errno;
+    use rtnetlink::{new_connection, Error, Handle, IpVersion};
     use serial_test::serial;
     use tokio;
 
-    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, ip_net_ns_exec, set_net_ns};
+    use crate::error::IprouteError;
+    use crate::ip::ipaddr::{get_addresses, AddrAction, IPAddr};
+    use crate::ip::iplink::{get_link, Action, IPLink, Opt};
+    use crate::ip::ipnetns::{
+        ip_net_ns_add, ip_net_ns_add_async, ip_net_ns_add_with_etc, ip_net_ns_clone, ip_net_ns_del,
+        ip_net_ns_del_async, ip_net_ns_exec, ip_net_ns_exec_by_pid, ip_net_ns_list, set_net_ns,
+        thread_net_ns_block_on, thread_net_ns_exec, NETNS_RUN_DIR,
+    };
+    use crate::ip::iproute::{get_routes, route_add_ecmp, Nexthop};
+    use crate::ip::testutil::add_veth;
 
     async fn get_links(handle: Handle) -> Result<Vec<LinkMessage>, Error> {
         let mut links = handle.link().get().execute();
@@ -253,7 +669,7 @@ mod test {
                 let msgs_out =
                     futures::executor::block_on(async { get_links(handle).await.unwrap() });
                 let devices_out = std::fs::read_dir(Path::new("/sys/class/net/")).unwrap();
-                ip_net_ns_del(ns_name).unwrap();
+                ip_net_ns_del(ns_name, false).unwrap();
                 assert_ne!(msgs_out, msgs_in);
                 assert_eq!(msgs_in.len(), 1);
 
@@ -267,6 +683,30 @@ mod test {
             });
     }
 
+    #[test]
+    #[serial]
+    fn test_thread_net_ns_exec() {
+        ip_net_ns_add("vnetns22".to_string()).unwrap();
+        let devices = thread_net_ns_exec("vnetns22".to_string(), || {
+            // two header lines, then lo
+            Ok(std::fs::read_to_string("/proc/thread-self/net/dev")?
+                .lines()
+                .count())
+        })
+        .join()
+        .unwrap();
+        let err = thread_net_ns_exec("vnetns22".to_string(), || -> anyhow::Result<()> {
+            Err(anyhow!("failed in vnetns22"))
+        })
+        .join()
+        .unwrap()
+        .unwrap_err();
+        ip_net_ns_del("vnetns22".to_string(), false).unwrap();
+
+        assert_eq!(devices.unwrap(), 3);
+        assert_eq!(err.to_string(), "failed in vnetns22");
+    }
+
     #[test]
     #[serial]
     fn test_ip_net_ns_exec() {
@@ -278,7 +718,7 @@ mod test {
                 std::thread::spawn(|| {
                     let ns_name = "vnetns0".to_string();
                     ip_net_ns_add(ns_name.clone()).unwrap();
-                    ip_net_ns_exec(ns_name.clone(), || {
+                    let count = ip_net_ns_exec(ns_name.clone(), || {
                         tokio::runtime::Builder::new_multi_thread()
                             .enable_all()
                             .build()
@@ -287,15 +727,407 @@ mod test {
                                 let (connection, handle, _) = new_connection()?;
                                 tokio::spawn(connection);
                                 let msgs = get_links(handle).await?;
-                                assert_eq!(msgs.len(), 1);
-                                Ok(())
+                                Ok(msgs.len())
                             })
-                    })
-                    .unwrap();
-                    ip_net_ns_del(ns_name).unwrap();
+                    });
+                    let err = ip_net_ns_exec(ns_name.clone(), || -> anyhow::Result<u32> {
+                        Err(anyhow!("no lo in {}", "vnetns0"))
+                    });
+                    ip_net_ns_del(ns_name, false).unwrap();
+
+                    assert_eq!(count.unwrap(), 1);
+                    assert_eq!(err.unwrap_err().to_string(), "no lo in vnetns0");
                 })
                 .join()
                 .unwrap();
             });
     }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_clone_failure() {
+        ip_net_ns_add("vnetns34".to_string()).unwrap();
+        // the route through lo keeps the address of the veth as its source, which is not copied
+        let setup = thread_net_ns_block_on("vnetns34".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            IPLink {
+                action: Action::Set,
+                name: "lo".to_string(),
+                options: vec![Opt::Up],
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await?;
+            add_veth(&mut handle, "clonefail0", "clonefail1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.34.0.1".parse()?, 24, "clonefail0")
+                .execute(&mut handle)
+                .await?;
+            let lo = get_link(&handle, "lo").await?;
+            let mut route = handle
+                .route()
+                .add()
+                .v4()
+                .destination_prefix("203.0.113.0".parse()?, 24)
+                .output_interface(lo.header.index);
+            route
+                .message_mut()
+                .nlas
+                .push(RouteNla::PrefSource(vec![10, 34, 0, 1]));
+            route.execute().await?;
+            Ok(())
+        });
+
+        let res = setup.map(|_| ip_net_ns_clone("vnetns34", "vnetns35"));
+        let listed = ip_net_ns_list();
+        ip_net_ns_del("vnetns34".to_string(), false).unwrap();
+
+        let err = res.unwrap().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NetlinkError(msg)) if msg.code == -(Errno::EINVAL as i32)
+        ));
+        assert!(!listed.unwrap().contains(&"vnetns35".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_clone() {
+        ip_net_ns_add("vnetns4".to_string()).unwrap();
+        thread_net_ns_block_on("vnetns4".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            IPLink {
+                action: Action::Set,
+                name: "lo".to_string(),
+                options: vec![Opt::Up],
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await?;
+            IPAddr::new(AddrAction::Add, "10.4.0.1".parse()?, 32, "lo")
+                .execute(&mut handle)
+                .await?;
+            let lo = get_link(&handle, "lo").await?;
+            handle
+                .route()
+                .add()
+                .v4()
+                .destination_prefix("203.0.113.0".parse()?, 24)
+                .output_interface(lo.header.index)
+                .execute()
+                .await?;
+            Ok(())
+        })
+        .unwrap();
+
+        ip_net_ns_clone("vnetns4", "vnetns5").unwrap();
+
+        let (addresses, routes) = thread_net_ns_block_on("vnetns5".to_string(), || async {
+            let (connection, handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            let addresses = get_addresses(&handle, "lo").await?;
+            let routes = get_routes(&handle, IpVersion::V4).await?;
+            Ok((addresses, routes))
+        })
+        .unwrap();
+        ip_net_ns_del("vnetns4".to_string(), false).unwrap();
+        ip_net_ns_del("vnetns5".to_string(), false).unwrap();
+
+        assert!(addresses.iter().any(|address| address
+            .nlas
+            .iter()
+            .any(|nla| matches!(nla, AddressNla::Address(addr) if addr == &[10, 4, 0, 1]))));
+        assert!(routes
+            .iter()
+            .any(|route| route.header.destination_prefix_length == 24
+                && route.nlas.iter().any(
+                    |nla| matches!(nla, RouteNla::Destination(dst) if dst == &[203, 0, 113, 0])
+                )));
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_clone_multipath() {
+        ip_net_ns_add("vnetns48".to_string()).unwrap();
+        let setup = thread_net_ns_block_on("vnetns48".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            IPLink {
+                action: Action::Set,
+                name: "lo".to_string(),
+                options: vec![Opt::Up],
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await?;
+            add_veth(&mut handle, "clonemp0", "clonemp1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.48.0.1".parse()?, 24, "clonemp0")
+                .execute(&mut handle)
+                .await?;
+            let nexthop = |gateway: Option<&str>, dev: &str, weight| Nexthop {
+                gateway: gateway.map(|gateway| gateway.parse().unwrap()),
+                dev: dev.to_string(),
+                weight,
+                flags: 0,
+            };
+            // lo exists in the clone, clonemp0 does not
+            route_add_ecmp(
+                &mut handle,
+                ("192.0.2.0".parse()?, 24),
+                vec![nexthop(None, "lo", 1), nexthop(None, "lo", 2)],
+            )
+            .await?;
+            route_add_ecmp(
+                &mut handle,
+                ("198.51.100.0".parse()?, 24),
+                vec![
+                    nexthop(None, "lo", 1),
+                    nexthop(Some("10.48.0.2"), "clonemp0", 1),
+                ],
+            )
+            .await?;
+            Ok(())
+        });
+
+        let routes = setup
+            .and_then(|_| ip_net_ns_clone("vnetns48", "vnetns49"))
+            .and_then(|_| {
+                thread_net_ns_block_on("vnetns49".to_string(), || async {
+                    let (connection, handle, _) = new_connection()?;
+                    tokio::spawn(connection);
+                    get_routes(&handle, IpVersion::V4).await
+                })
+            });
+        ip_net_ns_del("vnetns48".to_string(), false).unwrap();
+        // a failed clone removes vnetns49 itself
+        if ip_net_ns_list().unwrap().contains(&"vnetns49".to_string()) {
+            ip_net_ns_del("vnetns49".to_string(), false).unwrap();
+        }
+
+        let routes = routes.unwrap();
+        let copied = |octets: &[u8]| {
+            routes.iter().any(|route| {
+                route.nlas.iter().any(
+                    |nla| matches!(nla, RouteNla::Destination(dst) if dst.as_slice() == octets),
+                ) && route
+                    .nlas
+                    .iter()
+                    .any(|nla| matches!(nla, RouteNla::MultiPath(_)))
+            })
+        };
+        assert!(copied(&[192, 0, 2, 0]));
+        assert!(!copied(&[198, 51, 100, 0]));
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_add_with_etc() {
+        let resolv_conf = "nameserver 192.0.2.53\n";
+        let mut etc_files = HashMap::new();
+        etc_files.insert("resolv.conf".to_string(), resolv_conf.to_string());
+        ip_net_ns_add_with_etc("vnetns9".to_string(), etc_files).unwrap();
+
+        let result = ip_net_ns_exec("vnetns9".to_string(), move || {
+            if std::fs::read_to_string("/etc/resolv.conf")? != resolv_conf {
+                return Err(anyhow!("/etc/resolv.conf was not bind mounted"));
+            }
+            Ok(())
+        });
+        ip_net_ns_del("vnetns9".to_string(), false).unwrap();
+        std::fs::remove_dir_all("/etc/netns/vnetns9").unwrap();
+
+        result.unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_add_with_etc_failure() {
+        for file_name in ["../resolv.conf", "a/b", "..", ""] {
+            let mut etc_files = HashMap::new();
+            etc_files.insert(file_name.to_string(), String::new());
+            let err = ip_net_ns_add_with_etc("vnetns33".to_string(), etc_files).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+        assert!(!Path::new("/etc/netns/vnetns33").exists());
+
+        // longer than NAME_MAX
+        let mut etc_files = HashMap::new();
+        etc_files.insert("resolv.conf".to_string(), String::new());
+        etc_files.insert("h".repeat(256), String::new());
+        let res = ip_net_ns_add_with_etc("vnetns33".to_string(), etc_files);
+        let listed = ip_net_ns_list().unwrap();
+
+        assert!(res.is_err());
+        assert!(!Path::new("/etc/netns/vnetns33").exists());
+        assert!(!listed.contains(&"vnetns33".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_add_with_etc_restore() {
+        std::fs::create_dir_all("/etc/netns/vnetns47").unwrap();
+        std::fs::write("/etc/netns/vnetns47/resolv.conf", "nameserver 192.0.2.1\n").unwrap();
+        // the namespace already exists, so adding it again fails once the files are written
+        ip_net_ns_add("vnetns47".to_string()).unwrap();
+        let mut etc_files = HashMap::new();
+        etc_files.insert(
+            "resolv.conf".to_string(),
+            "nameserver 192.0.2.2\n".to_string(),
+        );
+        etc_files.insert("hosts".to_string(), "192.0.2.3 host\n".to_string());
+        let res = ip_net_ns_add_with_etc("vnetns47".to_string(), etc_files);
+        let resolv_conf = std::fs::read_to_string("/etc/netns/vnetns47/resolv.conf");
+        let hosts = Path::new("/etc/netns/vnetns47/hosts").exists();
+        ip_net_ns_del("vnetns47".to_string(), false).unwrap();
+        std::fs::remove_dir_all("/etc/netns/vnetns47").unwrap();
+
+        assert!(res.is_err());
+        assert_eq!(resolv_conf.unwrap(), "nameserver 192.0.2.1\n");
+        assert!(!hosts);
+    }
+
+    #[test]
+    fn test_ns_name_traversal() {
+        for name in ["../../etc/shadow", "a/b", "..", "-n", "ns\0"] {
+            let err = ip_net_ns_add(name.to_string()).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_list() {
+        ip_net_ns_add("vnetns16".to_string()).unwrap();
+        let added = ip_net_ns_list();
+        ip_net_ns_del("vnetns16".to_string(), false).unwrap();
+        let deleted = ip_net_ns_list().unwrap();
+
+        assert!(added.unwrap().contains(&"vnetns16".to_string()));
+        assert!(!deleted.contains(&"vnetns16".to_string()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn test_ip_net_ns_add_async() {
+        ip_net_ns_add_async("vnetns19".to_string()).await.unwrap();
+        let added = ip_net_ns_list().unwrap();
+        // a namespace of its own: only lo, and not the links of the caller's namespace
+        let links = thread_net_ns_block_on("vnetns19".to_string(), || async {
+            let (connection, handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            Ok(get_links(handle).await?)
+        });
+        ip_net_ns_del_async("vnetns19".to_string(), false)
+            .await
+            .unwrap();
+        let deleted = ip_net_ns_list().unwrap();
+
+        assert!(added.contains(&"vnetns19".to_string()));
+        assert!(!deleted.contains(&"vnetns19".to_string()));
+        assert_eq!(links.unwrap().len(), 1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_del_busy() {
+        ip_net_ns_add("vnetns20".to_string()).unwrap();
+        // the child holds the namespace file open as its stdin
+        let ns_file = std::fs::File::open(format!("{}vnetns20", NETNS_RUN_DIR)).unwrap();
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .stdin(ns_file)
+            .spawn()
+            .unwrap();
+
+        let busy = ip_net_ns_del("vnetns20".to_string(), false);
+        let forced = ip_net_ns_del("vnetns20".to_string(), true);
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert_eq!(
+            busy.unwrap_err().downcast_ref::<IprouteError>(),
+            Some(&IprouteError::NamespaceBusy("vnetns20".to_string()))
+        );
+        forced.unwrap();
+        assert!(!ip_net_ns_list().unwrap().contains(&"vnetns20".to_string()));
+    }
+
+    /// Waits for the process pid to leave the network namespace of the caller.
+    fn wait_for_other_net_ns(pid: u32) {
+        let own = std::fs::read_link("/proc/thread-self/ns/net").unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while std::fs::read_link(format!("/proc/{}/ns/net", pid)).unwrap() == own {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "{} did not enter a namespace",
+                pid
+            );
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_del_with_process() {
+        ip_net_ns_add("vnetns36".to_string()).unwrap();
+        let mut child = std::process::Command::new("ip")
+            .args(["netns", "exec", "vnetns36", "sleep", "10"])
+            .spawn()
+            .unwrap();
+        wait_for_other_net_ns(child.id());
+
+        let deleted = ip_net_ns_del("vnetns36".to_string(), false);
+        let listed = ip_net_ns_list();
+        // the namespace outlives its name
+        let entered = std::fs::read_link(format!("/proc/{}/ns/net", child.id()));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        if deleted.is_err() {
+            ip_net_ns_del("vnetns36".to_string(), true).unwrap();
+        }
+
+        deleted.unwrap();
+        assert!(!listed.unwrap().contains(&"vnetns36".to_string()));
+        assert_ne!(
+            entered.unwrap(),
+            std::fs::read_link("/proc/thread-self/ns/net").unwrap()
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_ip_net_ns_exec_by_pid() {
+        ip_net_ns_add("vnetns21".to_string()).unwrap();
+        let mut child = std::process::Command::new("ip")
+            .args(["netns", "exec", "vnetns21", "sleep", "10"])
+            .spawn()
+            .unwrap();
+        wait_for_other_net_ns(child.id());
+
+        let links = ip_net_ns_exec_by_pid(child.id(), || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(async {
+                    let (connection, handle, _) = new_connection()?;
+                    tokio::spawn(connection);
+                    Ok(get_links(handle).await?)
+                })
+        });
+        child.kill().unwrap();
+        child.wait().unwrap();
+        ip_net_ns_del("vnetns21".to_string(), false).unwrap();
+
+        // only the lo of the namespace
+        assert_eq!(links.unwrap().len(), 1);
+        let err = ip_net_ns_exec_by_pid(u32::MAX, || Ok(())).unwrap_err();
+        assert!(err.to_string().contains("no process"));
+    }
 }
@@ -1,9 +1,24 @@
-use anyhow::Result;
-use futures::{StreamExt, TryStreamExt};
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{anyhow, Result};
+use futures::future::ready;
+use futures::{Stream, StreamExt, TryStreamExt};
+use ipnet::IpNet;
 use netlink_packet_route::constants::*;
-use netlink_packet_route::{NetlinkMessage, NetlinkPayload, RouteMessage, RtnlMessage};
+use netlink_packet_route::nlas::route::{Metrics, NextHop, NextHopFlags, Nla};
+use netlink_packet_route::traits::Emitable;
+use netlink_packet_route::{NetlinkMessage, NetlinkPayload, RouteFlags, RouteMessage, RtnlMessage};
+use nix::errno::Errno;
+use rtnetlink::constants::{RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_ROUTE};
 use rtnetlink::{Handle, IpVersion};
 
+use crate::error::IprouteError;
+use crate::ip::iplink::{
+    emit_nlas, request_ack, resolve_link_index, retry_dump_on_enomem, subscribe, DumpHandle,
+    NetlinkConnection,
+};
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct IPRoute {
     pub action: Action,
@@ -12,38 +27,451 @@ pub struct IPRoute {
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Action {
+    /// Fails when the route exists.
     Add,
     Del,
+    /// ip route replace: adds the route, or replaces the one to the same destination, table,
+    /// tos and priority.
+    Replace,
+    /// ip route change: replaces an existing route, fails when there is none.
+    Change,
 }
 
 impl IPRoute {
     pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
         let mut req = match self.action {
             Action::Del => NetlinkMessage::from(RtnlMessage::DelRoute(self.msg.clone())),
-            Action::Add => NetlinkMessage::from(RtnlMessage::NewRoute(self.msg.clone())),
+            Action::Add | Action::Replace | Action::Change => {
+                NetlinkMessage::from(RtnlMessage::NewRoute(self.msg.clone()))
+            }
         };
 
-        if self.action == Action::Add {
-            req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE
-        } else {
-            req.header.flags = NLM_F_REQUEST | NLM_F_ACK
+        req.header.flags = match self.action {
+            Action::Add => NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
+            Action::Del => NLM_F_REQUEST | NLM_F_ACK,
+            Action::Replace => NLM_F_REQUEST | NLM_F_ACK | NLM_F_REPLACE | NLM_F_CREATE,
+            Action::Change => NLM_F_REQUEST | NLM_F_ACK | NLM_F_REPLACE,
         };
 
-        let mut response = handle.request(req)?;
-        while let Some(message) = response.next().await {
-            if let NetlinkPayload::Error(err) = message.payload {
-                return Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err)));
+        request_ack(handle, req).await
+    }
+}
+
+/// RTPROT_*, who installed a route.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum RouteProtocol {
+    Kernel,
+    Boot,
+    Static,
+    Dhcp,
+    Other(u8),
+}
+
+impl RouteProtocol {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            RouteProtocol::Kernel => RTPROT_KERNEL,
+            RouteProtocol::Boot => RTPROT_BOOT,
+            RouteProtocol::Static => RTPROT_STATIC,
+            RouteProtocol::Dhcp => RTPROT_DHCP,
+            RouteProtocol::Other(protocol) => *protocol,
+        }
+    }
+}
+
+/// RT_SCOPE_*, how far the destination is.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum RouteScope {
+    Universe,
+    Site,
+    /// On the link, reached without a gateway.
+    Link,
+    Host,
+    Nowhere,
+}
+
+impl RouteScope {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            RouteScope::Universe => RT_SCOPE_UNIVERSE,
+            RouteScope::Site => RT_SCOPE_SITE,
+            RouteScope::Link => RT_SCOPE_LINK,
+            RouteScope::Host => RT_SCOPE_HOST,
+            RouteScope::Nowhere => RT_SCOPE_NOWHERE,
+        }
+    }
+}
+
+/// RTN_*, what happens to the packets of a route.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum RouteType {
+    Unicast,
+    Local,
+    Broadcast,
+    Blackhole,
+    Unreachable,
+    Prohibit,
+    /// Continue the lookup with the next rule.
+    Throw,
+    Anycast,
+    Multicast,
+}
+
+impl RouteType {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            RouteType::Unicast => RTN_UNICAST,
+            RouteType::Local => RTN_LOCAL,
+            RouteType::Broadcast => RTN_BROADCAST,
+            RouteType::Blackhole => RTN_BLACKHOLE,
+            RouteType::Unreachable => RTN_UNREACHABLE,
+            RouteType::Prohibit => RTN_PROHIBIT,
+            RouteType::Throw => RTN_THROW,
+            RouteType::Anycast => RTN_ANYCAST,
+            RouteType::Multicast => RTN_MULTICAST,
+        }
+    }
+
+    /// Whether the packets are dropped or handed back to the rules rather than sent somewhere,
+    /// so that a gateway or dev is meaningless.
+    pub fn has_no_nexthop(&self) -> bool {
+        matches!(
+            self,
+            RouteType::Blackhole | RouteType::Unreachable | RouteType::Prohibit | RouteType::Throw
+        )
+    }
+}
+
+/// ip route add [type kind] destination/prefix_len [via gateway] [dev dev] [table table]
+///     [metric metric] [proto proto] [scope scope]
+/// Meant to be filled with struct update syntax:
+/// IPRouteBuilder { destination: Some(dst), prefix_len: 24, gateway: Some(gw), ..Default::default() }.build(&handle).await
+/// The default is a unicast route of the main table, installed at boot, with neither destination
+/// (i.e. a default route) nor gateway and dev.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPRouteBuilder {
+    pub action: Action,
+    pub destination: Option<IpAddr>,
+    pub prefix_len: u8,
+    pub gateway: Option<IpAddr>,
+    pub dev: Option<String>,
+    /// RT_TABLE_LOCAL (255) and RT_TABLE_DEFAULT (253) are reserved: the kernel fills the local
+    /// table from the addresses of the links, and the default table is looked up last by the
+    /// default rules. Routes are not meant to be written to either.
+    pub table: u32,
+    /// 0 leaves the priority out.
+    pub metric: u32,
+    pub proto: RouteProtocol,
+    pub scope: RouteScope,
+    pub kind: RouteType,
+    /// Multipath (ECMP) next hops, instead of gateway and dev.
+    pub nexthops: Vec<Nexthop>,
+    /// ip -4|-6 route add ..., the family of a route with neither destination nor gateway, e.g.
+    /// default dev wg0 or blackhole default. It must agree with destination and gateway otherwise.
+    pub family: Option<IpVersion>,
+    /// RTAX_* values, sent as RTA_METRICS. See the rtt, rttvar, cwnd, advmss and hoplimit
+    /// methods for their units.
+    pub metrics: Vec<Metrics>,
+}
+
+/// A next hop of a multipath route: ip route add ... nexthop via gateway dev dev weight weight
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Nexthop {
+    pub gateway: Option<IpAddr>,
+    pub dev: String,
+    /// Share of the flows, 1 to 255.
+    pub weight: u8,
+    /// RTNH_F_*, e.g. RTNH_F_ONLINK.
+    pub flags: u32,
+}
+
+impl Nexthop {
+    /// interface_id is the index of dev.
+    fn emit(&self, interface_id: u32) -> Result<Vec<u8>> {
+        if self.weight == 0 {
+            return Err(IprouteError::InvalidArgument(format!(
+                "weight 0 for the nexthop through {}",
+                self.dev
+            ))
+            .into());
+        }
+        let flags = u8::try_from(self.flags)
+            .ok()
+            .and_then(NextHopFlags::from_bits)
+            .ok_or_else(|| {
+                IprouteError::InvalidArgument(format!("invalid nexthop flags {:#x}", self.flags))
+            })?;
+        let nexthop = NextHop {
+            flags,
+            hops: self.weight - 1,
+            interface_id,
+            gateway: self.gateway,
+        };
+        let mut buffer = vec![0; nexthop.buffer_len()];
+        nexthop.emit(&mut buffer);
+        Ok(buffer)
+    }
+}
+
+impl Default for IPRouteBuilder {
+    fn default() -> Self {
+        IPRouteBuilder {
+            action: Action::Add,
+            destination: None,
+            prefix_len: 0,
+            gateway: None,
+            dev: None,
+            table: RT_TABLE_MAIN as u32,
+            metric: 0,
+            proto: RouteProtocol::Boot,
+            scope: RouteScope::Universe,
+            kind: RouteType::Unicast,
+            nexthops: vec![],
+            metrics: vec![],
+            family: None,
+        }
+    }
+}
+
+impl IPRouteBuilder {
+    /// ip route add ... metric metric, the priority among routes to the same destination, lowest
+    /// first.
+    pub fn metric(mut self, metric: u32) -> IPRouteBuilder {
+        self.metric = metric;
+        self
+    }
+
+    /// ip route add ... rtt rtt_ms, the initial round trip time estimate.
+    pub fn rtt(mut self, rtt_ms: u32) -> IPRouteBuilder {
+        // the kernel keeps it in units of 1/8 ms
+        self.metrics.push(Metrics::Rtt(rtt_ms.saturating_mul(8)));
+        self
+    }
+
+    /// ip route add ... rttvar rttvar_ms, the initial round trip time variance estimate.
+    pub fn rttvar(mut self, rttvar_ms: u32) -> IPRouteBuilder {
+        // in units of 1/4 ms
+        self.metrics
+            .push(Metrics::RttVar(rttvar_ms.saturating_mul(4)));
+        self
+    }
+
+    /// ip route add ... cwnd cwnd, the congestion window clamp in packets. The kernel ignores it
+    /// unless locked, so this is ip route add ... cwnd lock cwnd: see lock.
+    pub fn cwnd(mut self, cwnd: u32) -> IPRouteBuilder {
+        self.metrics.push(Metrics::Cwnd(cwnd));
+        self.lock(RTAX_CWND)
+    }
+
+    /// ip route add ... metric lock value, for metric an RTAX_* such as RTAX_MTU: sets its bit in
+    /// RTAX_LOCK so that the kernel keeps the value instead of updating it from path discovery.
+    pub fn lock(mut self, metric: u16) -> IPRouteBuilder {
+        let bit = 1 << metric;
+        match self.metrics.iter_mut().find_map(|metric| match metric {
+            Metrics::Lock(locked) => Some(locked),
+            _ => None,
+        }) {
+            Some(locked) => *locked |= bit,
+            None => self.metrics.push(Metrics::Lock(bit)),
+        }
+        self
+    }
+
+    /// ip route add ... advmss advmss, the MSS advertised to TCP peers on this route.
+    pub fn advmss(mut self, advmss: u32) -> IPRouteBuilder {
+        self.metrics.push(Metrics::Advmss(advmss));
+        self
+    }
+
+    /// ip route add ... hoplimit hoplimit, the TTL or hop limit of the packets sent on this route.
+    pub fn hoplimit(mut self, hoplimit: u32) -> IPRouteBuilder {
+        self.metrics.push(Metrics::Hoplimit(hoplimit));
+        self
+    }
+
+    /// Appends a multipath next hop.
+    pub fn nexthop(mut self, nexthop: Nexthop) -> IPRouteBuilder {
+        self.nexthops.push(nexthop);
+        self
+    }
+
+    /// The family is the one of destination, or of gateway for a default route, or else family.
+    /// dev and the devs of the nexthops are resolved to their indexes through handle, so they
+    /// must exist in its network namespace.
+    pub async fn build(self, handle: &Handle) -> Result<IPRoute> {
+        let invalid =
+            |message: String| -> anyhow::Error { IprouteError::InvalidArgument(message).into() };
+        let family_of = |addr: &IpAddr| match addr {
+            IpAddr::V4(_) => AF_INET,
+            IpAddr::V6(_) => AF_INET6,
+        };
+        if self.kind.has_no_nexthop()
+            && (self.gateway.is_some() || self.dev.is_some() || !self.nexthops.is_empty())
+        {
+            return Err(invalid(format!(
+                "a {:?} route has no gateway or dev",
+                self.kind
+            )));
+        }
+        if !self.nexthops.is_empty() && (self.gateway.is_some() || self.dev.is_some()) {
+            return Err(invalid(
+                "a multipath route has its gateways and devs in its nexthops".to_string(),
+            ));
+        }
+        let gateway = self
+            .gateway
+            .or_else(|| self.nexthops.iter().find_map(|nexthop| nexthop.gateway));
+        for nexthop_gateway in self.nexthops.iter().filter_map(|nexthop| nexthop.gateway) {
+            if gateway.map(|gateway| gateway.is_ipv4()) != Some(nexthop_gateway.is_ipv4()) {
+                return Err(invalid(format!(
+                    "nexthop gateways {} and {:?} are not of the same family",
+                    nexthop_gateway, gateway
+                )));
+            }
+        }
+        let family = match (&self.destination, &gateway) {
+            (Some(destination), Some(gateway)) if family_of(destination) != family_of(gateway) => {
+                return Err(invalid(format!(
+                    "gateway {} is not in the family of {}",
+                    gateway, destination
+                )))
+            }
+            (Some(addr), _) | (None, Some(addr)) => family_of(addr),
+            (None, None) => match self.family {
+                Some(IpVersion::V4) => AF_INET,
+                Some(IpVersion::V6) => AF_INET6,
+                None => {
+                    return Err(invalid(
+                        "a route needs a destination, a gateway or a family".to_string(),
+                    ))
+                }
+            },
+        };
+        match (&self.family, family) {
+            (Some(IpVersion::V4), AF_INET6) | (Some(IpVersion::V6), AF_INET) => {
+                return Err(invalid(format!(
+                    "the destination and gateway of the route are not of family {:?}",
+                    self.family
+                )))
+            }
+            _ => {}
+        }
+        let max_prefix_len = if family == AF_INET { 32 } else { 128 };
+        if self.prefix_len > max_prefix_len || (self.destination.is_none() && self.prefix_len != 0)
+        {
+            return Err(invalid(format!(
+                "invalid prefix length {}",
+                self.prefix_len
+            )));
+        }
+
+        let mut msg = RouteMessage::default();
+        msg.header.address_family = family as u8;
+        msg.header.destination_prefix_length = self.prefix_len;
+        msg.header.protocol = self.proto.as_u8();
+        msg.header.scope = self.scope.as_u8();
+        msg.header.kind = self.kind.as_u8();
+        // ids past 255 do not fit the header and are only given as RTA_TABLE
+        msg.header.table = u8::try_from(self.table).unwrap_or(RT_TABLE_UNSPEC);
+        msg.nlas.push(Nla::Table(self.table));
+        if let Some(destination) = self.destination {
+            msg.nlas.push(Nla::Destination(ip_octets(destination)));
+        }
+        if let Some(gateway) = self.gateway {
+            msg.nlas.push(Nla::Gateway(ip_octets(gateway)));
+        }
+        if let Some(dev) = &self.dev {
+            msg.nlas
+                .push(Nla::Oif(resolve_link_index(handle, dev).await?));
+        }
+        if self.metric != 0 {
+            msg.nlas.push(Nla::Priority(self.metric));
+        }
+        if !self.metrics.is_empty() {
+            msg.nlas.push(Nla::Metrics(emit_nlas(&self.metrics)));
+        }
+        if !self.nexthops.is_empty() {
+            let mut multipath = vec![];
+            for nexthop in &self.nexthops {
+                let index = resolve_link_index(handle, &nexthop.dev).await?;
+                multipath.extend(nexthop.emit(index)?);
             }
+            msg.nlas.push(Nla::MultiPath(multipath));
         }
+        Ok(IPRoute {
+            action: self.action,
+            msg,
+        })
+    }
+}
+
+pub async fn get_routes<'a>(
+    handle: impl Into<DumpHandle<'a>>,
+    ip_version: IpVersion,
+) -> Result<Vec<RouteMessage>> {
+    let handle = handle.into();
+    retry_dump_on_enomem(handle.rcvbufs(), |rcvbuf| {
+        let ip_version = ip_version.clone();
+        async move {
+            let routes_exec = handle.attempt(rcvbuf)?.route().get(ip_version).execute();
+            let routes: Vec<RouteMessage> = routes_exec.try_collect().await?;
+            Ok(routes)
+        }
+    })
+    .await
+}
 
-        Ok(())
+/// ip route add dst nexthop ... nexthop ..., in the main table.
+pub async fn route_add_ecmp(
+    handle: &mut Handle,
+    dst: (IpAddr, u8),
+    nexthops: Vec<Nexthop>,
+) -> Result<()> {
+    IPRouteBuilder {
+        destination: Some(dst.0),
+        prefix_len: dst.1,
+        nexthops,
+        ..Default::default()
     }
+    .build(handle)
+    .await?
+    .execute(handle)
+    .await
 }
 
-pub async fn get_routes(handle: &Handle, ip_version: IpVersion) -> Result<Vec<RouteMessage>> {
-    let routes_exec = handle.route().get(ip_version).execute();
-    let routes: Vec<RouteMessage> = routes_exec.try_collect().await?;
-    Ok(routes)
+/// The table of route, from RTA_TABLE when present as ids past 255 do not fit the header.
+pub fn route_table(route: &RouteMessage) -> u32 {
+    route
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or(route.header.table as u32)
+}
+
+/// ip route show table table
+pub async fn get_routes_in_table(
+    handle: &Handle,
+    ip_version: IpVersion,
+    table: u32,
+) -> Result<Vec<RouteMessage>> {
+    Ok(get_routes(handle, ip_version)
+        .await?
+        .into_iter()
+        .filter(|route| route_table(route) == table)
+        .collect())
+}
+
+/// ip route flush table table
+/// Returns how many routes were deleted.
+pub async fn del_routes_in_table(
+    handle: &Handle,
+    ip_version: IpVersion,
+    table: u32,
+) -> Result<u32> {
+    Ok(flush_routes(handle, ip_version, Some(table), None, None).await? as u32)
 }
 
 pub async fn del_routes(handle: &Handle, route_msg: RouteMessage) -> Result<()> {
@@ -51,12 +479,337 @@ pub async fn del_routes(handle: &Handle, route_msg: RouteMessage) -> Result<()>
     Ok(())
 }
 
+/// ip route flush [table table] [proto proto] [scope scope]
+/// Deletes the routes of table matching all the given predicates, returns how many were deleted.
+/// As with ip route flush, table defaults to the main table: the local table (255) is only
+/// flushed when named. Routes gone before being deleted, e.g. with their link, are skipped.
+pub async fn flush_routes(
+    handle: &Handle,
+    ip_version: IpVersion,
+    table: Option<u32>,
+    proto: Option<u8>,
+    scope: Option<u8>,
+) -> Result<usize> {
+    let table = table.unwrap_or(RT_TABLE_MAIN as u32);
+    let mut deleted = 0;
+    for route in get_routes(handle, ip_version).await? {
+        if route_table(&route) != table
+            || proto.is_some_and(|proto| route.header.protocol != proto)
+            || scope.is_some_and(|scope| route.header.scope != scope)
+        {
+            continue;
+        }
+        match del_routes(handle, route).await {
+            Ok(()) => deleted += 1,
+            Err(e) if route_gone(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(deleted)
+}
+
+/// Whether deleting a dumped route failed because it was removed in the meantime: the kernel
+/// answers ESRCH for a missing route, ENOENT for a missing table.
+fn route_gone(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<rtnetlink::Error>(),
+        Some(rtnetlink::Error::NetlinkError(msg))
+            if msg.code == -(Errno::ESRCH as i32) || msg.code == -(Errno::ENOENT as i32)
+    )
+}
+
+/// ip route flush exact 0/0 table all
+/// Deletes the default route of every table for version, returns how many were deleted.
+pub async fn flush_default_routes(handle: &Handle, version: IpVersion) -> Result<u32> {
+    let mut deleted = 0;
+    for route in get_routes(handle, version).await? {
+        if route.header.destination_prefix_length == 0 {
+            match del_routes(handle, route).await {
+                Ok(()) => deleted += 1,
+                Err(e) if route_gone(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+pub fn ip_octets(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+/// A notification of ip monitor route.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum RouteEvent {
+    /// Also sent when a route is replaced.
+    Added(RouteMessage),
+    Deleted(RouteMessage),
+}
+
+/// ip monitor route, of both IPv4 and IPv6 and every table, received as subscribe(connection,
+/// RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE) until the stream is dropped, in the network namespace
+/// connection was opened in.
+/// The stream is Unpin, so it can be polled by reference, e.g. in select!.
+pub fn route_event_stream(
+    connection: NetlinkConnection,
+) -> Result<impl Stream<Item = Result<RouteEvent>> + Unpin> {
+    let (_, messages) = subscribe(connection, RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE)?;
+    Ok(messages.filter_map(|(message, _)| {
+        ready(match message.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(route)) => {
+                Some(Ok(RouteEvent::Added(route)))
+            }
+            NetlinkPayload::InnerMessage(RtnlMessage::DelRoute(route)) => {
+                Some(Ok(RouteEvent::Deleted(route)))
+            }
+            NetlinkPayload::Error(err) => {
+                Some(Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err))))
+            }
+            _ => None,
+        })
+    }))
+}
+
+/// ip route get dst [from src] [oif oif]
+pub async fn route_lookup(
+    handle: &Handle,
+    dst: IpAddr,
+    src: Option<IpAddr>,
+    oif: Option<u32>,
+) -> Result<RouteMessage> {
+    let mut message = lookup_message(dst, src);
+    if let Some(oif) = oif {
+        message.nlas.push(Nla::Oif(oif));
+    }
+    get_route(handle, message, dst).await
+}
+
+/// ip route get dst [from src] [iif iif] [mark mark]
+/// The lookup goes through the rules like a packet would, so the answer is the route, table,
+/// gateway and output dev the kernel would use. With iif, the packet is looked up as received
+/// on it, iif must be up and src is usually needed.
+pub async fn route_get(
+    handle: &Handle,
+    dst: IpAddr,
+    src: Option<IpAddr>,
+    iif: Option<&str>,
+    mark: Option<u32>,
+) -> Result<RouteMessage> {
+    let mut message = lookup_message(dst, src);
+    // answer with the table the route was found in rather than main
+    message.header.flags = RouteFlags::RTM_F_LOOKUP_TABLE;
+    if let Some(iif) = iif {
+        message
+            .nlas
+            .push(Nla::Iif(resolve_link_index(handle, iif).await?));
+    }
+    if let Some(mark) = mark {
+        message.nlas.push(Nla::Mark(mark));
+    }
+    get_route(handle, message, dst).await
+}
+
+fn lookup_message(dst: IpAddr, src: Option<IpAddr>) -> RouteMessage {
+    let mut message = RouteMessage::default();
+    match dst {
+        IpAddr::V4(_) => {
+            message.header.address_family = AF_INET as u8;
+            message.header.destination_prefix_length = 32;
+        }
+        IpAddr::V6(_) => {
+            message.header.address_family = AF_INET6 as u8;
+            message.header.destination_prefix_length = 128;
+        }
+    }
+    message.nlas.push(Nla::Destination(ip_octets(dst)));
+    if let Some(src) = src {
+        message.header.source_prefix_length = message.header.destination_prefix_length;
+        message.nlas.push(Nla::Source(ip_octets(src)));
+    }
+    message
+}
+
+async fn get_route(handle: &Handle, message: RouteMessage, dst: IpAddr) -> Result<RouteMessage> {
+    let mut req = NetlinkMessage::from(RtnlMessage::GetRoute(message));
+    req.header.flags = NLM_F_REQUEST;
+
+    let mut response = handle.clone().request(req)?;
+    while let Some(message) = response.next().await {
+        match message.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(route)) => return Ok(route),
+            NetlinkPayload::Error(err) => {
+                return Err(anyhow::Error::new(rtnetlink::Error::NetlinkError(err)))
+            }
+            _ => {}
+        }
+    }
+    Err(anyhow!("no answer to the route lookup of {}", dst))
+}
+
+/// Whether a packet from src to dst would be routed, i.e. it does not hit a blackhole, unreachable
+/// or prohibit route, or no route at all.
+/// The kernel refuses those lookups with EINVAL, EHOSTUNREACH, EACCES and ENETUNREACH respectively.
+pub async fn is_reachable(handle: &Handle, src: IpAddr, dst: IpAddr) -> Result<bool> {
+    routable(handle, dst, Some(src)).await
+}
+
+/// ip route get dst succeeding with a route that forwards, as is_reachable without a source.
+pub async fn has_route(handle: &Handle, dst: IpAddr) -> Result<bool> {
+    routable(handle, dst, None).await
+}
+
+async fn routable(handle: &Handle, dst: IpAddr, src: Option<IpAddr>) -> Result<bool> {
+    match route_lookup(handle, dst, src, None).await {
+        Ok(route) => Ok(!matches!(
+            route.header.kind,
+            RTN_BLACKHOLE | RTN_UNREACHABLE | RTN_PROHIBIT
+        )),
+        Err(e) => match e.downcast_ref::<rtnetlink::Error>() {
+            Some(rtnetlink::Error::NetlinkError(msg))
+                if [
+                    Errno::EINVAL,
+                    Errno::EHOSTUNREACH,
+                    Errno::EACCES,
+                    Errno::ENETUNREACH,
+                ]
+                .iter()
+                .any(|errno| msg.code == -(*errno as i32)) =>
+            {
+                Ok(false)
+            }
+            _ => Err(e),
+        },
+    }
+}
+
+/// The destination of route, None for a route of neither family.
+pub fn route_destination(route: &RouteMessage) -> Option<IpNet> {
+    let destination = route.nlas.iter().find_map(|nla| match nla {
+        Nla::Destination(octets) => Some(octets.as_slice()),
+        _ => None,
+    });
+    let addr = match (route.header.address_family as u16, destination) {
+        (AF_INET, Some(octets)) => IpAddr::from(<[u8; 4]>::try_from(octets).ok()?),
+        (AF_INET6, Some(octets)) => IpAddr::from(<[u8; 16]>::try_from(octets).ok()?),
+        (AF_INET, None) => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        (AF_INET6, None) => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+        _ => return None,
+    };
+    IpNet::new(addr, route.header.destination_prefix_length).ok()
+}
+
+#[derive(Debug, Clone, Default)]
+struct PrefixNode {
+    route: Option<(IpNet, RouteMessage)>,
+    children: [Option<Box<PrefixNode>>; 2],
+}
+
+/// Route destinations in a binary trie per family, for longest prefix matches without asking
+/// the kernel.
+#[derive(Debug, Clone, Default)]
+pub struct PrefixTree {
+    v4: PrefixNode,
+    v6: PrefixNode,
+    len: usize,
+}
+
+fn bit(octets: &[u8], index: usize) -> usize {
+    (octets[index / 8] >> (7 - index % 8) & 1) as usize
+}
+
+impl PrefixTree {
+    /// Replaces the route already stored for the same prefix, if any.
+    pub fn insert(&mut self, destination: IpNet, route: RouteMessage) {
+        let destination = destination.trunc();
+        let octets = ip_octets(destination.addr());
+        let mut node = match destination {
+            IpNet::V4(_) => &mut self.v4,
+            IpNet::V6(_) => &mut self.v6,
+        };
+        for index in 0..destination.prefix_len() as usize {
+            node = node.children[bit(&octets, index)].get_or_insert_with(Default::default);
+        }
+        if node.route.replace((destination, route)).is_none() {
+            self.len += 1;
+        }
+    }
+
+    /// The most specific prefix containing addr, with its route.
+    pub fn longest_match(&self, addr: IpAddr) -> Option<(IpNet, &RouteMessage)> {
+        let octets = ip_octets(addr);
+        let mut node = match addr {
+            IpAddr::V4(_) => &self.v4,
+            IpAddr::V6(_) => &self.v6,
+        };
+        let mut found = node.route.as_ref();
+        for index in 0..octets.len() * 8 {
+            node = match &node.children[bit(&octets, index)] {
+                Some(child) => child,
+                None => break,
+            };
+            found = node.route.as_ref().or(found);
+        }
+        found.map(|(destination, route)| (*destination, route))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// ip route show, as a PrefixTree.
+/// The table, type and metric of the routes are not looked at, so only routes of a single table
+/// should be given for the lookups to match the kernel's; of routes to the same prefix the last
+/// one is kept.
+pub fn routes_to_prefix_tree(routes: &[RouteMessage]) -> PrefixTree {
+    let mut tree = PrefixTree::default();
+    for route in routes {
+        if let Some(destination) = route_destination(route) {
+            tree.insert(destination, route.clone());
+        }
+    }
+    tree
+}
+
 #[cfg(test)]
 mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use netlink_packet_route::constants::{
+        AF_INET, AF_INET6, RTAX_CWND, RTAX_RTT, RTN_BLACKHOLE, RTN_UNICAST, RTPROT_STATIC,
+        RT_SCOPE_LINK, RT_TABLE_LOCAL, RT_TABLE_MAIN,
+    };
+    use netlink_packet_route::nlas::route::{Metrics, NextHop, NextHopBuffer, Nla};
+    use netlink_packet_route::nlas::NlasIterator;
+    use netlink_packet_route::traits::{Emitable, Parseable};
     use netlink_packet_route::RouteMessage;
+    use nix::errno::Errno;
     use rtnetlink::{new_connection, IpVersion};
+    use serial_test::serial;
 
-    use crate::ip::iproute::{get_routes, Action, IPRoute};
+    use crate::error::IprouteError;
+    use crate::ip::ipaddr::{AddrAction, IPAddr};
+    use crate::ip::iplink::{get_link_index, Action as LinkAction, IPLink, Opt};
+    use crate::ip::ipnetns::{
+        ip_net_ns_add, ip_net_ns_del, thread_net_ns_block_on, thread_net_ns_exec,
+    };
+    use crate::ip::iproute::{
+        del_routes_in_table, flush_default_routes, flush_routes, get_routes, get_routes_in_table,
+        is_reachable, route_add_ecmp, route_event_stream, route_get, route_lookup, route_table,
+        routes_to_prefix_tree, Action, IPRoute, IPRouteBuilder, Nexthop, RouteEvent, RouteProtocol,
+        RouteScope, RouteType,
+    };
+    use crate::ip::iprule::{IPRuleBuilder, RuleType};
+    use crate::ip::testutil::add_veth;
 
     #[tokio::test]
     async fn test_dump_addresses() {
@@ -85,4 +838,824 @@ mod test {
             .unwrap();
         }
     }
+
+    #[tokio::test]
+    async fn test_iproute_builder() {
+        let (connection, handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let route = IPRouteBuilder {
+            destination: Some("10.1.0.0".parse().unwrap()),
+            prefix_len: 16,
+            gateway: Some("10.0.0.254".parse().unwrap()),
+            dev: Some("lo".to_string()),
+            metric: 100,
+            ..Default::default()
+        }
+        .build(&handle)
+        .await
+        .unwrap();
+        assert_eq!(route.action, Action::Add);
+        assert_eq!(route.msg.header.destination_prefix_length, 16);
+        assert_eq!(route.msg.header.table, RT_TABLE_MAIN);
+        assert_eq!(route.msg.header.kind, RTN_UNICAST);
+        for nla in [
+            Nla::Table(RT_TABLE_MAIN as u32),
+            Nla::Destination(vec![10, 1, 0, 0]),
+            Nla::Gateway(vec![10, 0, 0, 254]),
+            Nla::Oif(1),
+            Nla::Priority(100),
+        ] {
+            assert!(route.msg.nlas.contains(&nla));
+        }
+
+        let route = IPRouteBuilder {
+            action: Action::Del,
+            destination: Some("2001:db8::".parse().unwrap()),
+            prefix_len: 32,
+            table: 1000,
+            scope: RouteScope::Link,
+            kind: RouteType::Blackhole,
+            ..Default::default()
+        }
+        .build(&handle)
+        .await
+        .unwrap();
+        assert_eq!(route.action, Action::Del);
+        assert_eq!(route.msg.header.table, 0);
+        assert_eq!(route.msg.header.scope, RT_SCOPE_LINK);
+        assert_eq!(route.msg.header.kind, RTN_BLACKHOLE);
+        assert!(route.msg.nlas.contains(&Nla::Table(1000)));
+
+        for builder in [
+            IPRouteBuilder::default(),
+            IPRouteBuilder {
+                destination: Some("10.1.0.0".parse().unwrap()),
+                gateway: Some("2001:db8::1".parse().unwrap()),
+                ..Default::default()
+            },
+            IPRouteBuilder {
+                destination: Some("10.1.0.0".parse().unwrap()),
+                prefix_len: 33,
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                builder
+                    .build(&handle)
+                    .await
+                    .unwrap_err()
+                    .downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_iproute_builder_no_nexthop() {
+        let (connection, handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let err = IPRouteBuilder {
+            destination: Some("192.0.2.0".parse().unwrap()),
+            prefix_len: 24,
+            gateway: Some("10.0.0.254".parse().unwrap()),
+            kind: RouteType::Unreachable,
+            ..Default::default()
+        }
+        .build(&handle)
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_iproute_builder_handle_namespace() {
+        ip_net_ns_add("vnetns42".to_string()).unwrap();
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns42".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        let added = add_veth(&mut handle, "nsroute0", "nsroute1", false).await;
+        let index = thread_net_ns_exec("vnetns42".to_string(), || get_link_index("nsroute0"))
+            .join()
+            .unwrap();
+        let builder = |dev: &str| IPRouteBuilder {
+            destination: Some("10.1.0.0".parse().unwrap()),
+            prefix_len: 16,
+            dev: Some(dev.to_string()),
+            ..Default::default()
+        };
+        let route = builder("nsroute0").build(&handle).await;
+        // nsroute1 only exists in the namespace of handle
+        let multipath = IPRouteBuilder {
+            nexthops: vec![Nexthop {
+                gateway: Some("10.0.0.1".parse().unwrap()),
+                dev: "nsroute1".to_string(),
+                weight: 1,
+                flags: 0,
+            }],
+            dev: None,
+            ..builder("nsroute0")
+        }
+        .build(&handle)
+        .await;
+
+        ip_net_ns_del("vnetns42".to_string(), false).unwrap();
+
+        added.unwrap();
+        assert!(route.unwrap().msg.nlas.contains(&Nla::Oif(index.unwrap())));
+        multipath.unwrap();
+        assert!(get_link_index("nsroute0").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_iproute_builder_family() {
+        let (connection, handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let route = IPRouteBuilder {
+            kind: RouteType::Blackhole,
+            family: Some(IpVersion::V6),
+            ..Default::default()
+        }
+        .build(&handle)
+        .await
+        .unwrap();
+        assert_eq!(route.msg.header.address_family, AF_INET6 as u8);
+        assert_eq!(route.msg.header.destination_prefix_length, 0);
+
+        let route = IPRouteBuilder {
+            dev: Some("lo".to_string()),
+            family: Some(IpVersion::V4),
+            ..Default::default()
+        }
+        .build(&handle)
+        .await
+        .unwrap();
+        assert_eq!(route.msg.header.address_family, AF_INET as u8);
+
+        for builder in [
+            IPRouteBuilder {
+                dev: Some("lo".to_string()),
+                ..Default::default()
+            },
+            IPRouteBuilder {
+                destination: Some("192.0.2.0".parse().unwrap()),
+                prefix_len: 24,
+                family: Some(IpVersion::V6),
+                ..Default::default()
+            },
+        ] {
+            let err = builder.build(&handle).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_route_types() {
+        ip_net_ns_add("vnetns28".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns28".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            IPLink {
+                action: LinkAction::Set,
+                name: "lo".to_string(),
+                options: vec![Opt::Up],
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await?;
+
+            let mut errors = vec![];
+            for (dst, kind) in [
+                ("192.0.2.0", RouteType::Blackhole),
+                ("198.51.100.0", RouteType::Unreachable),
+                ("203.0.113.0", RouteType::Prohibit),
+            ] {
+                let dst: IpAddr = dst.parse()?;
+                IPRouteBuilder {
+                    destination: Some(dst),
+                    prefix_len: 24,
+                    kind,
+                    ..Default::default()
+                }
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await?;
+                let err = route_lookup(&handle, dst, None, None).await.unwrap_err();
+                errors.push(match err.downcast_ref::<rtnetlink::Error>() {
+                    Some(rtnetlink::Error::NetlinkError(msg)) => msg.code,
+                    _ => return Err(err),
+                });
+            }
+            Ok(errors)
+        });
+        ip_net_ns_del("vnetns28".to_string(), false).unwrap();
+
+        // what a local sender gets in place of an ICMP error, unreachable and prohibit routes
+        // answer forwarded packets with ICMP host unreachable and admin prohibited
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                -(Errno::EINVAL as i32),
+                -(Errno::EHOSTUNREACH as i32),
+                -(Errno::EACCES as i32),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_route_get() {
+        ip_net_ns_add("vnetns29".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns29".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "rget0", "rget1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.9.0.1".parse()?, 24, "rget0")
+                .execute(&mut handle)
+                .await?;
+            IPAddr::new(AddrAction::Add, "10.9.1.1".parse()?, 24, "rget1")
+                .execute(&mut handle)
+                .await?;
+            IPRouteBuilder {
+                gateway: Some("10.9.0.254".parse()?),
+                ..Default::default()
+            }
+            .build(&handle)
+            .await?
+            .execute(&mut handle)
+            .await?;
+            IPRouteBuilder {
+                gateway: Some("10.9.1.254".parse()?),
+                table: 100,
+                ..Default::default()
+            }
+            .build(&handle)
+            .await?
+            .execute(&mut handle)
+            .await?;
+            IPRuleBuilder {
+                priority: Some(100),
+                fwmark: Some(1),
+                rule_type: RuleType::Table(100),
+                ..Default::default()
+            }
+            .build()?
+            .execute(&mut handle)
+            .await?;
+
+            let dst = "192.0.2.1".parse()?;
+            let unmarked = route_get(&handle, dst, None, None, None).await?;
+            let marked = route_get(&handle, dst, None, None, Some(1)).await?;
+            Ok((unmarked, marked))
+        });
+        ip_net_ns_del("vnetns29".to_string(), false).unwrap();
+
+        let (unmarked, marked) = res.unwrap();
+        assert!(unmarked.nlas.contains(&Nla::Gateway(vec![10, 9, 0, 254])));
+        assert_eq!(route_table(&unmarked), RT_TABLE_MAIN as u32);
+        assert!(marked.nlas.contains(&Nla::Gateway(vec![10, 9, 1, 254])));
+        assert_eq!(route_table(&marked), 100);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_route_get_iif_in_handle_namespace() {
+        ip_net_ns_add("vnetns43".to_string()).unwrap();
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns43".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        let res = async {
+            add_veth(&mut handle, "nsget0", "nsget1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.31.0.1".parse()?, 24, "nsget0")
+                .execute(&mut handle)
+                .await?;
+            // nsget1 only exists in the namespace of handle
+            route_get(
+                &handle,
+                "10.31.0.1".parse()?,
+                Some("10.31.0.2".parse()?),
+                Some("nsget1"),
+                None,
+            )
+            .await
+        }
+        .await;
+        let index = thread_net_ns_exec("vnetns43".to_string(), || get_link_index("nsget1"))
+            .join()
+            .unwrap();
+        ip_net_ns_del("vnetns43".to_string(), false).unwrap();
+
+        assert!(res.unwrap().nlas.contains(&Nla::Iif(index.unwrap())));
+    }
+
+    #[test]
+    #[serial]
+    fn test_flush_routes() {
+        ip_net_ns_add("vnetns30".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns30".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "flush0", "flush1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.10.0.1".parse()?, 24, "flush0")
+                .execute(&mut handle)
+                .await?;
+            for (dst, table, proto) in [
+                ("10.20.0.0", RT_TABLE_MAIN as u32, RouteProtocol::Static),
+                ("10.21.0.0", RT_TABLE_MAIN as u32, RouteProtocol::Boot),
+                ("10.22.0.0", 100, RouteProtocol::Static),
+                ("10.23.0.0", 100, RouteProtocol::Boot),
+            ] {
+                IPRouteBuilder {
+                    destination: Some(dst.parse()?),
+                    prefix_len: 16,
+                    gateway: Some("10.10.0.254".parse()?),
+                    table,
+                    proto,
+                    ..Default::default()
+                }
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await?;
+            }
+
+            let static_main = flush_routes(
+                &handle,
+                IpVersion::V4,
+                Some(RT_TABLE_MAIN as u32),
+                Some(RTPROT_STATIC),
+                None,
+            )
+            .await?;
+            let table_100 = flush_routes(&handle, IpVersion::V4, Some(100), None, None).await?;
+            let remaining = get_routes(&handle, IpVersion::V4).await?;
+            let main = flush_routes(&handle, IpVersion::V4, None, None, None).await?;
+            let after_main = get_routes(&handle, IpVersion::V4).await?;
+            Ok((static_main, table_100, remaining, main, after_main))
+        });
+        ip_net_ns_del("vnetns30".to_string(), false).unwrap();
+
+        let (static_main, table_100, remaining, main, after_main) = res.unwrap();
+        assert_eq!(static_main, 1);
+        assert_eq!(table_100, 2);
+        // 10.21.0.0/16 and the prefix route of 10.10.0.1/24
+        assert_eq!(main, 2);
+        assert!(after_main
+            .iter()
+            .all(|route| route_table(route) != RT_TABLE_MAIN as u32));
+        assert!(after_main
+            .iter()
+            .any(|route| route_table(route) == RT_TABLE_LOCAL as u32));
+        let destinations: Vec<_> = remaining
+            .iter()
+            .filter(|route| route.header.destination_prefix_length == 16)
+            .filter_map(|route| {
+                route.nlas.iter().find_map(|nla| match nla {
+                    Nla::Destination(destination) => Some(destination.clone()),
+                    _ => None,
+                })
+            })
+            .collect();
+        assert_eq!(destinations, vec![vec![10, 21, 0, 0]]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_route_event_stream() {
+        ip_net_ns_add("vnetns31".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns31".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            let mut events = route_event_stream(new_connection()?)?;
+            let route = IPRouteBuilder {
+                destination: Some("192.0.2.0".parse()?),
+                prefix_len: 24,
+                kind: RouteType::Blackhole,
+                ..Default::default()
+            }
+            .build(&handle)
+            .await?;
+            route.execute(&mut handle).await?;
+            let added = tokio::time::timeout(Duration::from_secs(5), events.next()).await?;
+            IPRoute {
+                action: Action::Del,
+                ..route
+            }
+            .execute(&mut handle)
+            .await?;
+            let deleted = tokio::time::timeout(Duration::from_secs(5), events.next()).await?;
+            Ok((added, deleted))
+        });
+        ip_net_ns_del("vnetns31".to_string(), false).unwrap();
+
+        let (added, deleted) = res.unwrap();
+        let destination = Nla::Destination(vec![192, 0, 2, 0]);
+        match (added, deleted) {
+            (Some(Ok(RouteEvent::Added(added))), Some(Ok(RouteEvent::Deleted(deleted)))) => {
+                assert!(added.nlas.contains(&destination));
+                assert!(deleted.nlas.contains(&destination));
+            }
+            events => panic!("unexpected route events {:?}", events),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_iproute_builder_metrics() {
+        let (connection, handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let route = IPRouteBuilder {
+            destination: Some("10.1.0.0".parse().unwrap()),
+            prefix_len: 16,
+            gateway: Some("10.0.0.254".parse().unwrap()),
+            ..Default::default()
+        }
+        .metric(20)
+        .rtt(10)
+        .rttvar(5)
+        .advmss(1400)
+        .hoplimit(32)
+        .cwnd(10)
+        .lock(RTAX_RTT)
+        .build(&handle)
+        .await
+        .unwrap();
+        assert!(route.msg.nlas.contains(&Nla::Priority(20)));
+        let metrics: Vec<Metrics> = route
+            .msg
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Metrics(bytes) => Some(bytes.clone()),
+                _ => None,
+            })
+            .map(|bytes| {
+                NlasIterator::new(bytes.as_slice())
+                    .map(|nla| Metrics::parse(&nla.unwrap()).unwrap())
+                    .collect()
+            })
+            .unwrap();
+        assert_eq!(
+            metrics,
+            vec![
+                Metrics::Rtt(80),
+                Metrics::RttVar(20),
+                Metrics::Advmss(1400),
+                Metrics::Hoplimit(32),
+                Metrics::Cwnd(10),
+                Metrics::Lock(1 << RTAX_CWND | 1 << RTAX_RTT),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_is_reachable() {
+        ip_net_ns_add("vnetns8".to_string()).unwrap();
+        let (blackholed, routed) = thread_net_ns_block_on("vnetns8".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            IPLink {
+                action: LinkAction::Set,
+                name: "lo".to_string(),
+                options: vec![Opt::Up],
+                link_type: None,
+            }
+            .execute(&mut handle)
+            .await?;
+            IPAddr::new(AddrAction::Add, "10.8.0.1".parse()?, 24, "lo")
+                .execute(&mut handle)
+                .await?;
+            let mut blackhole = handle
+                .route()
+                .add()
+                .v4()
+                .destination_prefix("198.51.100.0".parse()?, 24);
+            blackhole.message_mut().header.kind = RTN_BLACKHOLE;
+            blackhole.execute().await?;
+
+            let src = "10.8.0.1".parse()?;
+            let blackholed = is_reachable(&handle, src, "198.51.100.7".parse()?).await?;
+            let routed = is_reachable(&handle, src, "10.8.0.2".parse()?).await?;
+            Ok((blackholed, routed))
+        })
+        .unwrap();
+        ip_net_ns_del("vnetns8".to_string(), false).unwrap();
+
+        assert!(!blackholed);
+        assert!(routed);
+    }
+
+    #[test]
+    #[serial]
+    fn test_flush_default_routes() {
+        ip_net_ns_add("vnetns10".to_string()).unwrap();
+        let (flushed, routes) = thread_net_ns_block_on("vnetns10".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "dflt0", "dflt1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.10.0.1".parse()?, 24, "dflt0")
+                .execute(&mut handle)
+                .await?;
+
+            let index = get_link_index("dflt0")?;
+            for table in [254, 100] {
+                handle
+                    .route()
+                    .add()
+                    .v4()
+                    .table(table)
+                    .output_interface(index)
+                    .gateway("10.10.0.2".parse()?)
+                    .execute()
+                    .await?;
+            }
+            handle
+                .route()
+                .add()
+                .v4()
+                .destination_prefix("10.20.0.0".parse()?, 24)
+                .output_interface(index)
+                .gateway("10.10.0.2".parse()?)
+                .execute()
+                .await?;
+
+            let flushed = flush_default_routes(&handle, IpVersion::V4).await?;
+            let routes = get_routes(&handle, IpVersion::V4).await?;
+            Ok((flushed, routes))
+        })
+        .unwrap();
+        ip_net_ns_del("vnetns10".to_string(), false).unwrap();
+
+        assert_eq!(flushed, 2);
+        assert!(!routes
+            .iter()
+            .any(|route| route.header.destination_prefix_length == 0));
+        assert!(routes
+            .iter()
+            .any(|route| route.header.destination_prefix_length == 24
+                && route.nlas.contains(&Nla::Destination(vec![10, 20, 0, 0]))));
+    }
+
+    #[test]
+    #[serial]
+    fn test_routes_in_table() {
+        ip_net_ns_add("vnetns24".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns24".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "rtable0", "rtable1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.24.0.1".parse()?, 24, "rtable0")
+                .execute(&mut handle)
+                .await?;
+            for (table, destination) in
+                [(100, "10.25.0.0"), (1000, "10.26.0.0"), (1000, "10.27.0.0")]
+            {
+                IPRouteBuilder {
+                    destination: Some(destination.parse()?),
+                    prefix_len: 16,
+                    gateway: Some("10.24.0.2".parse()?),
+                    table,
+                    ..Default::default()
+                }
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await?;
+            }
+
+            let in_table = get_routes_in_table(&handle, IpVersion::V4, 1000).await?;
+            let deleted = del_routes_in_table(&handle, IpVersion::V4, 1000).await?;
+            let routes = get_routes(&handle, IpVersion::V4).await?;
+            Ok((in_table, deleted, routes))
+        });
+        ip_net_ns_del("vnetns24".to_string(), false).unwrap();
+
+        let (in_table, deleted, routes) = res.unwrap();
+        assert_eq!(in_table.len(), 2);
+        assert_eq!(deleted, 2);
+        assert!(!routes.iter().any(|route| route_table(route) == 1000));
+        assert!(routes.iter().any(|route| route_table(route) == 100
+            && route.nlas.contains(&Nla::Destination(vec![10, 25, 0, 0]))));
+    }
+
+    #[test]
+    #[serial]
+    fn test_route_add_ecmp() {
+        ip_net_ns_add("vnetns26".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns26".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            for (name, peer_name, addr) in [
+                ("ecmp0", "ecmp1", "10.26.1.1"),
+                ("ecmp2", "ecmp3", "10.26.2.1"),
+            ] {
+                add_veth(&mut handle, name, peer_name, true).await?;
+                IPAddr::new(AddrAction::Add, addr.parse()?, 24, name)
+                    .execute(&mut handle)
+                    .await?;
+            }
+            route_add_ecmp(
+                &mut handle,
+                ("10.30.0.0".parse()?, 16),
+                vec![
+                    Nexthop {
+                        gateway: Some("10.26.1.2".parse()?),
+                        dev: "ecmp0".to_string(),
+                        weight: 1,
+                        flags: 0,
+                    },
+                    Nexthop {
+                        gateway: Some("10.26.2.2".parse()?),
+                        dev: "ecmp2".to_string(),
+                        weight: 2,
+                        flags: 0,
+                    },
+                ],
+            )
+            .await?;
+            let indexes = (get_link_index("ecmp0")?, get_link_index("ecmp2")?);
+            let routes = get_routes(&handle, IpVersion::V4).await?;
+            Ok((indexes, routes))
+        });
+        ip_net_ns_del("vnetns26".to_string(), false).unwrap();
+
+        let (indexes, routes) = res.unwrap();
+        let multipath = routes
+            .iter()
+            .filter(|route| route.nlas.contains(&Nla::Destination(vec![10, 30, 0, 0])))
+            .find_map(|route| {
+                route.nlas.iter().find_map(|nla| match nla {
+                    Nla::MultiPath(bytes) => Some(bytes.clone()),
+                    _ => None,
+                })
+            })
+            .unwrap();
+        let mut nexthops = vec![];
+        let mut offset = 0;
+        while offset < multipath.len() {
+            let rest = multipath[offset..].to_vec();
+            let nexthop = NextHop::parse(&NextHopBuffer::new_checked(&rest).unwrap()).unwrap();
+            offset += nexthop.buffer_len();
+            nexthops.push((nexthop.interface_id, nexthop.hops, nexthop.gateway));
+        }
+        assert_eq!(
+            nexthops,
+            vec![
+                (indexes.0, 0, Some("10.26.1.2".parse().unwrap())),
+                (indexes.1, 1, Some("10.26.2.2".parse().unwrap())),
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_route_replace() {
+        ip_net_ns_add("vnetns27".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns27".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "rreplace0", "rreplace1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.27.0.1".parse()?, 24, "rreplace0")
+                .execute(&mut handle)
+                .await?;
+
+            let route = |action, gateway: &str| IPRouteBuilder {
+                action,
+                destination: Some("10.28.0.0".parse().unwrap()),
+                prefix_len: 16,
+                gateway: Some(gateway.parse().unwrap()),
+                ..Default::default()
+            };
+            let change_missing = route(Action::Change, "10.27.0.2")
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await;
+            route(Action::Replace, "10.27.0.2")
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await?;
+            let add_existing = route(Action::Add, "10.27.0.3")
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await;
+            route(Action::Replace, "10.27.0.3")
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await?;
+            route(Action::Change, "10.27.0.4")
+                .build(&handle)
+                .await?
+                .execute(&mut handle)
+                .await?;
+            let routes = get_routes(&handle, IpVersion::V4).await?;
+            Ok((change_missing, add_existing, routes))
+        });
+        ip_net_ns_del("vnetns27".to_string(), false).unwrap();
+
+        let (change_missing, add_existing, routes) = res.unwrap();
+        assert!(change_missing.is_err());
+        assert!(add_existing.is_err());
+        let gateways: Vec<&Nla> = routes
+            .iter()
+            .filter(|route| route.nlas.contains(&Nla::Destination(vec![10, 28, 0, 0])))
+            .flat_map(|route| route.nlas.iter())
+            .filter(|nla| matches!(nla, Nla::Gateway(_)))
+            .collect();
+        assert_eq!(gateways, vec![&Nla::Gateway(vec![10, 27, 0, 4])]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_routes_to_prefix_tree() {
+        ip_net_ns_add("vnetns13".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns13".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            add_veth(&mut handle, "ptree0", "ptree1", true).await?;
+            IPAddr::new(AddrAction::Add, "10.13.0.1".parse()?, 24, "ptree0")
+                .execute(&mut handle)
+                .await?;
+
+            // 172.16.0.0/16 covering /24s and /25s, each through its own gateway so the route
+            // picked by the kernel can be told apart
+            for i in 0..100u8 {
+                let (dst, prefix_len) = match i {
+                    0 => (Ipv4Addr::new(172, 16, 0, 0), 16),
+                    i if i % 2 == 0 => (Ipv4Addr::new(172, 16, i, 0), 24),
+                    i => (Ipv4Addr::new(172, 16, i, 128), 25),
+                };
+                handle
+                    .route()
+                    .add()
+                    .v4()
+                    .destination_prefix(dst, prefix_len)
+                    .gateway(Ipv4Addr::new(10, 13, 0, i + 2))
+                    .execute()
+                    .await?;
+            }
+
+            let routes: Vec<RouteMessage> = get_routes(&handle, IpVersion::V4)
+                .await?
+                .into_iter()
+                .filter(|route| route.header.table == RT_TABLE_MAIN)
+                .collect();
+            let tree = routes_to_prefix_tree(&routes);
+
+            let mut lookups = vec![];
+            for j in 0..100u8 {
+                let dst = IpAddr::from(Ipv4Addr::new(172, 16, j, j.wrapping_mul(37)));
+                let gateway = |route: &RouteMessage| {
+                    route.nlas.iter().find_map(|nla| match nla {
+                        Nla::Gateway(gateway) => Some(gateway.clone()),
+                        _ => None,
+                    })
+                };
+                let kernel = gateway(&route_lookup(&handle, dst, None, None).await?);
+                let tree = tree
+                    .longest_match(dst)
+                    .and_then(|(_, route)| gateway(route));
+                lookups.push((dst, kernel, tree));
+            }
+            Ok((tree.len(), lookups))
+        });
+        ip_net_ns_del("vnetns13".to_string(), false).unwrap();
+
+        let (len, lookups) = res.unwrap();
+        // the 100 routes and the prefix route of 10.13.0.1/24
+        assert_eq!(len, 101);
+        for (dst, kernel, tree) in lookups {
+            assert!(kernel.is_some());
+            assert_eq!(kernel, tree, "{}", dst);
+        }
+    }
 }
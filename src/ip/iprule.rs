@@ -0,0 +1,600 @@
+use std::convert::TryFrom;
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Result};
+use futures::TryStreamExt;
+use netlink_packet_route::constants::*;
+use netlink_packet_route::nlas::rule::Nla;
+use netlink_packet_route::nlas::{DefaultNla, NlaBuffer};
+use netlink_packet_route::traits::Parseable;
+use netlink_packet_route::{NetlinkMessage, RtnlMessage, RuleMessage};
+use nix::sys::socket::AddressFamily;
+use rtnetlink::{Handle, IpVersion};
+
+use crate::error::IprouteError;
+use crate::ip::iplink::request_ack;
+use crate::ip::iproute::ip_octets;
+
+/// What a rule does with the packets it matches, like `ip rule add ... lookup TABLE`
+/// or `ip rule add ... blackhole`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum RuleType {
+    Table(u32),
+    Blackhole,
+    Unreachable,
+    Prohibit,
+}
+
+impl RuleType {
+    pub fn rule_type(&self, message: &mut RuleMessage) {
+        match self {
+            RuleType::Table(table) => {
+                message.header.action = FR_ACT_TO_TBL;
+                if *table < 256 {
+                    message.header.table = *table as u8;
+                } else {
+                    message.header.table = RT_TABLE_UNSPEC;
+                    message.nlas.push(Nla::Table(*table));
+                }
+            }
+            RuleType::Blackhole => message.header.action = FR_ACT_BLACKHOLE,
+            RuleType::Unreachable => message.header.action = FR_ACT_UNREACHABLE,
+            RuleType::Prohibit => message.header.action = FR_ACT_PROHIBIT,
+        }
+    }
+}
+
+/// ip rule add|del [not] [from src] [to dst] [iif iif] [oif oif] [fwmark fwmark[/fwmask]]
+///     priority priority lookup table
+/// family is AddressFamily::Inet or AddressFamily::Inet6. See IPRuleBuilder for the other rule
+/// types and for a priority picked by the kernel.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPRule {
+    pub action: RuleAction,
+    pub priority: u32,
+    pub family: AddressFamily,
+    /// address and prefix length
+    pub src: Option<(IpAddr, u8)>,
+    pub dst: Option<(IpAddr, u8)>,
+    pub iif: Option<String>,
+    pub oif: Option<String>,
+    pub fwmark: Option<u32>,
+    /// Only with fwmark.
+    pub fwmask: Option<u32>,
+    pub table: u32,
+    /// Match the packets the selectors do not.
+    pub invert: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum RuleAction {
+    Add,
+    Del,
+}
+
+impl IPRule {
+    pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
+        IPRuleBuilder::try_from(self)?.execute(handle).await
+    }
+}
+
+impl TryFrom<&IPRule> for IPRuleBuilder {
+    type Error = anyhow::Error;
+
+    fn try_from(rule: &IPRule) -> Result<Self> {
+        let family = match rule.family {
+            AddressFamily::Inet => IpVersion::V4,
+            AddressFamily::Inet6 => IpVersion::V6,
+            family => {
+                return Err(IprouteError::InvalidArgument(format!(
+                    "{:?} is not a rule family",
+                    family
+                ))
+                .into())
+            }
+        };
+        Ok(IPRuleBuilder {
+            action: rule.action.clone(),
+            priority: Some(rule.priority),
+            family,
+            src: rule.src,
+            dst: rule.dst,
+            iif: rule.iif.clone(),
+            oif: rule.oif.clone(),
+            fwmark: rule.fwmark,
+            fwmask: rule.fwmask,
+            rule_type: RuleType::Table(rule.table),
+            invert: rule.invert,
+        })
+    }
+}
+
+/// IPRule for any rule type, with the priority optional.
+/// Meant to be filled with struct update syntax, like IPRouteBuilder. The default is an IPv4 rule
+/// matching every packet and looking up the main table, with a priority picked by the kernel.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IPRuleBuilder {
+    pub action: RuleAction,
+    /// None lets the kernel use the priority below the first rule after the local one.
+    pub priority: Option<u32>,
+    pub family: IpVersion,
+    /// address and prefix length
+    pub src: Option<(IpAddr, u8)>,
+    pub dst: Option<(IpAddr, u8)>,
+    pub iif: Option<String>,
+    pub oif: Option<String>,
+    pub fwmark: Option<u32>,
+    /// Only with fwmark.
+    pub fwmask: Option<u32>,
+    pub rule_type: RuleType,
+    /// Match the packets the selectors do not.
+    pub invert: bool,
+}
+
+impl Default for IPRuleBuilder {
+    fn default() -> Self {
+        IPRuleBuilder {
+            action: RuleAction::Add,
+            priority: None,
+            family: IpVersion::V4,
+            src: None,
+            dst: None,
+            iif: None,
+            oif: None,
+            fwmark: None,
+            fwmask: None,
+            rule_type: RuleType::Table(RT_TABLE_MAIN as u32),
+            invert: false,
+        }
+    }
+}
+
+impl IPRuleBuilder {
+    /// The IPRule of the builder. An IPRule always has a priority and looks up a table, so the
+    /// builders without either are refused: execute them instead.
+    pub fn build(self) -> Result<IPRule> {
+        self.message()?;
+        let table = match self.rule_type {
+            RuleType::Table(table) => table,
+            rule_type => {
+                return Err(IprouteError::InvalidArgument(format!(
+                    "an IPRule cannot hold a {:?} rule",
+                    rule_type
+                ))
+                .into())
+            }
+        };
+        let priority = self.priority.ok_or_else(|| {
+            IprouteError::InvalidArgument("an IPRule needs a priority".to_string())
+        })?;
+        Ok(IPRule {
+            action: self.action,
+            priority,
+            family: match self.family {
+                IpVersion::V4 => AddressFamily::Inet,
+                IpVersion::V6 => AddressFamily::Inet6,
+            },
+            src: self.src,
+            dst: self.dst,
+            iif: self.iif,
+            oif: self.oif,
+            fwmark: self.fwmark,
+            fwmask: self.fwmask,
+            table,
+            invert: self.invert,
+        })
+    }
+
+    /// The RuleMessage the rule is sent as.
+    pub fn message(&self) -> Result<RuleMessage> {
+        let invalid =
+            |message: String| -> anyhow::Error { IprouteError::InvalidArgument(message).into() };
+        let (family, max_prefix_len) = match self.family {
+            IpVersion::V4 => (AF_INET, 32),
+            IpVersion::V6 => (AF_INET6, 128),
+        };
+        for (addr, prefix_len) in self.src.iter().chain(self.dst.iter()) {
+            if addr.is_ipv4() != (family == AF_INET) || *prefix_len > max_prefix_len {
+                return Err(invalid(format!(
+                    "{}/{} is not a prefix of the rule family",
+                    addr, prefix_len
+                )));
+            }
+        }
+        if self.fwmask.is_some() && self.fwmark.is_none() {
+            return Err(invalid("fwmask without fwmark".to_string()));
+        }
+
+        let mut msg = RuleMessage::default();
+        msg.header.family = family as u8;
+        if self.invert {
+            msg.header.flags |= FIB_RULE_INVERT;
+        }
+        if let Some(priority) = self.priority {
+            msg.nlas.push(Nla::Priority(priority));
+        }
+        if let Some((src, prefix_len)) = self.src {
+            msg.header.src_len = prefix_len;
+            msg.nlas.push(Nla::Source(ip_octets(src)));
+        }
+        if let Some((dst, prefix_len)) = self.dst {
+            msg.header.dst_len = prefix_len;
+            msg.nlas.push(Nla::Destination(ip_octets(dst)));
+        }
+        if let Some(iif) = &self.iif {
+            msg.nlas.push(raw_name_nla(FRA_IIFNAME, iif)?);
+        }
+        if let Some(oif) = &self.oif {
+            msg.nlas.push(raw_name_nla(FRA_OIFNAME, oif)?);
+        }
+        if let Some(fwmark) = self.fwmark {
+            msg.nlas.push(Nla::FwMark(fwmark));
+        }
+        if let Some(fwmask) = self.fwmask {
+            msg.nlas.push(Nla::FwMask(fwmask));
+        }
+        self.rule_type.rule_type(&mut msg);
+        Ok(msg)
+    }
+
+    pub async fn execute(&self, handle: &mut Handle) -> Result<()> {
+        let msg = self.message()?;
+        let mut req = match self.action {
+            RuleAction::Del => NetlinkMessage::from(RtnlMessage::DelRule(msg)),
+            RuleAction::Add => NetlinkMessage::from(RtnlMessage::NewRule(msg)),
+        };
+
+        if self.action == RuleAction::Add {
+            req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE
+        } else {
+            req.header.flags = NLM_F_REQUEST | NLM_F_ACK
+        };
+
+        request_ack(handle, req).await
+    }
+}
+
+pub async fn get_rules(handle: &Handle, ip_version: IpVersion) -> Result<Vec<RuleMessage>> {
+    let rules: Vec<RuleMessage> = handle
+        .rule()
+        .get(ip_version)
+        .execute()
+        .try_collect()
+        .await?;
+    Ok(rules)
+}
+
+pub fn rule_priority(rule: &RuleMessage) -> u32 {
+    rule.nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Priority(priority) => Some(*priority),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// ip rule add prio N type, with N the lowest rule priority of ip_version in
+/// [min_priority, max_priority] not used yet. Returns N.
+pub async fn rule_add_auto_priority(
+    handle: &mut Handle,
+    ip_version: IpVersion,
+    rule_type: RuleType,
+    min_priority: u32,
+    max_priority: u32,
+) -> Result<u32> {
+    let used: Vec<u32> = get_rules(handle, ip_version.clone())
+        .await?
+        .iter()
+        .map(rule_priority)
+        .collect();
+    let priority = (min_priority..=max_priority)
+        .find(|priority| !used.contains(priority))
+        .ok_or_else(|| {
+            anyhow!(
+                "no free rule priority between {} and {}",
+                min_priority,
+                max_priority
+            )
+        })?;
+
+    IPRuleBuilder {
+        priority: Some(priority),
+        family: ip_version,
+        rule_type,
+        ..Default::default()
+    }
+    .execute(handle)
+    .await?;
+
+    Ok(priority)
+}
+
+/// The table a rule points to, whether it fits in the header or is carried in FRA_TABLE.
+pub fn rule_table(rule: &RuleMessage) -> u32 {
+    rule.nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or(rule.header.table as u32)
+}
+
+/// Nla::Iifname and Nla::OifName emit the name without its NUL terminator and panic,
+/// so rules sent to the kernel carry them as raw attributes.
+fn raw_name_nla(kind: u16, name: &str) -> Result<Nla> {
+    let len = 4 + name.len() + 1;
+    let mut bytes = vec![0; len];
+    let mut buffer = NlaBuffer::new(&mut bytes);
+    buffer.set_kind(kind);
+    buffer.set_length(len as u16);
+    buffer.value_mut()[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(Nla::Other(DefaultNla::parse(&NlaBuffer::new(&bytes))?))
+}
+
+async fn del_rules<F>(handle: &mut Handle, matches: F) -> Result<u32>
+where
+    F: Fn(&RuleMessage) -> bool,
+{
+    let mut rules = get_rules(handle, IpVersion::V4).await?;
+    rules.extend(get_rules(handle, IpVersion::V6).await?);
+
+    let mut count = 0;
+    for mut rule in rules.into_iter().filter(|rule| matches(rule)) {
+        rule.nlas = rule
+            .nlas
+            .into_iter()
+            .map(|nla| match nla {
+                Nla::Iifname(name) => raw_name_nla(FRA_IIFNAME, &name),
+                Nla::OifName(name) => raw_name_nla(FRA_OIFNAME, &name),
+                nla => Ok(nla),
+            })
+            .collect::<Result<_>>()?;
+        handle.rule().del(rule).execute().await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// ip rule del fwmark mark lookup table, for every rule matching both. Returns how many were deleted.
+pub async fn del_rule_by_fwmark(handle: &mut Handle, mark: u32, table: u32) -> Result<u32> {
+    del_rules(handle, |rule| {
+        rule_table(rule) == table && rule.nlas.contains(&Nla::FwMark(mark))
+    })
+    .await
+}
+
+/// ip rule del iif iif lookup table, for every rule matching both. Returns how many were deleted.
+pub async fn del_rule_by_iif(handle: &mut Handle, iif: &str, table: u32) -> Result<u32> {
+    del_rules(handle, |rule| {
+        rule_table(rule) == table && rule.nlas.contains(&Nla::Iifname(iif.to_string()))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::constants::{FIB_RULE_INVERT, FR_ACT_TO_TBL, FR_ACT_UNREACHABLE};
+    use netlink_packet_route::nlas::rule::Nla;
+    use nix::sys::socket::AddressFamily;
+    use rtnetlink::{new_connection, IpVersion};
+    use serial_test::serial;
+
+    use crate::error::IprouteError;
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_block_on};
+    use crate::ip::iprule::{
+        del_rule_by_fwmark, del_rule_by_iif, get_rules, rule_add_auto_priority, rule_priority,
+        rule_table, IPRuleBuilder, RuleAction, RuleType,
+    };
+
+    #[test]
+    #[serial]
+    fn test_rule_add_auto_priority() {
+        ip_net_ns_add("vnetns6".to_string()).unwrap();
+        let (priorities, rules, v6, v6_rules) =
+            thread_net_ns_block_on("vnetns6".to_string(), || async {
+                let (connection, mut handle, _) = new_connection()?;
+                tokio::spawn(connection);
+                let mut priorities = vec![];
+                for table in 100..105 {
+                    priorities.push(
+                        rule_add_auto_priority(
+                            &mut handle,
+                            IpVersion::V4,
+                            RuleType::Table(table),
+                            1000,
+                            2000,
+                        )
+                        .await?,
+                    );
+                }
+                let v6 = rule_add_auto_priority(
+                    &mut handle,
+                    IpVersion::V6,
+                    RuleType::Unreachable,
+                    1000,
+                    2000,
+                )
+                .await?;
+                let rules = get_rules(&handle, IpVersion::V4).await?;
+                let v6_rules = get_rules(&handle, IpVersion::V6).await?;
+                Ok((priorities, rules, v6, v6_rules))
+            })
+            .unwrap();
+        ip_net_ns_del("vnetns6".to_string(), false).unwrap();
+
+        assert_eq!(priorities, vec![1000, 1001, 1002, 1003, 1004]);
+        for priority in priorities {
+            assert!(rules.iter().any(|rule| rule_priority(rule) == priority));
+        }
+        // the IPv6 priorities are not taken by the IPv4 rules
+        assert_eq!(v6, 1000);
+        assert!(v6_rules
+            .iter()
+            .any(|rule| rule_priority(rule) == 1000 && rule.header.action == FR_ACT_UNREACHABLE));
+    }
+
+    #[test]
+    #[serial]
+    fn test_del_rule_by_fwmark() {
+        ip_net_ns_add("vnetns7".to_string()).unwrap();
+        let (deleted, rules) = thread_net_ns_block_on("vnetns7".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            for table in 100..103 {
+                let mut req = handle.rule().add().v4().table(table).action(FR_ACT_TO_TBL);
+                req.message_mut().nlas.push(Nla::FwMark(7));
+                req.execute().await?;
+            }
+            let deleted = del_rule_by_fwmark(&mut handle, 7, 101).await?;
+            let rules = get_rules(&handle, IpVersion::V4).await?;
+            Ok((deleted, rules))
+        })
+        .unwrap();
+        ip_net_ns_del("vnetns7".to_string(), false).unwrap();
+
+        let mut tables: Vec<u32> = rules
+            .iter()
+            .filter(|rule| rule.nlas.contains(&Nla::FwMark(7)))
+            .map(rule_table)
+            .collect();
+        tables.sort_unstable();
+        assert_eq!(deleted, 1);
+        assert_eq!(tables, vec![100, 102]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_del_rule_by_iif() {
+        ip_net_ns_add("vnetns50".to_string()).unwrap();
+        let (deleted, rules) = thread_net_ns_block_on("vnetns50".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            // rtnetlink cannot emit FRA_IIFNAME, see raw_name_nla
+            for (priority, iif, table) in [(100, "lo", 100), (101, "lo", 101), (102, "eth9", 101)] {
+                IPRuleBuilder {
+                    priority: Some(priority),
+                    iif: Some(iif.to_string()),
+                    rule_type: RuleType::Table(table),
+                    ..Default::default()
+                }
+                .execute(&mut handle)
+                .await?;
+            }
+            let deleted = del_rule_by_iif(&mut handle, "lo", 101).await?;
+            let rules = get_rules(&handle, IpVersion::V4).await?;
+            Ok((deleted, rules))
+        })
+        .unwrap();
+        ip_net_ns_del("vnetns50".to_string(), false).unwrap();
+
+        let mut remaining: Vec<(String, u32)> = rules
+            .iter()
+            .filter_map(|rule| {
+                rule.nlas.iter().find_map(|nla| match nla {
+                    Nla::Iifname(iif) => Some((iif.clone(), rule_table(rule))),
+                    _ => None,
+                })
+            })
+            .collect();
+        remaining.sort_unstable();
+        assert_eq!(deleted, 1);
+        assert_eq!(
+            remaining,
+            vec![("eth9".to_string(), 101), ("lo".to_string(), 100)]
+        );
+    }
+
+    #[test]
+    fn test_iprule_builder_build() {
+        let rule = IPRuleBuilder {
+            priority: Some(500),
+            family: IpVersion::V6,
+            fwmark: Some(0x10),
+            rule_type: RuleType::Table(1000),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        assert_eq!(rule.priority, 500);
+        assert_eq!(rule.family, AddressFamily::Inet6);
+        assert_eq!(rule.table, 1000);
+        assert_eq!(rule.fwmark, Some(0x10));
+
+        for builder in [
+            IPRuleBuilder {
+                priority: Some(500),
+                rule_type: RuleType::Blackhole,
+                ..Default::default()
+            },
+            IPRuleBuilder::default(),
+        ] {
+            assert!(matches!(
+                builder.build().unwrap_err().downcast_ref::<IprouteError>(),
+                Some(IprouteError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_iprule_builder() {
+        let rule = || IPRuleBuilder {
+            priority: Some(500),
+            src: Some(("10.25.0.0".parse().unwrap(), 16)),
+            iif: Some("lo".to_string()),
+            fwmark: Some(0x10),
+            fwmask: Some(0xf0),
+            rule_type: RuleType::Table(1000),
+            invert: true,
+            ..Default::default()
+        };
+        let invalid = IPRuleBuilder {
+            family: IpVersion::V6,
+            src: Some(("10.25.0.0".parse().unwrap(), 16)),
+            ..Default::default()
+        }
+        .build();
+
+        ip_net_ns_add("vnetns25".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns25".to_string(), move || async move {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            rule().build()?.execute(&mut handle).await?;
+            let added = get_rules(&handle, IpVersion::V4).await?;
+            IPRuleBuilder {
+                action: RuleAction::Del,
+                ..rule()
+            }
+            .build()?
+            .execute(&mut handle)
+            .await?;
+            let deleted = get_rules(&handle, IpVersion::V4).await?;
+            Ok((added, deleted))
+        });
+        ip_net_ns_del("vnetns25".to_string(), false).unwrap();
+
+        assert!(matches!(
+            invalid.unwrap_err().downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        let (added, deleted) = res.unwrap();
+        let rule = added
+            .iter()
+            .find(|rule| rule_priority(rule) == 500)
+            .unwrap();
+        assert_eq!(rule_table(rule), 1000);
+        assert_eq!(rule.header.src_len, 16);
+        assert_ne!(rule.header.flags & FIB_RULE_INVERT, 0);
+        for nla in [
+            Nla::Source(vec![10, 25, 0, 0]),
+            Nla::Iifname("lo".to_string()),
+            Nla::FwMark(0x10),
+            Nla::FwMask(0xf0),
+        ] {
+            assert!(rule.nlas.contains(&nla));
+        }
+        assert!(!deleted.iter().any(|rule| rule_priority(rule) == 500));
+    }
+}
@@ -0,0 +1,306 @@
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::Result;
+use netlink_packet_route::nlas::{Nla as NlaTrait, NlasIterator};
+
+use crate::ip::geneve::GeneveConfig;
+use crate::ip::gre::GreConfig;
+use crate::ip::iplink::{get_link_info_raw, RawLinkInfo};
+use crate::ip::vxlan::VxlanConfig;
+
+pub const IFLA_IPTUN_LINK: u16 = 1;
+pub const IFLA_IPTUN_LOCAL: u16 = 2;
+pub const IFLA_IPTUN_REMOTE: u16 = 3;
+pub const IFLA_IPTUN_TTL: u16 = 4;
+pub const IFLA_IPTUN_TOS: u16 = 5;
+pub const IFLA_IPTUN_ENCAP_LIMIT: u16 = 6;
+pub const IFLA_IPTUN_FLOWINFO: u16 = 7;
+pub const IFLA_IPTUN_FLAGS: u16 = 8;
+pub const IFLA_IPTUN_PMTUDISC: u16 = 10;
+pub const IFLA_IPTUN_6RD_PREFIX: u16 = 11;
+pub const IFLA_IPTUN_6RD_RELAY_PREFIX: u16 = 12;
+pub const IFLA_IPTUN_6RD_PREFIXLEN: u16 = 13;
+pub const IFLA_IPTUN_6RD_RELAY_PREFIXLEN: u16 = 14;
+
+pub const SIT_ISATAP: u16 = 0x1;
+
+pub const IP6_TNL_F_IGN_ENCAP_LIMIT: u32 = 0x1;
+pub const IP6_TNL_F_USE_ORIG_TCLASS: u32 = 0x2;
+
+/// IFLA_IPTUN_* attributes nested in IFLA_INFO_DATA, shared by the ip-in-ip style tunnels.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InfoIpTun {
+    Link(u32),
+    Local(IpAddr),
+    Remote(IpAddr),
+    Ttl(u8),
+    Tos(u8),
+    EncapLimit(u8),
+    /// network byte order on the wire
+    FlowInfo(u32),
+    Flags(u32),
+    /// IFLA_IPTUN_FLAGS of sit, which only takes 16 bits
+    SitFlags(u16),
+    PMtuDisc(u8),
+    SixRdPrefix(Ipv6Addr),
+    SixRdRelayPrefix(Ipv4Addr),
+    SixRdPrefixLen(u16),
+    SixRdRelayPrefixLen(u16),
+}
+
+impl NlaTrait for InfoIpTun {
+    fn value_len(&self) -> usize {
+        match self {
+            InfoIpTun::Link(_) | InfoIpTun::FlowInfo(_) | InfoIpTun::Flags(_) => 4,
+            InfoIpTun::Local(IpAddr::V4(_)) | InfoIpTun::Remote(IpAddr::V4(_)) => 4,
+            InfoIpTun::Local(IpAddr::V6(_)) | InfoIpTun::Remote(IpAddr::V6(_)) => 16,
+            InfoIpTun::Ttl(_)
+            | InfoIpTun::Tos(_)
+            | InfoIpTun::EncapLimit(_)
+            | InfoIpTun::PMtuDisc(_) => 1,
+            InfoIpTun::SitFlags(_)
+            | InfoIpTun::SixRdPrefixLen(_)
+            | InfoIpTun::SixRdRelayPrefixLen(_) => 2,
+            InfoIpTun::SixRdPrefix(_) => 16,
+            InfoIpTun::SixRdRelayPrefix(_) => 4,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            InfoIpTun::Link(_) => IFLA_IPTUN_LINK,
+            InfoIpTun::Local(_) => IFLA_IPTUN_LOCAL,
+            InfoIpTun::Remote(_) => IFLA_IPTUN_REMOTE,
+            InfoIpTun::Ttl(_) => IFLA_IPTUN_TTL,
+            InfoIpTun::Tos(_) => IFLA_IPTUN_TOS,
+            InfoIpTun::EncapLimit(_) => IFLA_IPTUN_ENCAP_LIMIT,
+            InfoIpTun::FlowInfo(_) => IFLA_IPTUN_FLOWINFO,
+            InfoIpTun::Flags(_) | InfoIpTun::SitFlags(_) => IFLA_IPTUN_FLAGS,
+            InfoIpTun::PMtuDisc(_) => IFLA_IPTUN_PMTUDISC,
+            InfoIpTun::SixRdPrefix(_) => IFLA_IPTUN_6RD_PREFIX,
+            InfoIpTun::SixRdRelayPrefix(_) => IFLA_IPTUN_6RD_RELAY_PREFIX,
+            InfoIpTun::SixRdPrefixLen(_) => IFLA_IPTUN_6RD_PREFIXLEN,
+            InfoIpTun::SixRdRelayPrefixLen(_) => IFLA_IPTUN_6RD_RELAY_PREFIXLEN,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            InfoIpTun::Link(value) | InfoIpTun::Flags(value) => {
+                buffer.copy_from_slice(&value.to_ne_bytes())
+            }
+            InfoIpTun::FlowInfo(value) => buffer.copy_from_slice(&value.to_be_bytes()),
+            InfoIpTun::Local(IpAddr::V4(addr)) | InfoIpTun::Remote(IpAddr::V4(addr)) => {
+                buffer.copy_from_slice(&addr.octets())
+            }
+            InfoIpTun::Local(IpAddr::V6(addr)) | InfoIpTun::Remote(IpAddr::V6(addr)) => {
+                buffer.copy_from_slice(&addr.octets())
+            }
+            InfoIpTun::Ttl(value)
+            | InfoIpTun::Tos(value)
+            | InfoIpTun::EncapLimit(value)
+            | InfoIpTun::PMtuDisc(value) => buffer[0] = *value,
+            InfoIpTun::SitFlags(value)
+            | InfoIpTun::SixRdPrefixLen(value)
+            | InfoIpTun::SixRdRelayPrefixLen(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            InfoIpTun::SixRdPrefix(addr) => buffer.copy_from_slice(&addr.octets()),
+            InfoIpTun::SixRdRelayPrefix(addr) => buffer.copy_from_slice(&addr.octets()),
+        }
+    }
+}
+
+/// The configuration of an existing ipip tunnel, as read back from the kernel.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IpipConfig {
+    /// index of the underlying link, 0 when unbound
+    pub link: u32,
+    pub local: Ipv4Addr,
+    pub remote: Ipv4Addr,
+    pub ttl: u8,
+    pub tos: u8,
+    pub pmtudisc: bool,
+}
+
+impl Default for IpipConfig {
+    fn default() -> Self {
+        IpipConfig {
+            link: 0,
+            local: Ipv4Addr::UNSPECIFIED,
+            remote: Ipv4Addr::UNSPECIFIED,
+            ttl: 0,
+            tos: 0,
+            pmtudisc: false,
+        }
+    }
+}
+
+/// The configuration of an existing sit tunnel, as read back from the kernel.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct SitConfig {
+    pub tunnel: IpipConfig,
+    pub isatap: bool,
+}
+
+impl IpipConfig {
+    /// Parses the IFLA_IPTUN_* attributes of IFLA_INFO_DATA.
+    pub fn from_info_data(data: &[u8]) -> Result<IpipConfig> {
+        Ok(SitConfig::from_info_data(data)?.tunnel)
+    }
+}
+
+impl SitConfig {
+    /// Parses the IFLA_IPTUN_* attributes of IFLA_INFO_DATA, a superset of the ipip ones.
+    pub fn from_info_data(data: &[u8]) -> Result<SitConfig> {
+        let mut config = SitConfig::default();
+        for nla in NlasIterator::new(data) {
+            let nla = nla?;
+            let value = nla.value();
+            let tunnel = &mut config.tunnel;
+            match (nla.kind(), value.len()) {
+                (IFLA_IPTUN_LINK, 4) => tunnel.link = u32::from_ne_bytes(value.try_into()?),
+                (IFLA_IPTUN_LOCAL, 4) => tunnel.local = Ipv4Addr::from(<[u8; 4]>::try_from(value)?),
+                (IFLA_IPTUN_REMOTE, 4) => {
+                    tunnel.remote = Ipv4Addr::from(<[u8; 4]>::try_from(value)?)
+                }
+                (IFLA_IPTUN_TTL, 1) => tunnel.ttl = value[0],
+                (IFLA_IPTUN_TOS, 1) => tunnel.tos = value[0],
+                (IFLA_IPTUN_PMTUDISC, 1) => tunnel.pmtudisc = value[0] != 0,
+                (IFLA_IPTUN_FLAGS, 2) => {
+                    config.isatap = u16::from_ne_bytes(value.try_into()?) & SIT_ISATAP != 0
+                }
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum TunnelType {
+    /// gre and gretap
+    Gre(GreConfig),
+    Vxlan(VxlanConfig),
+    Ipip(IpipConfig),
+    Sit(SitConfig),
+    Geneve(GeneveConfig),
+}
+
+impl TunnelType {
+    /// The tunnel configuration in info, None for links that are not tunnels.
+    pub fn from_raw(info: &RawLinkInfo) -> Result<Option<TunnelType>> {
+        let data = info.data.as_slice();
+        Ok(Some(match info.kind.as_str() {
+            "gre" | "gretap" => TunnelType::Gre(GreConfig::from_info_data(data)?),
+            "vxlan" => TunnelType::Vxlan(VxlanConfig::from_info_data(data)?),
+            "ipip" => TunnelType::Ipip(IpipConfig::from_info_data(data)?),
+            "sit" => TunnelType::Sit(SitConfig::from_info_data(data)?),
+            "geneve" => TunnelType::Geneve(GeneveConfig::from_info_data(data)?),
+            _ => return Ok(None),
+        }))
+    }
+}
+
+/// ip -d link show name, for tunnels. Returns None for links that are not tunnels.
+/// The link is read with get_link_info_raw, in the network namespace of the calling thread.
+pub fn get_tunnel_type(name: &str) -> Result<Option<TunnelType>> {
+    TunnelType::from_raw(&get_link_info_raw(name)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use rtnetlink::new_connection;
+
+    use crate::ip::iplink::{emit_nlas, Action, IPLink, LinkTypeEnum, RawLinkInfo};
+    use crate::ip::iptun::{get_tunnel_type, InfoIpTun, IpipConfig, SitConfig, TunnelType};
+    use crate::ip::testutil::del_link;
+    use crate::ip::veth::Veth;
+    use crate::ip::vxlan::Vxlan;
+
+    #[test]
+    fn test_tunnel_type_from_raw() {
+        let local = Ipv4Addr::new(192, 0, 2, 1);
+        let remote = Ipv4Addr::new(192, 0, 2, 2);
+        let data = emit_nlas(&[
+            InfoIpTun::Local(local.into()),
+            InfoIpTun::Remote(remote.into()),
+            InfoIpTun::Ttl(64),
+        ]);
+        let tunnel = IpipConfig {
+            local,
+            remote,
+            ttl: 64,
+            ..Default::default()
+        };
+        let raw = |kind: &str| RawLinkInfo {
+            kind: kind.to_string(),
+            data: data.clone(),
+        };
+
+        assert_eq!(
+            TunnelType::from_raw(&raw("ipip")).unwrap(),
+            Some(TunnelType::Ipip(tunnel.clone()))
+        );
+        assert_eq!(
+            TunnelType::from_raw(&raw("sit")).unwrap(),
+            Some(TunnelType::Sit(SitConfig {
+                tunnel,
+                isatap: false
+            }))
+        );
+        assert!(matches!(
+            TunnelType::from_raw(&raw("gre")).unwrap(),
+            Some(TunnelType::Gre(_))
+        ));
+        assert_eq!(TunnelType::from_raw(&raw("bridge")).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_tunnel_type() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let links = [
+            (
+                "tuntype0",
+                LinkTypeEnum::Vxlan(Vxlan {
+                    vni: 44,
+                    remote: Some("192.0.2.2".parse().unwrap()),
+                    ..Default::default()
+                }),
+            ),
+            (
+                "tuntype1",
+                LinkTypeEnum::Veth(Veth {
+                    peer_name: "tuntype2".to_string(),
+                    options: vec![],
+                    auto_up: false,
+                }),
+            ),
+        ];
+        for (name, link_type) in links.iter() {
+            IPLink {
+                action: Action::Add,
+                name: name.to_string(),
+                options: vec![],
+                link_type: Some(link_type.clone()),
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+        }
+        let vxlan = get_tunnel_type("tuntype0");
+        let veth = get_tunnel_type("tuntype1");
+        for (name, _) in links.iter() {
+            del_link(&mut handle, name).await.unwrap();
+        }
+
+        match vxlan.unwrap() {
+            Some(TunnelType::Vxlan(config)) => assert_eq!(config.vni, 44),
+            tunnel => panic!("unexpected tunnel {:?}", tunnel),
+        }
+        assert_eq!(veth.unwrap(), None);
+    }
+}
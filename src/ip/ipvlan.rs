@@ -0,0 +1,120 @@
+use anyhow::Result;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoIpVlan, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{get_link_index, LinkTypeTrait};
+
+pub const IPVLAN_MODE_L2: u16 = 0;
+pub const IPVLAN_MODE_L3: u16 = 1;
+pub const IPVLAN_MODE_L3S: u16 = 2;
+
+pub const IPVLAN_F_PRIVATE: u16 = 1;
+pub const IPVLAN_F_VEPA: u16 = 2;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum IpVlanMode {
+    L2,
+    L3,
+    /// L3 with the netfilter hooks of the slave namespace
+    L3S,
+}
+
+impl IpVlanMode {
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            IpVlanMode::L2 => IPVLAN_MODE_L2,
+            IpVlanMode::L3 => IPVLAN_MODE_L3,
+            IpVlanMode::L3S => IPVLAN_MODE_L3S,
+        }
+    }
+}
+
+/// How slaves of the same parent reach each other.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum IpVlanFlags {
+    /// switched inside the parent, the default
+    Bridge,
+    /// not at all
+    Private,
+    /// only through the external switch
+    Vepa,
+}
+
+impl IpVlanFlags {
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            IpVlanFlags::Bridge => 0,
+            IpVlanFlags::Private => IPVLAN_F_PRIVATE,
+            IpVlanFlags::Vepa => IPVLAN_F_VEPA,
+        }
+    }
+}
+
+/// ip link add link parent name type ipvlan mode mode flags
+/// Unlike a macvlan, every slave shares the MAC address of parent.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IpVlan {
+    pub parent: String,
+    pub mode: IpVlanMode,
+    pub flags: IpVlanFlags,
+}
+
+impl LinkTypeTrait for IpVlan {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Link(get_link_index(&self.parent)?));
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::IpVlan),
+            Info::Data(InfoData::IpVlan(vec![
+                InfoIpVlan::Mode(self.mode.as_u16()),
+                InfoIpVlan::Flags(self.flags.as_u16()),
+            ])),
+        ]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoIpVlan, Nla};
+    use netlink_packet_route::traits::{Emitable, Parseable};
+    use netlink_packet_route::{LinkMessage, LinkMessageBuffer};
+
+    use crate::ip::iplink::LinkTypeTrait;
+    use crate::ip::ipvlan::{IpVlan, IpVlanFlags, IpVlanMode, IPVLAN_F_VEPA, IPVLAN_MODE_L3S};
+
+    #[test]
+    fn test_ipvlan_round_trip() {
+        let mut message = LinkMessage::default();
+        IpVlan {
+            parent: "lo".to_string(),
+            mode: IpVlanMode::L3S,
+            flags: IpVlanFlags::Vepa,
+        }
+        .link_type(&mut message)
+        .unwrap();
+
+        let mut buffer = vec![0; message.buffer_len()];
+        message.emit(&mut buffer);
+        let parsed = LinkMessage::parse(&LinkMessageBuffer::new(&buffer)).unwrap();
+        assert_eq!(parsed, message);
+
+        let data = parsed
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Info(infos) => infos.iter().find_map(|info| match info {
+                    Info::Data(InfoData::IpVlan(data)) => Some(data.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            data,
+            vec![
+                InfoIpVlan::Mode(IPVLAN_MODE_L3S),
+                InfoIpVlan::Flags(IPVLAN_F_VEPA)
+            ]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::Result;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, InfoMacVlan, Nla};
+use netlink_packet_route::{
+    LinkMessage, NetlinkMessage, RtnlMessage, MACVLAN_MODE_BRIDGE, MACVLAN_MODE_PASSTHRU,
+    MACVLAN_MODE_PRIVATE, MACVLAN_MODE_SOURCE, MACVLAN_MODE_VEPA, NLM_F_ACK, NLM_F_REQUEST,
+};
+use rtnetlink::Handle;
+
+use super::iplink::{get_link_index, name, request_ack, LinkTypeTrait};
+
+pub const MACVLAN_MACADDR_ADD: u32 = 0;
+pub const MACVLAN_MACADDR_DEL: u32 = 1;
+pub const MACVLAN_MACADDR_FLUSH: u32 = 2;
+pub const MACVLAN_MACADDR_SET: u32 = 3;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum MacVlanMode {
+    Private,
+    VEPA,
+    Bridge,
+    Passthru,
+    Source,
+}
+
+impl MacVlanMode {
+    /// MACVLAN_MODE_*, shared by IFLA_MACVLAN_MODE and IFLA_MACVTAP_MODE.
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            MacVlanMode::Private => MACVLAN_MODE_PRIVATE,
+            MacVlanMode::VEPA => MACVLAN_MODE_VEPA,
+            MacVlanMode::Bridge => MACVLAN_MODE_BRIDGE,
+            MacVlanMode::Passthru => MACVLAN_MODE_PASSTHRU,
+            MacVlanMode::Source => MACVLAN_MODE_SOURCE,
+        }
+    }
+}
+
+/// Source MACs accepted by a macvlan in source mode.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct MacVlanSourceList {
+    pub macs: Vec<[u8; 6]>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct MacVlan {
+    pub parent: String,
+    pub mode: MacVlanMode,
+    pub source_list: MacVlanSourceList,
+}
+
+impl MacVlan {
+    /// ip link add link parent type macvlan mode mode
+    pub fn new(parent: &str, mode: MacVlanMode) -> MacVlan {
+        MacVlan {
+            parent: parent.to_string(),
+            mode,
+            source_list: MacVlanSourceList::default(),
+        }
+    }
+
+    /// ip link add link parent type macvlan mode source macaddr set macs...
+    pub fn source(parent: &str, macs: Vec<[u8; 6]>) -> MacVlan {
+        MacVlan {
+            parent: parent.to_string(),
+            mode: MacVlanMode::Source,
+            source_list: MacVlanSourceList { macs },
+        }
+    }
+}
+
+impl LinkTypeTrait for MacVlan {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Link(get_link_index(&self.parent)?));
+
+        let mut info = vec![InfoMacVlan::Mode(self.mode.as_u32())];
+        if self.mode == MacVlanMode::Source && !self.source_list.macs.is_empty() {
+            // MACVLAN_MACADDR_ADD only takes a single IFLA_MACVLAN_MACADDR,
+            // so the whole list is installed with MACVLAN_MACADDR_SET instead.
+            info.push(InfoMacVlan::MacAddrMode(MACVLAN_MACADDR_SET));
+            info.push(InfoMacVlan::MacAddrData(
+                self.source_list
+                    .macs
+                    .iter()
+                    .map(|mac| InfoMacVlan::MacAddr(*mac))
+                    .collect(),
+            ));
+        }
+
+        let mut link_info_nlas = vec![Info::Kind(InfoKind::MacVlan)];
+        link_info_nlas.push(Info::Data(InfoData::MacVlan(info)));
+        message.nlas.push(Nla::Info(link_info_nlas));
+        Ok(())
+    }
+}
+
+async fn macvlan_source_change(
+    handle: &mut Handle,
+    macvlan: &str,
+    mode: u32,
+    mac: [u8; 6],
+) -> Result<()> {
+    let mut message = LinkMessage::default();
+    name(macvlan, &mut message);
+    message.nlas.push(Nla::Info(vec![
+        Info::Kind(InfoKind::MacVlan),
+        Info::Data(InfoData::MacVlan(vec![
+            InfoMacVlan::MacAddrMode(mode),
+            InfoMacVlan::MacAddr(mac),
+        ])),
+    ]));
+
+    let mut req = NetlinkMessage::from(RtnlMessage::NewLink(message));
+    req.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+
+    request_ack(handle, req).await
+}
+
+/// ip link set macvlan type macvlan macaddr add mac
+pub async fn macvlan_add_mac(handle: &mut Handle, macvlan: &str, mac: [u8; 6]) -> Result<()> {
+    macvlan_source_change(handle, macvlan, MACVLAN_MACADDR_ADD, mac).await
+}
+
+/// ip link set macvlan type macvlan macaddr del mac
+pub async fn macvlan_del_mac(handle: &mut Handle, macvlan: &str, mac: [u8; 6]) -> Result<()> {
+    macvlan_source_change(handle, macvlan, MACVLAN_MACADDR_DEL, mac).await
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoMacVlan, Nla};
+    use rtnetlink::new_connection;
+
+    use crate::ip::iplink::{get_link, Action, IPLink, LinkTypeEnum};
+    use crate::ip::macvlan::{macvlan_add_mac, MacVlan, MacVlanMode};
+    use crate::ip::testutil::{add_veth, del_link};
+
+    #[tokio::test]
+    async fn test_macvlan_mode() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "mvmode0", "mvmode1", false)
+            .await
+            .unwrap();
+
+        let mut modes = vec![];
+        for (name, mode) in [
+            ("macvlan1", MacVlanMode::Private),
+            ("macvlan2", MacVlanMode::VEPA),
+            ("macvlan3", MacVlanMode::Bridge),
+        ] {
+            let expected = mode.as_u32();
+            IPLink {
+                action: Action::Add,
+                name: name.to_string(),
+                options: vec![],
+                link_type: Some(LinkTypeEnum::MacVlan(MacVlan::new("mvmode0", mode))),
+            }
+            .execute(&mut handle)
+            .await
+            .unwrap();
+            let link = get_link(&handle, name).await.unwrap();
+            let mode = link.nlas.iter().find_map(|nla| match nla {
+                Nla::Info(infos) => infos.iter().find_map(|info| match info {
+                    Info::Data(InfoData::MacVlan(data)) => {
+                        data.iter().find_map(|data| match data {
+                            InfoMacVlan::Mode(mode) => Some(*mode),
+                            _ => None,
+                        })
+                    }
+                    _ => None,
+                }),
+                _ => None,
+            });
+            modes.push((mode, expected));
+        }
+
+        del_link(&mut handle, "mvmode0").await.unwrap();
+
+        for (mode, expected) in modes {
+            assert_eq!(mode, Some(expected));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_macvlan_source() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "mvsrc0", "mvsrc1", false)
+            .await
+            .unwrap();
+
+        IPLink {
+            action: Action::Add,
+            name: "macvlan0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::MacVlan(MacVlan::source("mvsrc0", vec![]))),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let macs = [[0x02, 0, 0, 0, 0, 1], [0x02, 0, 0, 0, 0, 2]];
+        for mac in macs {
+            macvlan_add_mac(&mut handle, "macvlan0", mac).await.unwrap();
+        }
+        let link = get_link(&handle, "macvlan0").await.unwrap();
+
+        del_link(&mut handle, "mvsrc0").await.unwrap();
+
+        let entries: Vec<[u8; 6]> = link
+            .nlas
+            .iter()
+            .filter_map(|nla| match nla {
+                Nla::Info(infos) => Some(infos),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|info| match info {
+                Info::Data(InfoData::MacVlan(data)) => Some(data),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|data| match data {
+                InfoMacVlan::MacAddrData(entries) => Some(entries),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|entry| match entry {
+                InfoMacVlan::MacAddr(mac) => Some(*mac),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(entries.len(), 2);
+        for mac in macs {
+            assert!(entries.contains(&mac));
+        }
+    }
+}
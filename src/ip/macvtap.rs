@@ -0,0 +1,145 @@
+use anyhow::Result;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, InfoMacVtap, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{get_link_index, LinkTypeTrait};
+use super::macvlan::MacVlanMode;
+
+/// The modes of a macvtap are those of a macvlan.
+pub type MacVtapMode = MacVlanMode;
+
+/// ip link add link parent name type macvtap mode mode
+/// A macvlan whose packets are also exposed through a character device, which udev or devtmpfs
+/// creates as /dev/tap<index> with index the link index of the macvtap. Hypervisors open that
+/// file to read and write the frames of the guest.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct MacVtap {
+    pub parent: String,
+    pub mode: MacVtapMode,
+}
+
+impl LinkTypeTrait for MacVtap {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Link(get_link_index(&self.parent)?));
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::MacVtap),
+            Info::Data(InfoData::MacVtap(vec![InfoMacVtap::Mode(
+                self.mode.as_u32(),
+            )])),
+        ]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use netlink_packet_route::nlas::NlasIterator;
+    use netlink_packet_route::rtnl::link::nlas::InfoMacVtap;
+    use netlink_packet_route::traits::Parseable;
+    use netlink_packet_route::MACVLAN_MODE_BRIDGE;
+    use rtnetlink::new_connection;
+
+    use crate::ip::iplink::{get_link_index, get_link_info_raw, Action, IPLink, LinkTypeEnum};
+    use crate::ip::macvlan::{MacVlan, MacVlanMode};
+    use crate::ip::macvtap::{MacVtap, MacVtapMode};
+    use crate::ip::testutil::{add_veth, del_link};
+
+    #[tokio::test]
+    async fn test_macvtap_shared_mode() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "mvtap0", "mvtap1", false)
+            .await
+            .unwrap();
+
+        let mode = MacVlanMode::Bridge;
+        let links = [
+            (
+                "mvtapvlan",
+                LinkTypeEnum::MacVlan(MacVlan {
+                    parent: "mvtap0".to_string(),
+                    mode: mode.clone(),
+                    source_list: Default::default(),
+                }),
+            ),
+            (
+                "mvtaptap",
+                LinkTypeEnum::MacVtap(MacVtap {
+                    parent: "mvtap0".to_string(),
+                    mode: mode.clone(),
+                }),
+            ),
+        ];
+        let mut created = vec![];
+        for (name, link_type) in links.iter() {
+            created.push(
+                IPLink {
+                    action: Action::Add,
+                    name: name.to_string(),
+                    options: vec![],
+                    link_type: Some(link_type.clone()),
+                }
+                .execute(&mut handle)
+                .await,
+            );
+        }
+        let tap = get_link_info_raw("mvtaptap");
+
+        del_link(&mut handle, "mvtap0").await.unwrap();
+
+        for res in created {
+            res.unwrap();
+        }
+        assert_eq!(mode.as_u32(), MACVLAN_MODE_BRIDGE);
+        let tap = tap.unwrap();
+        assert_eq!(tap.kind, "macvtap");
+        let modes: Vec<InfoMacVtap> = NlasIterator::new(&tap.data)
+            .filter_map(|nla| InfoMacVtap::parse(&nla.ok()?).ok())
+            .filter(|nla| matches!(nla, InfoMacVtap::Mode(_)))
+            .collect();
+        assert_eq!(modes, vec![InfoMacVtap::Mode(MACVLAN_MODE_BRIDGE)]);
+    }
+
+    #[tokio::test]
+    async fn test_macvtap_dev_file() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "mvtapdev0", "mvtapdev1", false)
+            .await
+            .unwrap();
+        let created = IPLink {
+            action: Action::Add,
+            name: "mvtapdev".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::MacVtap(MacVtap {
+                parent: "mvtapdev0".to_string(),
+                mode: MacVtapMode::VEPA,
+            })),
+        }
+        .execute(&mut handle)
+        .await;
+        let dev_file = get_link_index("mvtapdev").map(|index| format!("/dev/tap{}", index));
+        // devtmpfs creates the file asynchronously
+        let mut exists = false;
+        if let Ok(dev_file) = &dev_file {
+            for _ in 0..50 {
+                exists = Path::new(dev_file).exists();
+                if exists {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+
+        del_link(&mut handle, "mvtapdev0").await.unwrap();
+
+        created.unwrap();
+        let dev_file = dev_file.unwrap();
+        assert!(exists, "{} does not exist", dev_file);
+        assert!(!Path::new(&dev_file).exists());
+    }
+}
@@ -1,5 +1,58 @@
+/// A newtype over the bits of a netlink flags field, with a constant per flag, contains and |.
+/// See AddressFlags for its use.
+macro_rules! flags {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident($bits:ty) {
+            $($(#[$flag_attr:meta])* const $flag:ident = $value:expr;)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+        pub struct $name(pub $bits);
+
+        impl $name {
+            $($(#[$flag_attr])* pub const $flag: $name = $name($value);)*
+
+            pub fn contains(&self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+        }
+
+        impl std::ops::BitOr for $name {
+            type Output = $name;
+
+            fn bitor(self, rhs: $name) -> $name {
+                $name(self.0 | rhs.0)
+            }
+        }
+    };
+}
+
+pub mod bareudp;
+pub mod bond;
 pub mod bridge;
+pub mod dummy;
+pub mod ethtool;
+pub mod geneve;
+pub mod gre;
+pub mod ip6tnl;
+pub mod ipaddr;
 pub mod iplink;
+pub mod ipneigh;
 pub mod ipnetns;
 pub mod iproute;
+pub mod iprule;
+pub mod iptun;
+pub mod ipvlan;
+pub mod macvlan;
+pub mod macvtap;
+pub mod sit;
+#[cfg(test)]
+pub(crate) mod testutil;
+pub mod tuntap;
 pub mod veth;
+pub mod vlan;
+pub mod vrf;
+pub mod vxlan;
+pub mod wireguard;
@@ -0,0 +1,128 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use anyhow::Result;
+use ipnet::{Ipv4Net, Ipv6Net};
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{emit_nlas, LinkTypeTrait};
+use super::iptun::{InfoIpTun, SIT_ISATAP};
+
+/// ip link add name type sit local local [remote remote] ttl ttl [mode isatap]
+///     [6rd-prefix sixrd_prefix] [6rd-relay_prefix sixrd_relay]
+/// IPv6 over IPv4. With remote None the tunnel takes packets from any remote, and sends each
+/// packet to the IPv4 address embedded in its IPv6 destination.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Sit {
+    pub local: Ipv4Addr,
+    pub remote: Option<Ipv4Addr>,
+    pub ttl: u8,
+    pub isatap: bool,
+    /// the 6RD prefix of the provider, e.g. 2001:db8::/32
+    pub sixrd_prefix: Option<Ipv6Net>,
+    /// the common prefix of the IPv4 addresses of the provider, left out of the 6RD addresses
+    pub sixrd_relay: Option<Ipv4Net>,
+}
+
+impl Default for Sit {
+    fn default() -> Self {
+        Sit {
+            local: Ipv4Addr::UNSPECIFIED,
+            remote: None,
+            ttl: 0,
+            isatap: false,
+            sixrd_prefix: None,
+            sixrd_relay: None,
+        }
+    }
+}
+
+impl Sit {
+    fn info_iptun(&self) -> Vec<InfoIpTun> {
+        let remote = self.remote.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        let mut nlas = vec![
+            InfoIpTun::Local(IpAddr::V4(self.local)),
+            InfoIpTun::Remote(IpAddr::V4(remote)),
+            InfoIpTun::Ttl(self.ttl),
+            // as ip does, a fixed ttl needs path MTU discovery to keep DF set
+            InfoIpTun::PMtuDisc(1),
+        ];
+        if self.isatap {
+            nlas.push(InfoIpTun::SitFlags(SIT_ISATAP));
+        }
+        if let Some(prefix) = self.sixrd_prefix {
+            nlas.push(InfoIpTun::SixRdPrefix(prefix.network()));
+            nlas.push(InfoIpTun::SixRdPrefixLen(prefix.prefix_len() as u16));
+        }
+        if let Some(relay) = self.sixrd_relay {
+            nlas.push(InfoIpTun::SixRdRelayPrefix(relay.network()));
+            nlas.push(InfoIpTun::SixRdRelayPrefixLen(relay.prefix_len() as u16));
+        }
+        nlas
+    }
+}
+
+impl LinkTypeTrait for Sit {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::SitTun),
+            Info::Data(InfoData::SitTun(emit_nlas(&self.info_iptun()))),
+        ]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use netlink_packet_route::nlas::NlasIterator;
+    use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, Nla};
+    use netlink_packet_route::LinkMessage;
+
+    use crate::ip::iplink::LinkTypeTrait;
+    use crate::ip::iptun::{SitConfig, IFLA_IPTUN_6RD_PREFIXLEN, IFLA_IPTUN_6RD_RELAY_PREFIX};
+    use crate::ip::sit::Sit;
+
+    #[test]
+    fn test_sit_info_data() {
+        let mut message = LinkMessage::default();
+        Sit {
+            local: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 64,
+            isatap: true,
+            sixrd_prefix: Some("2001:db8::/32".parse().unwrap()),
+            sixrd_relay: Some("192.0.2.0/24".parse().unwrap()),
+            ..Default::default()
+        }
+        .link_type(&mut message)
+        .unwrap();
+
+        let data = message
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Info(infos) => infos.iter().find_map(|info| match info {
+                    Info::Data(InfoData::SitTun(data)) => Some(data.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            })
+            .unwrap();
+        let config = SitConfig::from_info_data(&data).unwrap();
+        assert!(config.isatap);
+        assert_eq!(config.tunnel.local, Ipv4Addr::new(192, 0, 2, 1));
+        // any remote
+        assert_eq!(config.tunnel.remote, Ipv4Addr::UNSPECIFIED);
+        assert_eq!(config.tunnel.ttl, 64);
+
+        let nlas = NlasIterator::new(data.as_slice())
+            .map(|nla| {
+                let nla = nla.unwrap();
+                (nla.kind(), nla.value().to_vec())
+            })
+            .collect::<Vec<_>>();
+        assert!(nlas.contains(&(IFLA_IPTUN_6RD_PREFIXLEN, 32u16.to_ne_bytes().to_vec())));
+        assert!(nlas.contains(&(IFLA_IPTUN_6RD_RELAY_PREFIX, vec![192, 0, 2, 0])));
+    }
+}
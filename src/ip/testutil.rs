@@ -0,0 +1,33 @@
+use anyhow::Result;
+use rtnetlink::Handle;
+
+use crate::ip::iplink::{Action, IPLink, LinkTypeEnum};
+use crate::ip::veth::Veth;
+
+/// ip link add name type veth peer name peer, with both ends brought up when up.
+pub async fn add_veth(handle: &mut Handle, name: &str, peer: &str, up: bool) -> Result<()> {
+    IPLink {
+        action: Action::Add,
+        name: name.to_string(),
+        options: vec![],
+        link_type: Some(LinkTypeEnum::Veth(Veth {
+            peer_name: peer.to_string(),
+            options: vec![],
+            auto_up: up,
+        })),
+    }
+    .execute(handle)
+    .await
+}
+
+/// ip link del name
+pub async fn del_link(handle: &mut Handle, name: &str) -> Result<()> {
+    IPLink {
+        action: Action::Delete,
+        name: name.to_string(),
+        options: vec![],
+        link_type: None,
+    }
+    .execute(handle)
+    .await
+}
@@ -0,0 +1,256 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+
+use anyhow::{anyhow, Result};
+use netlink_packet_route::nlas::Nla as NlaTrait;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{emit_nlas, LinkTypeTrait};
+use super::vlan::IFNAME_MAX_LEN;
+
+pub const IFLA_TUN_OWNER: u16 = 1;
+pub const IFLA_TUN_GROUP: u16 = 2;
+pub const IFLA_TUN_TYPE: u16 = 3;
+pub const IFLA_TUN_PI: u16 = 4;
+pub const IFLA_TUN_VNET_HDR: u16 = 5;
+pub const IFLA_TUN_PERSIST: u16 = 6;
+pub const IFLA_TUN_MULTI_QUEUE: u16 = 7;
+
+const IFF_TUN: i16 = 0x0001;
+const IFF_TAP: i16 = 0x0002;
+const IFF_NO_PI: i16 = 0x1000;
+const IFF_MULTI_QUEUE: i16 = 0x0100;
+
+const TUNSETIFF: u32 = 0x400454ca;
+const TUNSETPERSIST: u32 = 0x400454cb;
+const TUNSETOWNER: u32 = 0x400454cc;
+const TUNSETGROUP: u32 = 0x400454ce;
+
+/// struct ifreq with ifr_flags as the active member of the union.
+#[repr(C)]
+struct IfReq {
+    name: [u8; 16],
+    flags: i16,
+    padding: [u8; 22],
+}
+
+nix::ioctl_write_ptr_bad!(tun_set_iff, TUNSETIFF, IfReq);
+nix::ioctl_write_int_bad!(tun_set_persist, TUNSETPERSIST);
+nix::ioctl_write_int_bad!(tun_set_owner, TUNSETOWNER);
+nix::ioctl_write_int_bad!(tun_set_group, TUNSETGROUP);
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum TunTapMode {
+    /// IP packets
+    Tun,
+    /// Ethernet frames
+    Tap,
+}
+
+/// IFLA_TUN_* attributes nested in IFLA_INFO_DATA.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InfoTun {
+    Owner(u32),
+    Group(u32),
+    Type(u8),
+    Pi(bool),
+    VnetHdr(bool),
+    Persist(bool),
+    MultiQueue(bool),
+}
+
+impl NlaTrait for InfoTun {
+    fn value_len(&self) -> usize {
+        match self {
+            InfoTun::Owner(_) | InfoTun::Group(_) => 4,
+            _ => 1,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            InfoTun::Owner(_) => IFLA_TUN_OWNER,
+            InfoTun::Group(_) => IFLA_TUN_GROUP,
+            InfoTun::Type(_) => IFLA_TUN_TYPE,
+            InfoTun::Pi(_) => IFLA_TUN_PI,
+            InfoTun::VnetHdr(_) => IFLA_TUN_VNET_HDR,
+            InfoTun::Persist(_) => IFLA_TUN_PERSIST,
+            InfoTun::MultiQueue(_) => IFLA_TUN_MULTI_QUEUE,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            InfoTun::Owner(value) | InfoTun::Group(value) => {
+                buffer.copy_from_slice(&value.to_ne_bytes())
+            }
+            InfoTun::Type(value) => buffer[0] = *value,
+            InfoTun::Pi(value)
+            | InfoTun::VnetHdr(value)
+            | InfoTun::Persist(value)
+            | InfoTun::MultiQueue(value) => buffer[0] = *value as u8,
+        }
+    }
+}
+
+/// ip tuntap add name mode tun|tap user owner group group multi_queue
+/// The kernel only reports the IFLA_TUN_* attributes and refuses to create a tun over netlink,
+/// so IPLink::execute creates it through /dev/net/tun with TunTap::create instead. The link is
+/// then created in the network namespace of the calling thread, not in the one of the handle.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TunTap {
+    pub mode: TunTapMode,
+    /// the uid allowed to attach to the link besides CAP_NET_ADMIN
+    pub owner: Option<u32>,
+    /// the gid allowed to attach to the link besides CAP_NET_ADMIN
+    pub group: Option<u32>,
+    pub multi_queue: bool,
+    /// the link outlives the file it was created with, IPLink refuses to add one without
+    pub persist: bool,
+}
+
+impl TunTap {
+    fn mode_flag(&self) -> i16 {
+        match self.mode {
+            TunTapMode::Tun => IFF_TUN,
+            TunTapMode::Tap => IFF_TAP,
+        }
+    }
+
+    fn flags(&self) -> i16 {
+        let mut flags = self.mode_flag() | IFF_NO_PI;
+        if self.multi_queue {
+            flags |= IFF_MULTI_QUEUE;
+        }
+        flags
+    }
+
+    fn info_tun(&self) -> Vec<InfoTun> {
+        let mut nlas = vec![InfoTun::Type(
+            self.flags() as u8 & (IFF_TUN | IFF_TAP) as u8,
+        )];
+        if let Some(owner) = self.owner {
+            nlas.push(InfoTun::Owner(owner));
+        }
+        if let Some(group) = self.group {
+            nlas.push(InfoTun::Group(group));
+        }
+        nlas.push(InfoTun::Pi(false));
+        nlas.push(InfoTun::MultiQueue(self.multi_queue));
+        nlas.push(InfoTun::Persist(self.persist));
+        nlas
+    }
+
+    /// Creates the link name in the network namespace of the calling thread and returns the file
+    /// attached to it, reading and writing the packets of the link. Without persist the link goes
+    /// away with the file.
+    pub fn create(&self, name: &str) -> Result<File> {
+        if name.len() > IFNAME_MAX_LEN {
+            return Err(anyhow!("invalid interface name {}", name));
+        }
+        let mut req = IfReq {
+            name: [0; 16],
+            flags: self.flags(),
+            padding: [0; 22],
+        };
+        req.name[..name.len()].copy_from_slice(name.as_bytes());
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/net/tun")?;
+        let fd = file.as_raw_fd();
+        let res = unsafe { tun_set_iff(fd, &req) }.and_then(|_| {
+            if let Some(owner) = self.owner {
+                unsafe { tun_set_owner(fd, owner as _) }?;
+            }
+            if let Some(group) = self.group {
+                unsafe { tun_set_group(fd, group as _) }?;
+            }
+            if self.persist {
+                unsafe { tun_set_persist(fd, 1) }?;
+            }
+            Ok(())
+        });
+        if let Err(e) = res {
+            return Err(anyhow!("creating tun {} failed: {}", name, e));
+        }
+        Ok(file)
+    }
+}
+
+impl LinkTypeTrait for TunTap {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::Tun),
+            Info::Data(InfoData::Tun(emit_nlas(&self.info_tun()))),
+        ]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rtnetlink::new_connection;
+
+    use crate::error::IprouteError;
+    use crate::ip::iplink::{link_exists_checked, Action, IPLink, LinkTypeEnum};
+    use crate::ip::testutil::del_link;
+    use crate::ip::tuntap::{TunTap, TunTapMode};
+
+    #[tokio::test]
+    async fn test_tuntap_persist() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "tun0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::TunTap(TunTap {
+                mode: TunTapMode::Tun,
+                owner: Some(1000),
+                group: None,
+                multi_queue: true,
+                persist: true,
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        let exists = link_exists_checked("tun0");
+
+        del_link(&mut handle, "tun0").await.unwrap();
+
+        assert!(exists.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tuntap_without_persist() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        let err = IPLink {
+            action: Action::Add,
+            name: "tun1".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::TunTap(TunTap {
+                mode: TunTapMode::Tap,
+                owner: None,
+                group: None,
+                multi_queue: false,
+                persist: false,
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        assert!(!link_exists_checked("tun1").unwrap());
+    }
+}
@@ -1,13 +1,23 @@
 use anyhow::Result;
+use ipnet::IpNet;
 use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, Nla, VethInfo};
 use netlink_packet_route::LinkMessage;
+use rtnetlink::{new_connection, Handle};
 
-use super::iplink::{name, options, LinkTypeTrait, Opt};
+use super::iplink::{
+    get_link_name_by_index, get_link_without_info_data, name, options, resolve_link_index, Action,
+    IPLink, LinkTypeEnum, LinkTypeTrait, Opt,
+};
+use crate::error::{undone, IprouteError};
+use crate::ip::ipaddr::{AddrAction, IPAddr};
+use crate::ip::ipnetns::thread_net_ns_block_on;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Veth {
     pub peer_name: String,
     pub options: Vec<Opt>,
+    /// bring both ends up once the pair is created
+    pub auto_up: bool,
 }
 
 impl LinkTypeTrait for Veth {
@@ -21,3 +31,330 @@ impl LinkTypeTrait for Veth {
         Ok(())
     }
 }
+
+/// The other end of a veth pair.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct VethPeerInfo {
+    /// the index of the peer in its own namespace
+    pub peer_ifindex: u32,
+    /// None when the peer is in another namespace, where the index cannot be resolved
+    pub peer_name: Option<String>,
+}
+
+/// ip link show veth_name, the peer being the @ part of the name.
+/// The kernel does not report the veth peer in IFLA_INFO_DATA, only as IFLA_LINK, with
+/// IFLA_LINK_NETNSID added when the peer lives in another namespace.
+/// The link is read with get_link_without_info_data, in the network namespace of the calling
+/// thread.
+pub fn veth_peer_info(veth_name: &str) -> Result<VethPeerInfo> {
+    let link = get_link_without_info_data(veth_name)?;
+    let is_veth = link.nlas.iter().any(|nla| match nla {
+        Nla::Info(infos) => infos.contains(&Info::Kind(InfoKind::Veth)),
+        _ => false,
+    });
+    if !is_veth {
+        return Err(IprouteError::InvalidArgument(format!("{} is not a veth", veth_name)).into());
+    }
+
+    let peer_ifindex = link
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Link(index) => Some(*index),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("{} has no peer", veth_name))?;
+    let other_ns = link.nlas.iter().any(|nla| matches!(nla, Nla::NetnsId(_)));
+    let peer_name = if other_ns {
+        None
+    } else {
+        Some(get_link_name_by_index(peer_ifindex)?)
+    };
+    Ok(VethPeerInfo {
+        peer_ifindex,
+        peer_name,
+    })
+}
+
+/// One end of a veth pair set up by setup_veth_pair.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct VethEndSpec {
+    pub name: String,
+    pub addr: IpNet,
+    /// left to the kernel to pick at random when None
+    pub mac: Option<[u8; 6]>,
+}
+
+async fn configure_veth_end(handle: &mut Handle, spec: &VethEndSpec) -> Result<()> {
+    IPAddr::new(
+        AddrAction::Add,
+        spec.addr.addr(),
+        spec.addr.prefix_len(),
+        &spec.name,
+    )
+    .execute(handle)
+    .await?;
+    IPLink {
+        action: Action::Set,
+        name: spec.name.clone(),
+        options: vec![Opt::Up],
+        link_type: None,
+    }
+    .execute(handle)
+    .await
+}
+
+/// ip link add host.name type veth peer name container.name &&
+/// ip link set container.name netns container_ns &&
+/// ip addr add host.addr dev host.name && ip link set host.name up &&
+/// ip -n container_ns addr add container.addr dev container.name &&
+/// ip -n container_ns link set container.name up
+/// The MAC addresses are set before the container end is moved. When any step fails the pair is
+/// deleted again, wherever its ends are.
+pub async fn setup_veth_pair(
+    handle: &mut Handle,
+    host_spec: VethEndSpec,
+    container_spec: VethEndSpec,
+    container_ns: &str,
+) -> Result<()> {
+    IPLink {
+        action: Action::Add,
+        name: host_spec.name.clone(),
+        options: vec![],
+        link_type: Some(LinkTypeEnum::Veth(Veth {
+            peer_name: container_spec.name.clone(),
+            options: vec![],
+            auto_up: false,
+        })),
+    }
+    .execute(handle)
+    .await?;
+
+    let res = configure_veth_pair(handle, &host_spec, container_spec, container_ns).await;
+    if let Err(e) = res {
+        // deleting either end deletes the pair
+        let undo = IPLink {
+            action: Action::Delete,
+            name: host_spec.name.clone(),
+            options: vec![],
+            link_type: None,
+        }
+        .execute(handle)
+        .await;
+        return Err(undone(
+            e,
+            undo,
+            &format!("the creation of {}", host_spec.name),
+        ));
+    }
+    Ok(())
+}
+
+async fn configure_veth_pair(
+    handle: &mut Handle,
+    host_spec: &VethEndSpec,
+    container_spec: VethEndSpec,
+    container_ns: &str,
+) -> Result<()> {
+    for spec in [host_spec, &container_spec] {
+        if let Some(mac) = spec.mac {
+            handle
+                .link()
+                .set(resolve_link_index(handle, &spec.name).await?)
+                .address(mac.to_vec())
+                .execute()
+                .await?;
+        }
+    }
+    IPLink {
+        action: Action::Set,
+        name: container_spec.name.clone(),
+        options: vec![Opt::NetNS(container_ns.to_string())],
+        link_type: None,
+    }
+    .execute(handle)
+    .await?;
+
+    configure_veth_end(handle, host_spec).await?;
+
+    // the namespace thread is joined on a blocking task, not to stall the caller's runtime
+    let container_ns = container_ns.to_string();
+    tokio::task::spawn_blocking(move || {
+        thread_net_ns_block_on(container_ns, move || async move {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            configure_veth_end(&mut handle, &container_spec).await
+        })
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::nlas::address::Nla as AddressNla;
+    use netlink_packet_route::rtnl::link::nlas::Nla;
+    use netlink_packet_route::{AddressMessage, IFF_UP};
+    use rtnetlink::new_connection;
+    use serial_test::serial;
+
+    use crate::ip::ipaddr::get_addresses;
+    use crate::ip::iplink::{get_link_index, get_link_without_info_data, Action, IPLink, Opt};
+    use crate::ip::ipnetns::{
+        ip_net_ns_add, ip_net_ns_del, thread_net_ns_block_on, thread_net_ns_exec,
+    };
+    use crate::ip::testutil::{add_veth, del_link};
+    use crate::ip::veth::{setup_veth_pair, veth_peer_info, VethEndSpec};
+
+    #[tokio::test]
+    async fn test_veth_peer_info() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "vpeer0", "vpeer1", false)
+            .await
+            .unwrap();
+
+        let peer_index = get_link_index("vpeer1");
+        let info = veth_peer_info("vpeer0");
+
+        del_link(&mut handle, "vpeer0").await.unwrap();
+
+        let info = info.unwrap();
+        assert_eq!(info.peer_ifindex, peer_index.unwrap());
+        assert_eq!(info.peer_name.as_deref(), Some("vpeer1"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_veth_peer_info_other_ns() {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let (connection, mut handle, _) = new_connection().unwrap();
+                tokio::spawn(connection);
+                ip_net_ns_add("vnetns12".to_string()).unwrap();
+
+                add_veth(&mut handle, "vpeer2", "vpeer3", false)
+                    .await
+                    .unwrap();
+                IPLink {
+                    action: Action::Set,
+                    name: "vpeer3".to_string(),
+                    options: vec![Opt::NetNS("vnetns12".to_string())],
+                    link_type: None,
+                }
+                .execute(&mut handle)
+                .await
+                .unwrap();
+
+                let info = veth_peer_info("vpeer2");
+                // the pair goes away with the namespace
+                ip_net_ns_del("vnetns12".to_string(), false).unwrap();
+
+                assert_eq!(info.unwrap().peer_name, None);
+            });
+    }
+
+    #[test]
+    #[serial]
+    fn test_setup_veth_pair() {
+        ip_net_ns_add("vnetns17".to_string()).unwrap();
+        ip_net_ns_add("vnetns18".to_string()).unwrap();
+        let mac = [0x02, 0, 0, 0, 0, 0x18];
+        let host = thread_net_ns_block_on("vnetns17".to_string(), move || async move {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            setup_veth_pair(
+                &mut handle,
+                VethEndSpec {
+                    name: "vhost0".to_string(),
+                    addr: "10.17.0.1/24".parse()?,
+                    mac: None,
+                },
+                VethEndSpec {
+                    name: "vctr0".to_string(),
+                    addr: "10.17.0.2/24".parse()?,
+                    mac: Some(mac),
+                },
+                "vnetns18",
+            )
+            .await?;
+            Ok((
+                get_link_without_info_data("vhost0")?,
+                get_addresses(&handle, "vhost0").await?,
+            ))
+        });
+        let container = thread_net_ns_block_on("vnetns18".to_string(), || async {
+            let (connection, handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            Ok((
+                get_link_without_info_data("vctr0")?,
+                get_addresses(&handle, "vctr0").await?,
+            ))
+        });
+        ip_net_ns_del("vnetns17".to_string(), false).unwrap();
+        ip_net_ns_del("vnetns18".to_string(), false).unwrap();
+
+        let has_address = |addresses: &[AddressMessage], octets: Vec<u8>| {
+            addresses
+                .iter()
+                .any(|address| address.nlas.contains(&AddressNla::Address(octets.clone())))
+        };
+        let (host_link, host_addrs) = host.unwrap();
+        let (container_link, container_addrs) = container.unwrap();
+        assert_ne!(host_link.header.flags & IFF_UP, 0);
+        assert_ne!(container_link.header.flags & IFF_UP, 0);
+        assert!(has_address(&host_addrs, vec![10, 17, 0, 1]));
+        assert!(has_address(&container_addrs, vec![10, 17, 0, 2]));
+        assert!(container_link.nlas.contains(&Nla::Address(mac.to_vec())));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_setup_veth_pair_handle_namespace() {
+        ip_net_ns_add("vnetns45".to_string()).unwrap();
+        ip_net_ns_add("vnetns46".to_string()).unwrap();
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns45".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        // the pair is created in the namespace of handle, so the MAC must be set there too
+        let mac = [0x02, 0, 0, 0, 0, 0x45];
+        let res = setup_veth_pair(
+            &mut handle,
+            VethEndSpec {
+                name: "nshost0".to_string(),
+                addr: "10.45.0.1/24".parse().unwrap(),
+                mac: Some(mac),
+            },
+            VethEndSpec {
+                name: "nsctr0".to_string(),
+                addr: "10.45.0.2/24".parse().unwrap(),
+                mac: None,
+            },
+            "vnetns46",
+        )
+        .await;
+        let host_link = thread_net_ns_exec("vnetns45".to_string(), || {
+            get_link_without_info_data("nshost0")
+        })
+        .join()
+        .unwrap();
+        ip_net_ns_del("vnetns45".to_string(), false).unwrap();
+        ip_net_ns_del("vnetns46".to_string(), false).unwrap();
+
+        res.unwrap();
+        assert!(host_link
+            .unwrap()
+            .nlas
+            .contains(&Nla::Address(mac.to_vec())));
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::Result;
+use ipnet::IpNet;
+use netlink_packet_route::nlas::Nla as NlaTrait;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, InfoVlan, Nla};
+use netlink_packet_route::LinkMessage;
+use rtnetlink::Handle;
+
+use super::iplink::{
+    create_link_with_addr, emit_nlas, get_link_index, Action, IPLink, LinkTypeEnum, LinkTypeTrait,
+};
+use crate::error::{undone, IprouteError};
+
+/// IFNAMSIZ minus the trailing nul.
+pub const IFNAME_MAX_LEN: usize = 15;
+/// 0 and 4095 are reserved.
+pub const VLAN_VID_MAX: u16 = 4094;
+pub const ETH_P_8021Q: u16 = 0x8100;
+pub const ETH_P_8021AD: u16 = 0x88a8;
+pub const IFLA_VLAN_QOS_MAPPING: u16 = 1;
+pub const VLAN_FLAG_REORDER_HDR: u32 = 0x1;
+/// The PCP field of the tag is 3 bits.
+pub const VLAN_PRIO_MAX: u32 = 7;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum VlanProtocol {
+    /// 802.1Q, the default
+    Dot1Q,
+    /// 802.1ad, the outer tag of a QinQ stack
+    Dot1AD,
+}
+
+/// One IFLA_VLAN_QOS_MAPPING, a struct ifla_vlan_qos_mapping { from, to } in native order.
+/// In the ingress map from is the priority of the tag and to the skb priority, in the egress
+/// map the other way round.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct VlanQosMapping {
+    pub from: u32,
+    pub to: u32,
+}
+
+impl NlaTrait for VlanQosMapping {
+    fn value_len(&self) -> usize {
+        8
+    }
+
+    fn kind(&self) -> u16 {
+        IFLA_VLAN_QOS_MAPPING
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        buffer[..4].copy_from_slice(&self.from.to_ne_bytes());
+        buffer[4..].copy_from_slice(&self.to.to_ne_bytes());
+    }
+}
+
+/// ip link add link parent name type vlan id id protocol protocol reorder_hdr on|off
+///     ingress-qos-map from:to... egress-qos-map from:to...
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Vlan {
+    pub parent: String,
+    pub id: u16,
+    pub protocol: VlanProtocol,
+    /// the tag is removed from the frames handed to the stack, the kernel default
+    pub reorder_hdr: bool,
+    /// tag priority to skb priority, sent as nested IFLA_VLAN_INGRESS_QOS
+    pub ingress_qos: Vec<VlanQosMapping>,
+    /// skb priority to tag priority, sent as nested IFLA_VLAN_EGRESS_QOS
+    pub egress_qos: Vec<VlanQosMapping>,
+}
+
+impl Vlan {
+    pub fn new(parent: &str, id: u16) -> Vlan {
+        Vlan {
+            parent: parent.to_string(),
+            id,
+            protocol: VlanProtocol::Dot1Q,
+            reorder_hdr: true,
+            ingress_qos: vec![],
+            egress_qos: vec![],
+        }
+    }
+
+    /// Stacks a QinQ pair on dev: an 802.1ad vlan outer_vid on dev, and an 802.1Q vlan inner_vid
+    /// on top of it, named dev.outer_vid and dev.outer_vid.inner_vid.
+    /// Returns the names of the outer and inner links. The outer link is deleted again when the
+    /// inner one cannot be created.
+    pub async fn on_vlan(
+        handle: &mut Handle,
+        outer_vid: u16,
+        inner_vid: u16,
+        dev: &str,
+    ) -> Result<(String, String)> {
+        let outer = vlan_interface_name(dev, outer_vid)?;
+        let inner = vlan_interface_name(&outer, inner_vid)?;
+
+        IPLink {
+            action: Action::Add,
+            name: outer.clone(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Vlan(Vlan {
+                protocol: VlanProtocol::Dot1AD,
+                ..Vlan::new(dev, outer_vid)
+            })),
+        }
+        .execute(handle)
+        .await?;
+
+        let res = IPLink {
+            action: Action::Add,
+            name: inner.clone(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Vlan(Vlan::new(&outer, inner_vid))),
+        }
+        .execute(handle)
+        .await;
+        if let Err(e) = res {
+            let undo = IPLink {
+                action: Action::Delete,
+                name: outer.clone(),
+                options: vec![],
+                link_type: None,
+            }
+            .execute(handle)
+            .await;
+            return Err(undone(e, undo, &format!("the creation of {}", outer)));
+        }
+        Ok((outer, inner))
+    }
+}
+
+impl LinkTypeTrait for Vlan {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        if self.id == 0 || self.id > VLAN_VID_MAX {
+            return Err(IprouteError::InvalidArgument(format!(
+                "vlan id {} is not in 1-{}",
+                self.id, VLAN_VID_MAX
+            ))
+            .into());
+        }
+        let tag_prio = self
+            .ingress_qos
+            .iter()
+            .map(|mapping| mapping.from)
+            .chain(self.egress_qos.iter().map(|mapping| mapping.to));
+        for prio in tag_prio {
+            if prio > VLAN_PRIO_MAX {
+                return Err(IprouteError::InvalidArgument(format!(
+                    "vlan priority {} is not in 0-{}",
+                    prio, VLAN_PRIO_MAX
+                ))
+                .into());
+            }
+        }
+        message.nlas.push(Nla::Link(get_link_index(&self.parent)?));
+
+        let protocol = match self.protocol {
+            VlanProtocol::Dot1Q => ETH_P_8021Q,
+            VlanProtocol::Dot1AD => ETH_P_8021AD,
+        };
+        let flags = if self.reorder_hdr {
+            VLAN_FLAG_REORDER_HDR
+        } else {
+            0
+        };
+        let mut info = vec![
+            InfoVlan::Id(self.id),
+            // emitted in native order but the kernel reads it in network order
+            InfoVlan::Protocol(protocol.to_be()),
+            // flags and the mask of the flags to change
+            InfoVlan::Flags((flags, VLAN_FLAG_REORDER_HDR)),
+        ];
+        if !self.ingress_qos.is_empty() {
+            info.push(InfoVlan::IngressQos(emit_nlas(&self.ingress_qos)));
+        }
+        if !self.egress_qos.is_empty() {
+            info.push(InfoVlan::EgressQos(emit_nlas(&self.egress_qos)));
+        }
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::Vlan),
+            Info::Data(InfoData::Vlan(info)),
+        ]));
+        Ok(())
+    }
+}
+
+/// The conventional name of the vid sub-interface of parent, e.g. eth0.100.
+pub fn vlan_interface_name(parent: &str, vid: u16) -> Result<String> {
+    let name = format!("{}.{}", parent, vid);
+    if name.len() > IFNAME_MAX_LEN {
+        return Err(IprouteError::InvalidArgument(format!(
+            "parent name {} is too long for vlan {}, {} is longer than {} bytes",
+            parent, vid, name, IFNAME_MAX_LEN
+        ))
+        .into());
+    }
+    Ok(name)
+}
+
+/// ip link add link parent name parent.vid type vlan id vid &&
+/// ip addr add addr dev parent.vid && ip link set parent.vid up
+/// Returns the name of the vlan link, which is deleted again when the address cannot be added.
+pub async fn setup_vlan_interface(
+    handle: &mut Handle,
+    parent: &str,
+    vid: u16,
+    addr: IpNet,
+) -> Result<String> {
+    let name = vlan_interface_name(parent, vid)?;
+    let link = IPLink {
+        action: Action::Add,
+        name: name.clone(),
+        options: vec![],
+        link_type: Some(LinkTypeEnum::Vlan(Vlan::new(parent, vid))),
+    };
+    create_link_with_addr(handle, &link, addr).await?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::nlas::address::Nla as AddressNla;
+    use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoVlan, Nla};
+    use netlink_packet_route::{LinkMessage, IFF_UP};
+    use rtnetlink::new_connection;
+
+    use crate::error::IprouteError;
+    use crate::ip::ipaddr::get_addresses;
+    use crate::ip::iplink::{get_link_index, get_link_without_info_data, LinkTypeTrait};
+    use crate::ip::testutil::{add_veth, del_link};
+    use crate::ip::vlan::{setup_vlan_interface, vlan_interface_name, Vlan, VlanQosMapping};
+
+    #[test]
+    fn test_vlan_qos_map() {
+        let mut message = LinkMessage::default();
+        Vlan {
+            reorder_hdr: false,
+            egress_qos: vec![VlanQosMapping { from: 2, to: 5 }],
+            ..Vlan::new("lo", 100)
+        }
+        .link_type(&mut message)
+        .unwrap();
+
+        let info = message
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                Nla::Info(infos) => infos.iter().find_map(|info| match info {
+                    Info::Data(InfoData::Vlan(info)) => Some(info.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            })
+            .unwrap();
+        assert!(info.contains(&InfoVlan::Flags((0, 1))));
+        // one IFLA_VLAN_QOS_MAPPING: length 12, type 1, from 2, to 5
+        let mut mapping = vec![12, 0, 1, 0];
+        mapping.extend_from_slice(&2u32.to_ne_bytes());
+        mapping.extend_from_slice(&5u32.to_ne_bytes());
+        assert!(info.contains(&InfoVlan::EgressQos(mapping)));
+
+        let err = Vlan {
+            ingress_qos: vec![VlanQosMapping { from: 8, to: 0 }],
+            ..Vlan::new("lo", 100)
+        }
+        .link_type(&mut LinkMessage::default())
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_vlan_interface_name() {
+        assert_eq!(vlan_interface_name("eth0", 100).unwrap(), "eth0.100");
+
+        let err = vlan_interface_name("enp0s31f6abcde", 100).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the 8021q kernel module"]
+    async fn test_vlan_qinq() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        // a veth stands in for the parent, the dummy module is not always available
+        add_veth(&mut handle, "qinq0", "qinq1", false)
+            .await
+            .unwrap();
+
+        let names = Vlan::on_vlan(&mut handle, 100, 200, "qinq0").await;
+        let outer = get_link_index("qinq0.100");
+        let inner = get_link_index("qinq0.100.200");
+
+        del_link(&mut handle, "qinq0").await.unwrap();
+
+        assert_eq!(
+            names.unwrap(),
+            ("qinq0.100".to_string(), "qinq0.100.200".to_string())
+        );
+        assert!(outer.is_ok());
+        assert!(inner.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the 8021q kernel module"]
+    async fn test_setup_vlan_interface() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "cni0", "cni1", false).await.unwrap();
+
+        let name =
+            setup_vlan_interface(&mut handle, "cni0", 10, "192.0.2.50/24".parse().unwrap()).await;
+        let link = get_link_without_info_data("cni0.10");
+        let addresses = get_addresses(&handle, "cni0.10").await;
+
+        del_link(&mut handle, "cni0").await.unwrap();
+
+        assert_eq!(name.unwrap(), "cni0.10");
+        assert_ne!(link.unwrap().header.flags & IFF_UP, 0);
+        assert!(addresses.unwrap().iter().any(|address| address
+            .nlas
+            .contains(&AddressNla::Address(vec![192, 0, 2, 50]))));
+    }
+}
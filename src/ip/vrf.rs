@@ -0,0 +1,84 @@
+use anyhow::Result;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, InfoVrf, Nla};
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::LinkTypeTrait;
+
+/// ip link add name type vrf table table
+/// Links enslaved to the VRF with Opt::Master route through table instead of the main table.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Vrf {
+    pub table: u32,
+}
+
+impl LinkTypeTrait for Vrf {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::Vrf),
+            Info::Data(InfoData::Vrf(vec![InfoVrf::TableId(self.table)])),
+        ]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::nlas::route::Nla as RouteNla;
+    use netlink_packet_route::RT_TABLE_MAIN;
+    use rtnetlink::{new_connection, IpVersion};
+    use serial_test::serial;
+
+    use crate::ip::dummy::Dummy;
+    use crate::ip::ipaddr::{AddrAction, IPAddr};
+    use crate::ip::iplink::{Action, IPLink, LinkTypeEnum, Opt};
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_block_on};
+    use crate::ip::iproute::{get_routes, route_table};
+    use crate::ip::vrf::Vrf;
+
+    #[test]
+    #[serial]
+    #[ignore = "needs the vrf and dummy kernel modules"]
+    fn test_vrf_route_isolation() {
+        ip_net_ns_add("vnetns23".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns23".to_string(), || async {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+
+            IPLink {
+                action: Action::Add,
+                name: "vrf0".to_string(),
+                options: vec![Opt::Up],
+                link_type: Some(LinkTypeEnum::Vrf(Vrf { table: 10 })),
+            }
+            .execute(&mut handle)
+            .await?;
+            IPLink {
+                action: Action::Add,
+                name: "vrfport0".to_string(),
+                options: vec![Opt::Master("vrf0".to_string()), Opt::Up],
+                link_type: Some(LinkTypeEnum::Dummy(Dummy)),
+            }
+            .execute(&mut handle)
+            .await?;
+            IPAddr::new(AddrAction::Add, "10.20.0.1".parse()?, 24, "vrfport0")
+                .execute(&mut handle)
+                .await?;
+
+            get_routes(&handle, IpVersion::V4).await
+        });
+        ip_net_ns_del("vnetns23".to_string(), false).unwrap();
+
+        let tables: Vec<u32> = res
+            .unwrap()
+            .iter()
+            .filter(|route| {
+                route
+                    .nlas
+                    .contains(&RouteNla::Destination(vec![10, 20, 0, 0]))
+            })
+            .map(route_table)
+            .collect();
+        assert!(tables.contains(&10));
+        assert!(!tables.contains(&(RT_TABLE_MAIN as u32)));
+    }
+}
@@ -0,0 +1,327 @@
+use std::net::IpAddr;
+
+use anyhow::{anyhow, Result};
+use netlink_packet_route::nlas::NlasIterator;
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoData, InfoKind, InfoVxlan, Nla};
+use netlink_packet_route::traits::Parseable;
+use netlink_packet_route::LinkMessage;
+
+use super::iplink::{get_link_index, get_link_info_raw, LinkTypeTrait};
+use crate::error::IprouteError;
+use crate::ip::ipaddr::ip_from_bytes;
+use crate::ip::iproute::ip_octets;
+
+/// VNIs are 24 bits wide.
+pub const VXLAN_VNI_MAX: u32 = (1 << 24) - 1;
+/// IANA assigned port.
+pub const VXLAN_PORT: u16 = 4789;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Vxlan {
+    pub vni: u32,
+    pub group: Option<IpAddr>,
+    pub local: Option<IpAddr>,
+    pub remote: Option<IpAddr>,
+    pub port: u16,
+    pub ttl: u8,
+    pub learning: bool,
+    pub rsc: bool,
+    pub proxy: bool,
+    pub l2miss: bool,
+    pub l3miss: bool,
+    pub udp_csum: bool,
+    /// the link the tunnel sends through, needed to join a multicast group
+    pub dev: Option<String>,
+    /// ip link add name type vxlan external
+    /// In metadata mode the FDB is bypassed and encapsulation is decided per packet (e.g. by an
+    /// eBPF program), so vni, group, remote and learning are not sent to the kernel.
+    pub collect_metadata: bool,
+}
+
+impl Default for Vxlan {
+    fn default() -> Self {
+        Vxlan {
+            vni: 0,
+            group: None,
+            local: None,
+            remote: None,
+            port: VXLAN_PORT,
+            ttl: 0,
+            learning: true,
+            rsc: false,
+            proxy: false,
+            l2miss: false,
+            l3miss: false,
+            udp_csum: false,
+            dev: None,
+            collect_metadata: false,
+        }
+    }
+}
+
+impl Vxlan {
+    pub fn collect_metadata(mut self) -> Self {
+        self.collect_metadata = true;
+        self
+    }
+
+    fn info_vxlan(&self) -> Result<Vec<InfoVxlan>> {
+        // netlink_packet_route emits the port in native byte order but the kernel reads it
+        // in network byte order
+        let mut nlas = vec![InfoVxlan::Port(self.port.to_be())];
+        if self.collect_metadata {
+            nlas.push(InfoVxlan::CollectMetadata(1));
+            return Ok(nlas);
+        }
+
+        if self.vni > VXLAN_VNI_MAX {
+            return Err(IprouteError::InvalidArgument(format!(
+                "vxlan id {} does not fit in 24 bits",
+                self.vni
+            ))
+            .into());
+        }
+        if self.group.is_some() && self.remote.is_some() {
+            return Err(IprouteError::InvalidArgument(
+                "vxlan group and remote are mutually exclusive".to_string(),
+            )
+            .into());
+        }
+
+        nlas.push(InfoVxlan::Id(self.vni));
+        if let Some(dev) = &self.dev {
+            nlas.push(InfoVxlan::Link(get_link_index(dev)?));
+        }
+        match self.group.or(self.remote) {
+            Some(addr @ IpAddr::V4(_)) => nlas.push(InfoVxlan::Group(ip_octets(addr))),
+            Some(addr @ IpAddr::V6(_)) => nlas.push(InfoVxlan::Group6(ip_octets(addr))),
+            None => {}
+        }
+        match self.local {
+            Some(addr @ IpAddr::V4(_)) => nlas.push(InfoVxlan::Local(ip_octets(addr))),
+            Some(addr @ IpAddr::V6(_)) => nlas.push(InfoVxlan::Local6(ip_octets(addr))),
+            None => {}
+        }
+        nlas.push(InfoVxlan::Ttl(self.ttl));
+        nlas.push(InfoVxlan::Learning(self.learning as u8));
+        nlas.push(InfoVxlan::Rsc(self.rsc as u8));
+        nlas.push(InfoVxlan::Proxy(self.proxy as u8));
+        nlas.push(InfoVxlan::L2Miss(self.l2miss as u8));
+        nlas.push(InfoVxlan::L3Miss(self.l3miss as u8));
+        nlas.push(InfoVxlan::UDPCsum(self.udp_csum as u8));
+        Ok(nlas)
+    }
+}
+
+impl LinkTypeTrait for Vxlan {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        let mut link_info_nlas = vec![Info::Kind(InfoKind::Vxlan)];
+        link_info_nlas.push(Info::Data(InfoData::Vxlan(self.info_vxlan()?)));
+        message.nlas.push(Nla::Info(link_info_nlas));
+        Ok(())
+    }
+}
+
+/// The configuration of an existing vxlan, as read back from the kernel.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct VxlanConfig {
+    pub vni: u32,
+    pub local: Option<IpAddr>,
+    pub remote: Option<IpAddr>,
+    pub group: Option<IpAddr>,
+    pub port: u16,
+    pub ttl: u8,
+    pub learning: bool,
+    /// seconds
+    pub ageing: u32,
+    pub collect_metadata: bool,
+}
+
+impl VxlanConfig {
+    /// Parses the IFLA_VXLAN_* attributes of IFLA_INFO_DATA.
+    pub fn from_info_data(data: &[u8]) -> Result<VxlanConfig> {
+        let mut config = VxlanConfig::default();
+        for nla in NlasIterator::new(data) {
+            // attributes newer than netlink_packet_route are skipped
+            let nla = match InfoVxlan::parse(&nla?) {
+                Ok(nla) => nla,
+                Err(_) => continue,
+            };
+            match nla {
+                InfoVxlan::Id(vni) => config.vni = vni,
+                InfoVxlan::Local(addr) | InfoVxlan::Local6(addr) => {
+                    config.local = ip_from_bytes(&addr)
+                }
+                InfoVxlan::Group(addr) | InfoVxlan::Group6(addr) => match ip_from_bytes(&addr) {
+                    Some(addr) if addr.is_multicast() => config.group = Some(addr),
+                    addr => config.remote = addr,
+                },
+                InfoVxlan::Port(port) => config.port = port,
+                InfoVxlan::Ttl(ttl) => config.ttl = ttl,
+                InfoVxlan::Learning(learning) => config.learning = learning != 0,
+                InfoVxlan::Ageing(ageing) => config.ageing = ageing,
+                InfoVxlan::CollectMetadata(metadata) => config.collect_metadata = metadata != 0,
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// ip -d link show name type vxlan
+/// The link is read with get_link_info_raw, a Handle cannot parse vxlan links of recent kernels,
+/// so it is looked up in the network namespace of the calling thread.
+pub fn get_vxlan_config(name: &str) -> Result<VxlanConfig> {
+    let info = get_link_info_raw(name)?;
+    if info.kind != "vxlan" {
+        return Err(anyhow!("{} is a {} link, not a vxlan", name, info.kind));
+    }
+
+    VxlanConfig::from_info_data(&info.data)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use netlink_packet_route::nlas::NlasIterator;
+    use netlink_packet_route::{IFLA_VXLAN_COLLECT_METADATA, IFLA_VXLAN_PORT};
+    use rtnetlink::new_connection;
+
+    use crate::error::IprouteError;
+    use crate::ip::iplink::{get_link_info_raw, Action, IPLink, LinkTypeEnum};
+    use crate::ip::testutil::{add_veth, del_link};
+    use crate::ip::vxlan::{get_vxlan_config, Vxlan, VxlanConfig};
+
+    #[tokio::test]
+    async fn test_vxlan_collect_metadata() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "vxmeta0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Vxlan(Vxlan::default().collect_metadata())),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let info = get_link_info_raw("vxmeta0");
+
+        del_link(&mut handle, "vxmeta0").await.unwrap();
+
+        let info = info.unwrap();
+        assert_eq!(info.kind, "vxlan");
+        let nla_value = |kind| {
+            NlasIterator::new(info.data.as_slice())
+                .map(|nla| nla.unwrap())
+                .find(|nla| nla.kind() == kind)
+                .map(|nla| nla.value().to_vec())
+        };
+        assert_eq!(nla_value(IFLA_VXLAN_COLLECT_METADATA), Some(vec![1]));
+        assert_eq!(
+            nla_value(IFLA_VXLAN_PORT),
+            Some(4789u16.to_be_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_vxlan_config() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "vxconf0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Vxlan(Vxlan {
+                vni: 42,
+                local: Some("192.0.2.1".parse().unwrap()),
+                remote: Some("192.0.2.2".parse().unwrap()),
+                port: 4790,
+                ttl: 16,
+                learning: false,
+                ..Default::default()
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let config = get_vxlan_config("vxconf0");
+
+        del_link(&mut handle, "vxconf0").await.unwrap();
+
+        assert_eq!(
+            config.unwrap(),
+            VxlanConfig {
+                vni: 42,
+                local: Some("192.0.2.1".parse().unwrap()),
+                remote: Some("192.0.2.2".parse().unwrap()),
+                group: None,
+                port: 4790,
+                ttl: 16,
+                learning: false,
+                ageing: 300,
+                collect_metadata: false,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vxlan_multicast_group() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        add_veth(&mut handle, "vxgroup1", "vxgroup2", false)
+            .await
+            .unwrap();
+
+        let group: IpAddr = "239.1.1.1".parse().unwrap();
+        let err = IPLink {
+            action: Action::Add,
+            name: "vxgroup0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Vxlan(Vxlan {
+                vni: 43,
+                group: Some(group),
+                remote: Some("192.0.2.2".parse().unwrap()),
+                ..Default::default()
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+
+        IPLink {
+            action: Action::Add,
+            name: "vxgroup0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::Vxlan(Vxlan {
+                vni: 43,
+                group: Some(group),
+                dev: Some("vxgroup1".to_string()),
+                ..Default::default()
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+
+        let config = get_vxlan_config("vxgroup0");
+
+        // the vxlan goes with its link
+        del_link(&mut handle, "vxgroup1").await.unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.vni, 43);
+        assert_eq!(config.group, Some(group));
+        assert_eq!(config.remote, None);
+    }
+}
@@ -0,0 +1,553 @@
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{anyhow, Result};
+use ipnet::IpNet;
+use netlink_packet_route::constants::{AF_INET, AF_INET6};
+use netlink_packet_route::nlas::{Nla as NlaTrait, NlasIterator, NLA_F_NESTED};
+use netlink_packet_route::rtnl::link::nlas::{Info, InfoKind, Nla};
+use netlink_packet_route::traits::Emitable;
+use netlink_packet_route::{LinkMessage, NetlinkBuffer, NLM_F_ACK, NLM_F_REQUEST};
+use netlink_sys::protocols::NETLINK_GENERIC;
+use rtnetlink::Handle;
+
+use super::iplink::{emit_nlas, request_raw_protocol, resolve_local_link_index, LinkTypeTrait};
+
+pub const WG_GENL_NAME: &str = "wireguard";
+pub const WG_KEY_LEN: usize = 32;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const GENL_HDRLEN: usize = 4;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_SET_DEVICE: u8 = 1;
+
+pub const WGDEVICE_A_IFNAME: u16 = 2;
+pub const WGDEVICE_A_PRIVATE_KEY: u16 = 3;
+pub const WGDEVICE_A_FLAGS: u16 = 5;
+pub const WGDEVICE_A_LISTEN_PORT: u16 = 6;
+pub const WGDEVICE_A_FWMARK: u16 = 7;
+pub const WGDEVICE_A_PEERS: u16 = 8;
+pub const WGDEVICE_F_REPLACE_PEERS: u32 = 1;
+
+pub const WGPEER_A_PUBLIC_KEY: u16 = 1;
+pub const WGPEER_A_PRESHARED_KEY: u16 = 2;
+pub const WGPEER_A_FLAGS: u16 = 3;
+pub const WGPEER_A_ENDPOINT: u16 = 4;
+pub const WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+pub const WGPEER_A_ALLOWEDIPS: u16 = 9;
+pub const WGPEER_F_REMOVE_ME: u32 = 1;
+pub const WGPEER_F_REPLACE_ALLOWEDIPS: u32 = 2;
+
+pub const WGALLOWEDIP_A_FAMILY: u16 = 1;
+pub const WGALLOWEDIP_A_IPADDR: u16 = 2;
+pub const WGALLOWEDIP_A_CIDR_MASK: u16 = 3;
+
+/// ip link add name type wireguard
+/// rtnetlink only creates the link: the key, port and fwmark are set by IPLink::execute with
+/// wg_set_device once the link exists.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct WireGuard {
+    pub private_key: Option<[u8; WG_KEY_LEN]>,
+    pub listen_port: Option<u16>,
+    pub fwmark: Option<u32>,
+}
+
+impl WireGuard {
+    /// The wg_set_device configuration applied after the link is created, None when there is
+    /// nothing to set.
+    pub fn config(&self) -> Option<WireGuardConfig> {
+        if self.private_key.is_none() && self.listen_port.is_none() && self.fwmark.is_none() {
+            return None;
+        }
+        Some(WireGuardConfig {
+            private_key: self.private_key,
+            listen_port: self.listen_port,
+            fwmark: self.fwmark,
+            ..Default::default()
+        })
+    }
+}
+
+impl LinkTypeTrait for WireGuard {
+    fn link_type(&self, message: &mut LinkMessage) -> Result<()> {
+        message
+            .nlas
+            .push(Nla::Info(vec![Info::Kind(InfoKind::Wireguard)]));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct AllowedIp {
+    pub net: IpNet,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct WireGuardPeer {
+    pub public_key: [u8; WG_KEY_LEN],
+    pub preshared_key: Option<[u8; WG_KEY_LEN]>,
+    pub endpoint: Option<SocketAddr>,
+    /// seconds, 0 turns keepalives off
+    pub persistent_keepalive: Option<u16>,
+    pub allowed_ips: Vec<AllowedIp>,
+    /// drop the allowed ips of the peer before adding allowed_ips
+    pub replace_allowed_ips: bool,
+    /// remove the peer from the device, the other fields are ignored
+    pub remove: bool,
+}
+
+impl WireGuardPeer {
+    pub fn new(public_key: [u8; WG_KEY_LEN]) -> WireGuardPeer {
+        WireGuardPeer {
+            public_key,
+            preshared_key: None,
+            endpoint: None,
+            persistent_keepalive: None,
+            allowed_ips: vec![],
+            replace_allowed_ips: false,
+            remove: false,
+        }
+    }
+}
+
+/// wg set name private-key key listen-port port fwmark fwmark peer ...
+/// Fields left None are not changed.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct WireGuardConfig {
+    pub private_key: Option<[u8; WG_KEY_LEN]>,
+    pub listen_port: Option<u16>,
+    pub fwmark: Option<u32>,
+    /// drop the peers of the device before adding peers
+    pub replace_peers: bool,
+    pub peers: Vec<WireGuardPeer>,
+}
+
+/// An attribute holding other attributes, e.g. one entry of WGDEVICE_A_PEERS.
+struct Nested<T>(u16, Vec<T>);
+
+impl<T: NlaTrait> NlaTrait for Nested<T> {
+    fn value_len(&self) -> usize {
+        self.1.as_slice().buffer_len()
+    }
+
+    fn kind(&self) -> u16 {
+        self.0 | NLA_F_NESTED
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        self.1.as_slice().emit(buffer)
+    }
+}
+
+enum CtrlAttr {
+    FamilyName(String),
+}
+
+impl NlaTrait for CtrlAttr {
+    fn value_len(&self) -> usize {
+        match self {
+            CtrlAttr::FamilyName(name) => name.len() + 1,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            CtrlAttr::FamilyName(_) => CTRL_ATTR_FAMILY_NAME,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            CtrlAttr::FamilyName(name) => {
+                buffer[..name.len()].copy_from_slice(name.as_bytes());
+                buffer[name.len()] = 0;
+            }
+        }
+    }
+}
+
+enum WgDeviceAttr {
+    IfName(String),
+    PrivateKey([u8; WG_KEY_LEN]),
+    Flags(u32),
+    ListenPort(u16),
+    FwMark(u32),
+    Peers(Vec<Nested<WgPeerAttr>>),
+}
+
+impl NlaTrait for WgDeviceAttr {
+    fn value_len(&self) -> usize {
+        match self {
+            WgDeviceAttr::IfName(name) => name.len() + 1,
+            WgDeviceAttr::PrivateKey(_) => WG_KEY_LEN,
+            WgDeviceAttr::Flags(_) | WgDeviceAttr::FwMark(_) => 4,
+            WgDeviceAttr::ListenPort(_) => 2,
+            WgDeviceAttr::Peers(peers) => peers.as_slice().buffer_len(),
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            WgDeviceAttr::IfName(_) => WGDEVICE_A_IFNAME,
+            WgDeviceAttr::PrivateKey(_) => WGDEVICE_A_PRIVATE_KEY,
+            WgDeviceAttr::Flags(_) => WGDEVICE_A_FLAGS,
+            WgDeviceAttr::ListenPort(_) => WGDEVICE_A_LISTEN_PORT,
+            WgDeviceAttr::FwMark(_) => WGDEVICE_A_FWMARK,
+            WgDeviceAttr::Peers(_) => WGDEVICE_A_PEERS | NLA_F_NESTED,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            WgDeviceAttr::IfName(name) => {
+                buffer[..name.len()].copy_from_slice(name.as_bytes());
+                buffer[name.len()] = 0;
+            }
+            WgDeviceAttr::PrivateKey(key) => buffer.copy_from_slice(key),
+            WgDeviceAttr::Flags(value) | WgDeviceAttr::FwMark(value) => {
+                buffer.copy_from_slice(&value.to_ne_bytes())
+            }
+            WgDeviceAttr::ListenPort(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            WgDeviceAttr::Peers(peers) => peers.as_slice().emit(buffer),
+        }
+    }
+}
+
+enum WgPeerAttr {
+    PublicKey([u8; WG_KEY_LEN]),
+    PresharedKey([u8; WG_KEY_LEN]),
+    Flags(u32),
+    Endpoint(SocketAddr),
+    PersistentKeepalive(u16),
+    AllowedIps(Vec<Nested<WgAllowedIpAttr>>),
+}
+
+/// sizeof(struct sockaddr_in) and sizeof(struct sockaddr_in6)
+const SOCKADDR_IN_LEN: usize = 16;
+const SOCKADDR_IN6_LEN: usize = 28;
+
+impl NlaTrait for WgPeerAttr {
+    fn value_len(&self) -> usize {
+        match self {
+            WgPeerAttr::PublicKey(_) | WgPeerAttr::PresharedKey(_) => WG_KEY_LEN,
+            WgPeerAttr::Flags(_) => 4,
+            WgPeerAttr::Endpoint(SocketAddr::V4(_)) => SOCKADDR_IN_LEN,
+            WgPeerAttr::Endpoint(SocketAddr::V6(_)) => SOCKADDR_IN6_LEN,
+            WgPeerAttr::PersistentKeepalive(_) => 2,
+            WgPeerAttr::AllowedIps(ips) => ips.as_slice().buffer_len(),
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            WgPeerAttr::PublicKey(_) => WGPEER_A_PUBLIC_KEY,
+            WgPeerAttr::PresharedKey(_) => WGPEER_A_PRESHARED_KEY,
+            WgPeerAttr::Flags(_) => WGPEER_A_FLAGS,
+            WgPeerAttr::Endpoint(_) => WGPEER_A_ENDPOINT,
+            WgPeerAttr::PersistentKeepalive(_) => WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL,
+            WgPeerAttr::AllowedIps(_) => WGPEER_A_ALLOWEDIPS | NLA_F_NESTED,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            WgPeerAttr::PublicKey(key) | WgPeerAttr::PresharedKey(key) => {
+                buffer.copy_from_slice(key)
+            }
+            WgPeerAttr::Flags(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            // struct sockaddr_in and struct sockaddr_in6, the port in network byte order
+            WgPeerAttr::Endpoint(SocketAddr::V4(addr)) => {
+                buffer.fill(0);
+                buffer[..2].copy_from_slice(&AF_INET.to_ne_bytes());
+                buffer[2..4].copy_from_slice(&addr.port().to_be_bytes());
+                buffer[4..8].copy_from_slice(&addr.ip().octets());
+            }
+            WgPeerAttr::Endpoint(SocketAddr::V6(addr)) => {
+                buffer[..2].copy_from_slice(&AF_INET6.to_ne_bytes());
+                buffer[2..4].copy_from_slice(&addr.port().to_be_bytes());
+                buffer[4..8].copy_from_slice(&addr.flowinfo().to_be_bytes());
+                buffer[8..24].copy_from_slice(&addr.ip().octets());
+                buffer[24..].copy_from_slice(&addr.scope_id().to_ne_bytes());
+            }
+            WgPeerAttr::PersistentKeepalive(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            WgPeerAttr::AllowedIps(ips) => ips.as_slice().emit(buffer),
+        }
+    }
+}
+
+enum WgAllowedIpAttr {
+    Family(u16),
+    IpAddr(IpAddr),
+    CidrMask(u8),
+}
+
+impl NlaTrait for WgAllowedIpAttr {
+    fn value_len(&self) -> usize {
+        match self {
+            WgAllowedIpAttr::Family(_) => 2,
+            WgAllowedIpAttr::IpAddr(IpAddr::V4(_)) => 4,
+            WgAllowedIpAttr::IpAddr(IpAddr::V6(_)) => 16,
+            WgAllowedIpAttr::CidrMask(_) => 1,
+        }
+    }
+
+    fn kind(&self) -> u16 {
+        match self {
+            WgAllowedIpAttr::Family(_) => WGALLOWEDIP_A_FAMILY,
+            WgAllowedIpAttr::IpAddr(_) => WGALLOWEDIP_A_IPADDR,
+            WgAllowedIpAttr::CidrMask(_) => WGALLOWEDIP_A_CIDR_MASK,
+        }
+    }
+
+    fn emit_value(&self, buffer: &mut [u8]) {
+        match self {
+            WgAllowedIpAttr::Family(value) => buffer.copy_from_slice(&value.to_ne_bytes()),
+            WgAllowedIpAttr::IpAddr(IpAddr::V4(addr)) => buffer.copy_from_slice(&addr.octets()),
+            WgAllowedIpAttr::IpAddr(IpAddr::V6(addr)) => buffer.copy_from_slice(&addr.octets()),
+            WgAllowedIpAttr::CidrMask(value) => buffer[0] = *value,
+        }
+    }
+}
+
+fn peer_attrs(peer: &WireGuardPeer) -> Vec<WgPeerAttr> {
+    let mut attrs = vec![WgPeerAttr::PublicKey(peer.public_key)];
+    if peer.remove {
+        attrs.push(WgPeerAttr::Flags(WGPEER_F_REMOVE_ME));
+        return attrs;
+    }
+    if peer.replace_allowed_ips {
+        attrs.push(WgPeerAttr::Flags(WGPEER_F_REPLACE_ALLOWEDIPS));
+    }
+    if let Some(key) = peer.preshared_key {
+        attrs.push(WgPeerAttr::PresharedKey(key));
+    }
+    if let Some(endpoint) = peer.endpoint {
+        attrs.push(WgPeerAttr::Endpoint(endpoint));
+    }
+    if let Some(interval) = peer.persistent_keepalive {
+        attrs.push(WgPeerAttr::PersistentKeepalive(interval));
+    }
+    if !peer.allowed_ips.is_empty() {
+        let ips = peer
+            .allowed_ips
+            .iter()
+            .map(|ip| {
+                let family = match ip.net {
+                    IpNet::V4(_) => AF_INET,
+                    IpNet::V6(_) => AF_INET6,
+                };
+                Nested(
+                    0,
+                    vec![
+                        WgAllowedIpAttr::Family(family),
+                        WgAllowedIpAttr::IpAddr(ip.net.network()),
+                        WgAllowedIpAttr::CidrMask(ip.net.prefix_len()),
+                    ],
+                )
+            })
+            .collect();
+        attrs.push(WgPeerAttr::AllowedIps(ips));
+    }
+    attrs
+}
+
+/// The attributes of a WG_CMD_SET_DEVICE request for name.
+fn device_attrs(name: &str, config: &WireGuardConfig) -> Vec<u8> {
+    let mut attrs = vec![WgDeviceAttr::IfName(name.to_string())];
+    if let Some(key) = config.private_key {
+        attrs.push(WgDeviceAttr::PrivateKey(key));
+    }
+    if let Some(port) = config.listen_port {
+        attrs.push(WgDeviceAttr::ListenPort(port));
+    }
+    if let Some(fwmark) = config.fwmark {
+        attrs.push(WgDeviceAttr::FwMark(fwmark));
+    }
+    if config.replace_peers {
+        attrs.push(WgDeviceAttr::Flags(WGDEVICE_F_REPLACE_PEERS));
+    }
+    if !config.peers.is_empty() {
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| Nested(0, peer_attrs(peer)))
+            .collect();
+        attrs.push(WgDeviceAttr::Peers(peers));
+    }
+    emit_nlas(&attrs)
+}
+
+/// Sends a generic netlink request of family and returns the attributes of the answer, empty
+/// when the kernel only acknowledged it. what names the request in errors.
+fn genl_request(family: u16, cmd: u8, version: u8, attrs: &[u8], what: &str) -> Result<Vec<u8>> {
+    let len = 16 + GENL_HDRLEN + attrs.len();
+    let mut request = vec![0; len];
+    let mut buffer = NetlinkBuffer::new(&mut request);
+    buffer.set_length(len as u32);
+    buffer.set_message_type(family);
+    buffer.set_flags(NLM_F_REQUEST | NLM_F_ACK);
+    buffer.set_sequence_number(1);
+    let payload = buffer.payload_mut();
+    // struct genlmsghdr: cmd, version, reserved
+    payload[0] = cmd;
+    payload[1] = version;
+    payload[GENL_HDRLEN..].copy_from_slice(attrs);
+
+    let reply = request_raw_protocol(NETLINK_GENERIC, &request, family)
+        .map_err(|e| e.context(format!("{} failed", what)))?;
+    // empty for an ack
+    Ok(reply.get(GENL_HDRLEN..).unwrap_or_default().to_vec())
+}
+
+/// The id the kernel gave to the generic netlink family name.
+fn genl_family_id(name: &str) -> Result<u16> {
+    let attrs = emit_nlas(&[CtrlAttr::FamilyName(name.to_string())]);
+    let what = format!("resolving generic netlink family {}", name);
+    let reply = genl_request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, 1, &attrs, &what)?;
+    for nla in NlasIterator::new(reply.as_slice()) {
+        let nla = nla?;
+        if nla.kind() == CTRL_ATTR_FAMILY_ID && nla.value().len() == 2 {
+            return Ok(u16::from_ne_bytes([nla.value()[0], nla.value()[1]]));
+        }
+    }
+    Err(anyhow!("no family id in the answer for {}", name))
+}
+
+/// wg set name ...
+/// Goes through the wireguard generic netlink family, which a rtnetlink Handle cannot carry: the
+/// request is sent over a plain socket in the network namespace of the calling thread. name is
+/// resolved through handle first, and the link the calling thread sees under name must have the
+/// same index, so that a handle on another network namespace fails instead of configuring a link
+/// of the calling thread. The whole configuration is sent in one message, so very large peer
+/// lists should be split over several calls.
+pub async fn wg_set_device(handle: &mut Handle, name: &str, config: WireGuardConfig) -> Result<()> {
+    resolve_local_link_index(handle, name).await?;
+    let family = genl_family_id(WG_GENL_NAME)?;
+    genl_request(
+        family,
+        WG_CMD_SET_DEVICE,
+        WG_GENL_VERSION,
+        &device_attrs(name, &config),
+        &format!("configuring wireguard {}", name),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::nlas::{NlasIterator, NLA_F_NESTED};
+    use rtnetlink::new_connection;
+    use serial_test::serial;
+
+    use crate::error::IprouteError;
+    use crate::ip::iplink::{link_exists_checked, Action, IPLink, LinkTypeEnum};
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_exec};
+    use crate::ip::testutil::{add_veth, del_link};
+    use crate::ip::wireguard::{
+        device_attrs, wg_set_device, AllowedIp, WireGuard, WireGuardConfig, WireGuardPeer,
+        WGALLOWEDIP_A_CIDR_MASK, WGDEVICE_A_PEERS, WGPEER_A_ALLOWEDIPS, WGPEER_A_ENDPOINT,
+    };
+
+    /// The value of the first attribute of kind in data.
+    fn nla(data: &[u8], kind: u16) -> Option<&[u8]> {
+        NlasIterator::new(data)
+            .map(|nla| nla.unwrap())
+            .find(|nla| nla.kind() & !NLA_F_NESTED == kind)
+            .map(|nla| {
+                let len = nla.length() as usize;
+                &nla.into_inner()[4..len]
+            })
+    }
+
+    #[test]
+    fn test_wg_device_attrs() {
+        let mut peer = WireGuardPeer::new([1; 32]);
+        peer.endpoint = Some("192.0.2.1:51820".parse().unwrap());
+        peer.allowed_ips = vec![AllowedIp {
+            net: "10.0.0.0/8".parse().unwrap(),
+        }];
+        let config = WireGuardConfig {
+            listen_port: Some(51820),
+            peers: vec![peer],
+            ..Default::default()
+        };
+        let data = device_attrs("wg0", &config);
+
+        let peers = nla(&data, WGDEVICE_A_PEERS).unwrap();
+        let peer = nla(peers, 0).unwrap();
+        let endpoint = nla(peer, WGPEER_A_ENDPOINT).unwrap();
+        assert_eq!(endpoint.len(), 16);
+        assert_eq!(&endpoint[2..8], &[0xca, 0x6c, 192, 0, 2, 1]);
+        let allowed_ip = nla(nla(peer, WGPEER_A_ALLOWEDIPS).unwrap(), 0).unwrap();
+        assert_eq!(nla(allowed_ip, WGALLOWEDIP_A_CIDR_MASK), Some(&[8][..]));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs the wireguard kernel module"]
+    async fn test_wireguard() {
+        let (connection, mut handle, _) = new_connection().unwrap();
+        tokio::spawn(connection);
+
+        IPLink {
+            action: Action::Add,
+            name: "wg0".to_string(),
+            options: vec![],
+            link_type: Some(LinkTypeEnum::WireGuard(WireGuard {
+                private_key: Some([2; 32]),
+                listen_port: Some(51820),
+                fwmark: None,
+            })),
+        }
+        .execute(&mut handle)
+        .await
+        .unwrap();
+        let mut peer = WireGuardPeer::new([3; 32]);
+        peer.allowed_ips = vec![AllowedIp {
+            net: "10.0.0.0/8".parse().unwrap(),
+        }];
+        let res = wg_set_device(
+            &mut handle,
+            "wg0",
+            WireGuardConfig {
+                peers: vec![peer],
+                ..Default::default()
+            },
+        )
+        .await;
+        let exists = link_exists_checked("wg0");
+
+        del_link(&mut handle, "wg0").await.unwrap();
+
+        res.unwrap();
+        assert!(exists.unwrap());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_wg_set_device_other_namespace() {
+        ip_net_ns_add("vnetns39".to_string()).unwrap();
+        // a handle on the namespace, used from a thread outside of it
+        let runtime = tokio::runtime::Handle::current();
+        let (connection, mut handle, _) = thread_net_ns_exec("vnetns39".to_string(), move || {
+            let _guard = runtime.enter();
+            Ok(new_connection()?)
+        })
+        .join()
+        .unwrap()
+        .unwrap();
+        tokio::spawn(connection);
+
+        let added = add_veth(&mut handle, "nswg0", "nswg1", false).await;
+        let res = wg_set_device(&mut handle, "nswg0", WireGuardConfig::default()).await;
+        ip_net_ns_del("vnetns39".to_string(), false).unwrap();
+
+        added.unwrap();
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+    }
+}
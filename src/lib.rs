@@ -1 +1,4 @@
+pub mod capture;
+pub mod error;
 pub mod ip;
+pub mod spec;
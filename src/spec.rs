@@ -0,0 +1,338 @@
+use std::net::IpAddr;
+
+use anyhow::Result;
+use ipnet::IpNet;
+use netlink_packet_route::constants::*;
+use nix::errno::Errno;
+use nix::sys::socket::AddressFamily;
+use rtnetlink::Handle;
+use serde::Deserialize;
+
+use crate::error::IprouteError;
+use crate::ip::bridge::Bridge;
+use crate::ip::ipaddr::{AddrAction, IPAddr};
+use crate::ip::iplink::{Action as LinkAction, IPLink, LinkTypeEnum, Opt};
+use crate::ip::iproute::IPRouteBuilder;
+use crate::ip::iprule::{IPRule, RuleAction};
+use crate::ip::veth::Veth;
+
+/// Links, addresses, routes and rules to set up together, see NetworkSpec::apply.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct NetworkSpec {
+    pub links: Vec<IPLink>,
+    pub addresses: Vec<IPAddr>,
+    /// Built in apply, as their devs are resolved through its handle.
+    pub routes: Vec<IPRouteBuilder>,
+    pub rules: Vec<IPRule>,
+}
+
+/// How many objects of a NetworkSpec were created, and how many already existed.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct ApplyResult {
+    pub applied: u32,
+    pub unchanged: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonSpec {
+    #[serde(default)]
+    links: Vec<JsonLink>,
+    #[serde(default)]
+    addresses: Vec<JsonAddress>,
+    #[serde(default)]
+    routes: Vec<JsonRoute>,
+    #[serde(default)]
+    rules: Vec<JsonRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonLink {
+    name: String,
+    kind: String,
+    /// veth only
+    peer: Option<String>,
+    master: Option<String>,
+    #[serde(default)]
+    up: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonAddress {
+    dev: String,
+    /// addr/prefix_len
+    addr: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRoute {
+    /// addr/prefix_len
+    dst: String,
+    gateway: IpAddr,
+    table: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRule {
+    priority: u32,
+    table: u32,
+    fwmark: Option<u32>,
+    #[serde(default)]
+    ipv6: bool,
+}
+
+fn invalid(message: String) -> anyhow::Error {
+    IprouteError::InvalidArgument(message).into()
+}
+
+fn parse_net(net: &str) -> Result<IpNet> {
+    net.parse()
+        .map_err(|e| invalid(format!("invalid prefix {}: {}", net, e)))
+}
+
+impl JsonLink {
+    fn into_link(self) -> Result<IPLink> {
+        let link_type = match self.kind.as_str() {
+            "veth" => LinkTypeEnum::Veth(Veth {
+                peer_name: match self.peer {
+                    Some(peer) => peer,
+                    None => return Err(invalid(format!("veth {} has no peer", self.name))),
+                },
+                options: vec![],
+                auto_up: self.up,
+            }),
+            "bridge" => LinkTypeEnum::Bridge(Bridge { info: vec![] }),
+            kind => return Err(invalid(format!("unsupported link kind {}", kind))),
+        };
+        let mut options = vec![];
+        if let Some(master) = self.master {
+            options.push(Opt::Master(master));
+        }
+        if self.up {
+            options.push(Opt::Up);
+        }
+        Ok(IPLink {
+            action: LinkAction::Add,
+            name: self.name,
+            options,
+            link_type: Some(link_type),
+        })
+    }
+}
+
+impl JsonRoute {
+    /// The output device is left to the kernel to derive from the gateway, since the links of
+    /// the same spec do not exist yet when it is parsed.
+    fn into_route(self) -> Result<IPRouteBuilder> {
+        let dst = parse_net(&self.dst)?;
+        Ok(IPRouteBuilder {
+            destination: Some(dst.network()),
+            prefix_len: dst.prefix_len(),
+            gateway: Some(self.gateway),
+            table: self.table.map_or(RT_TABLE_MAIN as u32, u32::from),
+            ..Default::default()
+        })
+    }
+}
+
+impl JsonRule {
+    fn into_rule(self) -> Result<IPRule> {
+        Ok(IPRule {
+            action: RuleAction::Add,
+            priority: self.priority,
+            family: if self.ipv6 {
+                AddressFamily::Inet6
+            } else {
+                AddressFamily::Inet
+            },
+            src: None,
+            dst: None,
+            iif: None,
+            oif: None,
+            fwmark: self.fwmark,
+            fwmask: None,
+            table: self.table,
+            invert: false,
+        })
+    }
+}
+
+impl NetworkSpec {
+    /// Parses a spec like
+    /// {"links": [{"name": "br0", "kind": "bridge", "up": true},
+    ///            {"name": "veth0", "kind": "veth", "peer": "veth1", "master": "br0", "up": true}],
+    ///  "addresses": [{"dev": "br0", "addr": "10.0.0.1/24"}],
+    ///  "routes": [{"dst": "10.1.0.0/16", "gateway": "10.0.0.254", "table": 254}],
+    ///  "rules": [{"priority": 100, "table": 100, "fwmark": 1}]}
+    /// Only veth and bridge links are supported, and every object is added.
+    pub fn from_json(s: &str) -> Result<Self> {
+        let spec: JsonSpec = serde_json::from_str(s)?;
+        Ok(NetworkSpec {
+            links: spec
+                .links
+                .into_iter()
+                .map(JsonLink::into_link)
+                .collect::<Result<_>>()?,
+            addresses: spec
+                .addresses
+                .into_iter()
+                .map(|address| {
+                    let addr = parse_net(&address.addr)?;
+                    Ok(IPAddr::new(
+                        AddrAction::Add,
+                        addr.addr(),
+                        addr.prefix_len(),
+                        &address.dev,
+                    ))
+                })
+                .collect::<Result<_>>()?,
+            routes: spec
+                .routes
+                .into_iter()
+                .map(JsonRoute::into_route)
+                .collect::<Result<_>>()?,
+            rules: spec
+                .rules
+                .into_iter()
+                .map(JsonRule::into_rule)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Links first, then addresses, routes and rules, each in the order of the spec, so that
+    /// every object can refer to the ones before it.
+    /// Objects the kernel reports as already existing are counted as unchanged, so a spec can be
+    /// applied again. The first other error stops the apply, leaving what was done in place.
+    pub async fn apply(&self, handle: &mut Handle) -> Result<ApplyResult> {
+        let mut result = ApplyResult::default();
+        let mut count = |res: Result<()>| -> Result<()> {
+            match res {
+                Ok(()) => result.applied += 1,
+                Err(e)
+                    if matches!(
+                        e.downcast_ref::<rtnetlink::Error>(),
+                        Some(rtnetlink::Error::NetlinkError(msg))
+                            if msg.code == -(Errno::EEXIST as i32)
+                    ) =>
+                {
+                    result.unchanged += 1
+                }
+                Err(e) => return Err(e),
+            }
+            Ok(())
+        };
+
+        for link in &self.links {
+            count(link.execute(handle).await)?;
+        }
+        for address in &self.addresses {
+            count(address.execute(handle).await)?;
+        }
+        for route in &self.routes {
+            let route = route.clone().build(handle).await?;
+            count(route.execute(handle).await)?;
+        }
+        for rule in &self.rules {
+            count(rule.execute(handle).await)?;
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use netlink_packet_route::nlas::address::Nla as AddressNla;
+    use netlink_packet_route::nlas::route::Nla as RouteNla;
+    use netlink_packet_route::nlas::rule::Nla as RuleNla;
+    use rtnetlink::{new_connection, IpVersion};
+    use serial_test::serial;
+
+    use crate::error::IprouteError;
+    use crate::ip::ipaddr::get_addresses_by_index;
+    use crate::ip::iplink::{get_link_index, link_exists_checked};
+    use crate::ip::ipnetns::{ip_net_ns_add, ip_net_ns_del, thread_net_ns_block_on};
+    use crate::ip::iproute::get_routes;
+    use crate::ip::iprule::get_rules;
+    use crate::spec::{ApplyResult, NetworkSpec};
+
+    const SPEC: &str = r#"{
+        "links": [
+            {"name": "specbr0", "kind": "bridge", "up": true},
+            {"name": "specveth0", "kind": "veth", "peer": "specveth1", "master": "specbr0", "up": true}
+        ],
+        "addresses": [
+            {"dev": "specveth0", "addr": "10.11.0.1/24"},
+            {"dev": "specveth1", "addr": "10.11.1.1/24"}
+        ],
+        "routes": [{"dst": "10.12.0.0/16", "gateway": "10.11.1.2"}],
+        "rules": [{"priority": 100, "table": 100, "fwmark": 1}]
+    }"#;
+
+    #[test]
+    fn test_network_spec_from_json_invalid() {
+        let err =
+            NetworkSpec::from_json(r#"{"links": [{"name": "x", "kind": "nope"}]}"#).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IprouteError>(),
+            Some(IprouteError::InvalidArgument(_))
+        ));
+        assert!(NetworkSpec::from_json(r#"{"addresses": [{"dev": "x"}]}"#).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_network_spec_apply() {
+        let spec = NetworkSpec::from_json(SPEC).unwrap();
+        ip_net_ns_add("vnetns11".to_string()).unwrap();
+        let res = thread_net_ns_block_on("vnetns11".to_string(), move || async move {
+            let (connection, mut handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            let first = spec.apply(&mut handle).await?;
+            let second = spec.apply(&mut handle).await?;
+
+            let mut links = vec![];
+            for name in ["specbr0", "specveth0", "specveth1"] {
+                links.push(link_exists_checked(name)?);
+            }
+            let port_addrs = get_addresses_by_index(&handle, get_link_index("specveth0")?).await?;
+            let veth_addrs = get_addresses_by_index(&handle, get_link_index("specveth1")?).await?;
+            let routes = get_routes(&handle, IpVersion::V4).await?;
+            let rules = get_rules(&handle, IpVersion::V4).await?;
+            Ok((first, second, links, port_addrs, veth_addrs, routes, rules))
+        });
+        ip_net_ns_del("vnetns11".to_string(), false).unwrap();
+
+        let (first, second, links, port_addrs, veth_addrs, routes, rules) = res.unwrap();
+        assert_eq!(
+            first,
+            ApplyResult {
+                applied: 6,
+                unchanged: 0
+            }
+        );
+        assert_eq!(
+            second,
+            ApplyResult {
+                applied: 0,
+                unchanged: 6
+            }
+        );
+        assert_eq!(links, vec![true, true, true]);
+        assert!(port_addrs.iter().any(|address| address
+            .nlas
+            .contains(&AddressNla::Address(vec![10, 11, 0, 1]))));
+        assert!(veth_addrs.iter().any(|address| address
+            .nlas
+            .contains(&AddressNla::Address(vec![10, 11, 1, 1]))));
+        assert!(routes.iter().any(|route| route
+            .nlas
+            .contains(&RouteNla::Destination(vec![10, 12, 0, 0]))));
+        assert!(rules
+            .iter()
+            .any(|rule| rule.nlas.contains(&RuleNla::FwMark(1))));
+    }
+}